@@ -0,0 +1,60 @@
+use abac::{
+    bytecode::Program,
+    config::Config,
+    request::LogFormat,
+    resource::Hierarchy,
+    rule::{Context, Rule},
+    slo,
+};
+use std::{env, fs, hint::black_box, process::ExitCode, str::FromStr, time::Instant};
+
+const ITERATIONS: u32 = 100_000;
+
+fn main() -> ExitCode {
+    let rule = Rule::from_str(
+        "(if (and (eq $role admin) (in $team (list core infra data)))
+            (list create read update delete list)
+            (if (eq (concat $tenant / $project) acme/42) (list read list) (list)))",
+    )
+    .unwrap();
+    let program = Program::compile(&rule).unwrap();
+    let context = Context::from_str("role:user,team:core,tenant:acme,project:42").unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(rule.eval(black_box(&context)).unwrap());
+    }
+    let ast = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(program.eval(black_box(&context)).unwrap());
+    }
+    let bytecode = start.elapsed();
+
+    println!("ast:      {:?}/iter", ast / ITERATIONS);
+    println!("bytecode: {:?}/iter", bytecode / ITERATIONS);
+
+    // Checks a policy against its [slo] targets when given one, with an nginx
+    // access log as the request corpus.
+    let (Ok(policy), Ok(corpus)) = (env::var("ABAC_SLO_POLICY"), env::var("ABAC_SLO_CORPUS"))
+    else {
+        return ExitCode::SUCCESS;
+    };
+    let config: Config = toml::from_str(&fs::read_to_string(policy).unwrap()).unwrap();
+    let slo = config.slo.clone();
+    let rh: Hierarchy = config.try_into().unwrap();
+    let corpus = LogFormat::Nginx
+        .parse_all(&fs::read_to_string(corpus).unwrap())
+        .unwrap();
+    match slo.validate(&rh, &corpus) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(slo::Error::Exceeded(violations)) => {
+            for violation in violations {
+                eprintln!("SLO violation: {violation}");
+            }
+            ExitCode::FAILURE
+        }
+        Err(e) => panic!("{e}"),
+    }
+}
@@ -0,0 +1,285 @@
+use crate::permission::Operation;
+use crate::rule::Rule;
+
+impl From<bool> for Rule {
+    fn from(value: bool) -> Self {
+        Rule::Bool(value)
+    }
+}
+
+impl From<i32> for Rule {
+    fn from(value: i32) -> Self {
+        Rule::Integer(value)
+    }
+}
+
+impl From<f32> for Rule {
+    fn from(value: f32) -> Self {
+        Rule::Float(value)
+    }
+}
+
+impl From<&str> for Rule {
+    fn from(value: &str) -> Self {
+        Rule::String(value.to_string())
+    }
+}
+
+impl From<String> for Rule {
+    fn from(value: String) -> Self {
+        Rule::String(value)
+    }
+}
+
+impl<T: Into<Rule>> From<Vec<T>> for Rule {
+    fn from(values: Vec<T>) -> Self {
+        Rule::Tuple(values.into_iter().map(Into::into).collect())
+    }
+}
+
+pub struct RuleBuilder;
+
+impl RuleBuilder {
+    #[must_use]
+    pub fn iff(condition: impl Into<Rule>) -> IfBuilder {
+        IfBuilder {
+            condition: condition.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn var(name: &str) -> Rule {
+        Rule::String(format!("${name}"))
+    }
+
+    #[must_use]
+    pub fn eq(left: impl Into<Rule>, right: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::Eq(String::from("eq")),
+            left.into(),
+            right.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn ne(left: impl Into<Rule>, right: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::Eq(String::from("ne")),
+            left.into(),
+            right.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn and(left: impl Into<Rule>, right: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::And(String::from("and")),
+            left.into(),
+            right.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn or(left: impl Into<Rule>, right: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::Or(String::from("or")),
+            left.into(),
+            right.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn is_in(value: impl Into<Rule>, list: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::In(String::from("in")),
+            value.into(),
+            list.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn contains_all(list: impl Into<Rule>, items: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::ContainsAll(String::from("contains-all")),
+            list.into(),
+            items.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn contains_any(list: impl Into<Rule>, items: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::ContainsAny(String::from("contains-any")),
+            list.into(),
+            items.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn between(value: impl Into<Rule>, low: impl Into<Rule>, high: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::Between(String::from("between")),
+            value.into(),
+            low.into(),
+            high.into(),
+        ])
+    }
+
+    #[must_use]
+    pub fn has_key(key: &str) -> Rule {
+        Rule::Tuple(vec![
+            Rule::HasKey(String::from("has-key")),
+            Rule::String(key.to_string()),
+        ])
+    }
+
+    #[must_use]
+    pub fn list<T: Into<Rule>>(items: impl IntoIterator<Item = T>) -> Rule {
+        Rule::Tuple(
+            std::iter::once(Rule::List(String::from("list")))
+                .chain(items.into_iter().map(Into::into))
+                .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn perms(operations: impl IntoIterator<Item = Operation>) -> Rule {
+        Rule::Tuple(
+            std::iter::once(Rule::Perms(String::from("perms")))
+                .chain(
+                    operations
+                        .into_iter()
+                        .map(|operation| Rule::String(operation.to_string())),
+                )
+                .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn deny(operations: impl IntoIterator<Item = Operation>) -> Rule {
+        Rule::Tuple(
+            std::iter::once(Rule::Deny(String::from("deny")))
+                .chain(
+                    operations
+                        .into_iter()
+                        .map(|operation| Rule::String(operation.to_string())),
+                )
+                .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn concat<T: Into<Rule>>(items: impl IntoIterator<Item = T>) -> Rule {
+        Rule::Tuple(
+            std::iter::once(Rule::Concat(String::from("concat")))
+                .chain(items.into_iter().map(Into::into))
+                .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn require<T: Into<Rule>>(items: impl IntoIterator<Item = T>) -> Rule {
+        Rule::Tuple(
+            std::iter::once(Rule::Require(String::from("require")))
+                .chain(items.into_iter().map(Into::into))
+                .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn obligation<T: Into<Rule>>(items: impl IntoIterator<Item = T>) -> Rule {
+        Rule::Tuple(
+            std::iter::once(Rule::Require(String::from("obligation")))
+                .chain(items.into_iter().map(Into::into))
+                .collect(),
+        )
+    }
+}
+
+pub struct IfBuilder {
+    condition: Rule,
+}
+
+impl IfBuilder {
+    #[must_use]
+    pub fn then(self, then: impl Into<Rule>) -> ThenBuilder {
+        ThenBuilder {
+            condition: self.condition,
+            then: then.into(),
+        }
+    }
+}
+
+pub struct ThenBuilder {
+    condition: Rule,
+    then: Rule,
+}
+
+impl ThenBuilder {
+    #[must_use]
+    pub fn otherwise(self, otherwise: impl Into<Rule>) -> Rule {
+        Rule::Tuple(vec![
+            Rule::If(String::from("if")),
+            self.condition,
+            self.then,
+            otherwise.into(),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::Context;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_rule_builder_ok() {
+        assert_eq!(
+            RuleBuilder::iff(RuleBuilder::eq(RuleBuilder::var("role"), "admin"))
+                .then(RuleBuilder::list(["all"]))
+                .otherwise(RuleBuilder::list::<Rule>([])),
+            Rule::from_str("(if (eq $role admin) (list all) (list))").unwrap()
+        );
+        assert_eq!(
+            RuleBuilder::perms([Operation::Read, Operation::List]),
+            Rule::from_str("(perms read list)").unwrap()
+        );
+        assert_eq!(
+            RuleBuilder::and(
+                RuleBuilder::is_in(RuleBuilder::var("age"), RuleBuilder::list([18, 21])),
+                RuleBuilder::or(true, false)
+            ),
+            Rule::from_str("(and (in $age (list 18 21)) (or true false))").unwrap()
+        );
+        assert_eq!(
+            RuleBuilder::eq(
+                RuleBuilder::concat([RuleBuilder::var("tenant"), "/".into()]),
+                10.5
+            ),
+            Rule::from_str("(eq (concat $tenant /) 10.5)").unwrap()
+        );
+        assert_eq!(
+            RuleBuilder::ne(RuleBuilder::var("role"), "guest"),
+            Rule::from_str("(ne $role guest)").unwrap()
+        );
+        assert_eq!(
+            RuleBuilder::has_key("role"),
+            Rule::from_str("(has-key role)").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rule_builder_eval_ok() {
+        assert_eq!(
+            RuleBuilder::iff(RuleBuilder::eq(RuleBuilder::var("role"), "admin"))
+                .then(RuleBuilder::list(["create", "read"]))
+                .otherwise(RuleBuilder::list::<Rule>([]))
+                .eval(&Context::from_str("role:admin").unwrap()),
+            Ok(Rule::Tuple(vec![
+                Rule::String(String::from("create")),
+                Rule::String(String::from("read")),
+            ]))
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use crate::rule::{Context, Environment, Error, Rule};
+
+/// One step of a compiled rule. Operands are evaluated before the expression
+/// consuming them, so a program is the rule in postfix order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// Pushes a constant.
+    Push(Rule),
+    /// Pushes a context value or an interpolated string.
+    Load(Rule),
+    /// Applies a keyword or host function to the values on top of the stack.
+    /// Holds the head, the arity and the expression's pre-order position in
+    /// the rule, from which it is rebuilt for error reports.
+    Apply(Rule, usize, usize),
+    /// Host function call whose operands take the next instructions. They are
+    /// skipped for `()` when no such function is registered.
+    Call(String, usize),
+    /// Evaluates a `try` expression, resuming after the next instructions
+    /// with the fallback on a recoverable error.
+    Try(usize),
+    /// Ends a `try` expression, skipping the fallback instructions.
+    EndTry(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    rule: Rule,
+    depth: usize,
+    instructions: Vec<Instruction>,
+}
+
+enum Step<'a> {
+    Enter(&'a Rule, usize),
+    Apply(&'a Rule, usize, usize),
+    EndCall(usize, &'a Rule, usize, usize),
+    EndExpression(usize),
+    EndFallback(usize),
+}
+
+/// Whether the compiler emits the operands of the tuple rather than a
+/// constant for it.
+fn descends(rule: &Rule) -> bool {
+    let Rule::Tuple(children) = rule else {
+        return false;
+    };
+    match children.first() {
+        Some(Rule::Lambda(_)) => false,
+        Some(Rule::String(_)) => true,
+        _ => rule.is_valid_operation().unwrap_or(false),
+    }
+}
+
+impl Program {
+    pub fn compile(rule: &Rule) -> Result<Self, Error> {
+        let mut instructions = Vec::new();
+        let (mut nodes, mut depth) = (0, 0);
+        let mut steps = vec![Step::Enter(rule, 0)];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Enter(rule, level) => {
+                    let node = nodes;
+                    nodes += 1;
+                    depth = depth.max(level);
+                    let Rule::Tuple(children) = rule else {
+                        instructions.push(match rule {
+                            Rule::String(val) if val.starts_with('$') || val.contains("{$") => {
+                                Instruction::Load(rule.clone())
+                            }
+                            Rule::Literal(val) => Instruction::Push(Rule::String(val.clone())),
+                            _ => Instruction::Push(rule.clone()),
+                        });
+                        continue;
+                    };
+                    let valid = rule.is_valid_operation()?;
+                    match children.as_slice() {
+                        [Rule::Try(_), expression, fallback] => {
+                            instructions.push(Instruction::Try(0));
+                            let at = instructions.len() - 1;
+                            steps.push(Step::EndFallback(at));
+                            steps.push(Step::Enter(fallback, level + 1));
+                            steps.push(Step::EndExpression(at));
+                            steps.push(Step::Enter(expression, level + 1));
+                            continue;
+                        }
+                        [Rule::Lambda(_), ..] => {
+                            instructions.push(Instruction::Push(rule.clone()));
+                            continue;
+                        }
+                        [head @ Rule::String(name), ..] => {
+                            instructions.push(Instruction::Call(name.clone(), 0));
+                            steps.push(Step::EndCall(
+                                instructions.len() - 1,
+                                head,
+                                children.len() - 1,
+                                node,
+                            ));
+                        }
+                        [head, ..] if valid => {
+                            steps.push(Step::Apply(head, children.len() - 1, node));
+                        }
+                        _ => {
+                            instructions.push(Instruction::Push(Rule::Tuple(vec![])));
+                            continue;
+                        }
+                    }
+                    steps.extend(
+                        children
+                            .iter()
+                            .skip(1)
+                            .rev()
+                            .map(|child| Step::Enter(child, level + 1)),
+                    );
+                }
+                Step::Apply(head, arity, node) => {
+                    instructions.push(Instruction::Apply(head.clone(), arity, node));
+                }
+                Step::EndCall(at, head, arity, node) => {
+                    instructions.push(Instruction::Apply(head.clone(), arity, node));
+                    let len = instructions.len();
+                    if let Instruction::Call(_, skip) = &mut instructions[at] {
+                        *skip = len - at - 1;
+                    }
+                }
+                Step::EndExpression(at) => {
+                    instructions.push(Instruction::EndTry(0));
+                    instructions[at] = Instruction::Try(instructions.len() - at - 1);
+                }
+                Step::EndFallback(at) => {
+                    let len = instructions.len();
+                    if let Instruction::Try(expression) = instructions[at] {
+                        instructions[at + expression] =
+                            Instruction::EndTry(len - at - expression - 1);
+                    }
+                }
+            }
+        }
+        Ok(Program {
+            rule: rule.clone(),
+            depth,
+            instructions,
+        })
+    }
+
+    #[must_use]
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    #[must_use]
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    pub fn eval(&self, context: &Context) -> Result<Rule, Error> {
+        self.eval_in(context, &mut Environment::default())
+    }
+
+    /// Runs the program like [`Rule::eval_in`]. Rules nested deeper than the
+    /// environment allows, and environments that memoize or trace, are
+    /// evaluated on the rule itself.
+    pub fn eval_in(&self, context: &Context, environment: &mut Environment) -> Result<Rule, Error> {
+        if environment.records() || self.depth > environment.max_depth() {
+            return self.rule.eval_in(context, environment);
+        }
+        let context = environment.provide(&self.rule, context)?;
+        let context = context.as_ref();
+        let mut values: Vec<Rule> = Vec::new();
+        let mut handlers: Vec<(usize, usize)> = Vec::new();
+        let mut pc = 0;
+        while let Some(instruction) = self.instructions.get(pc) {
+            pc += 1;
+            let result = match instruction {
+                Instruction::Push(value) => environment.consume().map(|()| {
+                    values.push(value.clone());
+                }),
+                Instruction::Load(variable) => environment.consume().and_then(|()| {
+                    values.push(variable.eval_atom(context, environment.is_strict())?);
+                    Ok(())
+                }),
+                Instruction::Call(name, skip) => {
+                    if environment.calls(name) {
+                        Ok(())
+                    } else {
+                        pc += skip;
+                        environment.consume().map(|()| {
+                            values.push(Rule::Tuple(vec![]));
+                        })
+                    }
+                }
+                Instruction::Try(expression) => environment.consume().map(|()| {
+                    handlers.push((pc + expression, values.len()));
+                }),
+                Instruction::EndTry(fallback) => {
+                    handlers.pop();
+                    pc += fallback;
+                    Ok(())
+                }
+                Instruction::Apply(head, arity, node) => environment.consume().and_then(|()| {
+                    let operands = values.split_off(values.len() - arity);
+                    values.push(Rule::apply_head(
+                        head,
+                        operands,
+                        context,
+                        environment,
+                        &|| self.node(*node).clone(),
+                    )?);
+                    Ok(())
+                }),
+            };
+            if let Err(e) = result {
+                match handlers.pop() {
+                    Some((fallback, len)) if e.is_recoverable() => {
+                        values.truncate(len);
+                        pc = fallback;
+                    }
+                    _ => return Err(e),
+                }
+            }
+        }
+        values
+            .pop()
+            .ok_or_else(|| Error::CannotParse(self.rule.to_string()))
+    }
+
+    /// The expression at a pre-order position, counting only the nodes the
+    /// compiler visits.
+    fn node(&self, index: usize) -> &Rule {
+        let mut stack = vec![&self.rule];
+        let mut position = 0;
+        while let Some(rule) = stack.pop() {
+            if position == index {
+                return rule;
+            }
+            position += 1;
+            if let Rule::Tuple(children) = rule {
+                if descends(rule) {
+                    stack.extend(children.iter().skip(1).rev());
+                }
+            }
+        }
+        &self.rule
+    }
+}
+
+impl TryFrom<&Rule> for Program {
+    type Error = Error;
+
+    fn try_from(rule: &Rule) -> Result<Self, Error> {
+        Program::compile(rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Functions;
+    use crate::rule::Budget;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_program_compile_ok() {
+        let rule = Rule::from_str("(if (eq $role admin) (list all) ())").unwrap();
+        assert_eq!(
+            Program::compile(&rule).unwrap().instructions(),
+            &[
+                Instruction::Load(Rule::String(String::from("$role"))),
+                Instruction::Push(Rule::String(String::from("admin"))),
+                Instruction::Apply(Rule::Eq(String::from("eq")), 2, 1),
+                Instruction::Push(Rule::String(String::from("all"))),
+                Instruction::Apply(Rule::List(String::from("list")), 1, 4),
+                Instruction::Push(Rule::Tuple(vec![])),
+                Instruction::Apply(Rule::If(String::from("if")), 3, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_program_eval_ok() {
+        for (rule, context) in [
+            ("(if (eq $role admin) (list all) (list))", "role:admin"),
+            ("(if (eq $role admin) (list all) (list))", "role:user"),
+            ("(concat $tenant / (if true 1 2))", "tenant:acme"),
+            ("(and (in $age (list 18 21)) (or false true))", "age:21"),
+            ("(eq 1 true)", ""),
+        ] {
+            let rule = Rule::from_str(rule).unwrap();
+            let context = Context::from_str(context).unwrap();
+            assert_eq!(
+                Program::compile(&rule).unwrap().eval(&context),
+                rule.eval(&context)
+            );
+        }
+    }
+
+    #[test]
+    fn test_program_eval_in_ok() {
+        let mut functions = Functions::new();
+        functions.register("is-blacklisted", |arguments| {
+            Ok(Rule::Bool(arguments == [Rule::Integer(13)]))
+        });
+        let environments: [fn(&Functions) -> Environment; 4] = [
+            |functions| Environment::default().with_functions(functions),
+            |_| Environment::default().with_numeric_coercion(true),
+            |_| Environment::default().with_budget(Budget::default().with_nodes(4)),
+            |_| Environment::default().with_max_depth(1),
+        ];
+        for (rule, context) in [
+            (
+                "(if (is-blacklisted $user_id) (list) (list read))",
+                "user_id:13",
+            ),
+            ("(if (unknown $user_id) (list) (list read))", "user_id:13"),
+            ("(eq $age 18.0)", "age:18"),
+            ("(try (eq 1 true) (list read))", ""),
+            ("(try (list $missing) (eq 1 1))", ""),
+            ("(and (eq 1 1) (eq 2 2))", ""),
+        ] {
+            let rule = Rule::from_str(rule).unwrap();
+            let context = Context::from_str(context).unwrap();
+            let program = Program::compile(&rule).unwrap();
+            for environment in environments {
+                assert_eq!(
+                    program.eval_in(&context, &mut environment(&functions)),
+                    rule.eval_in(&context, &mut environment(&functions)),
+                    "{rule}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_program_compile_err() {
+        assert_eq!(
+            Program::compile(&Rule::from_str("(list (eq 1))").unwrap()),
+            Err(Error::InvalidEqStatement(Rule::from_str("(eq 1)").unwrap()))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::rule::{Category, Context, Rule};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = [
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+];
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+pub(crate) fn insert_environment(context: &mut Context, now: SystemTime) {
+    let seconds = now
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let days = seconds / 86_400;
+    let (hour, minute, second) = (seconds % 86_400 / 3_600, seconds % 3_600 / 60, seconds % 60);
+    let (year, month, day) = civil_from_days(days);
+    let date = format!("{year:04}-{month:02}-{day:02}");
+    for (key, value) in [
+        (
+            "now",
+            Rule::String(format!("{date}T{hour:02}:{minute:02}:{second:02}Z")),
+        ),
+        ("date", Rule::String(date)),
+        (
+            "weekday",
+            Rule::String(WEEKDAYS[(days % 7) as usize].to_string()),
+        ),
+        (
+            "hour",
+            Rule::Integer(i32::try_from(hour).unwrap_or_default()),
+        ),
+    ] {
+        if context
+            .get(&format!("{}.{key}", Category::Environment.prefix()))
+            .is_err()
+        {
+            context.insert_in(Category::Environment, key, value);
+        }
+    }
+}
+
+// Howard Hinnant's days-to-civil algorithm, restricted to dates after the epoch.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::Environment;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clock_environment_ok() {
+        // 2024-02-29T13:45:10Z, a Thursday.
+        let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(1_709_214_310));
+        let mut context = Context::default();
+        insert_environment(&mut context, clock.now());
+        assert_eq!(
+            context,
+            Context::builder()
+                .str("env.now", "2024-02-29T13:45:10Z")
+                .str("env.date", "2024-02-29")
+                .str("env.weekday", "thursday")
+                .int("env.hour", 13)
+                .build()
+        );
+
+        let rule = Rule::from_str(
+            "(if (and (in $env.weekday (list saturday sunday)) (ne $role admin)) (list) (list read))",
+        )
+        .unwrap();
+        let eval = |clock: &FixedClock, context: &str| {
+            rule.eval_in(
+                &Context::from_str(context).unwrap(),
+                &mut Environment::default().with_clock(clock),
+            )
+        };
+        let saturday = FixedClock(UNIX_EPOCH + Duration::from_secs(1_709_386_000));
+        let read = Ok(Rule::Tuple(vec![Rule::String(String::from("read"))]));
+        assert_eq!(eval(&clock, "role:user"), read);
+        assert_eq!(eval(&saturday, "role:user"), Ok(Rule::Tuple(vec![])));
+        assert_eq!(eval(&saturday, "role:admin"), read);
+        assert_eq!(
+            eval(&saturday, "role:user,env.weekday:monday"),
+            read,
+            "caller-supplied environment attributes take precedence"
+        );
+    }
+
+    struct CountingClock(std::cell::Cell<u64>);
+
+    impl Clock for CountingClock {
+        fn now(&self) -> SystemTime {
+            self.0.set(self.0.get() + 1);
+            UNIX_EPOCH + Duration::from_secs(1_709_214_310 + self.0.get())
+        }
+    }
+
+    #[test]
+    fn test_clock_cached_per_evaluation_ok() {
+        use crate::config::Config;
+        use crate::permission::Operation;
+        use crate::resource::{Hierarchy, Path};
+
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $env.now never) (list all) (list))"}
+            "/reports" = {access_rule = "(if (eq $env.weekday thursday) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let clock = CountingClock(std::cell::Cell::new(0));
+        let mut environment = Environment::default().with_clock(&clock);
+        assert_eq!(
+            rh.is_allowed_in(
+                Operation::Read,
+                &Path::from_str("/reports").unwrap(),
+                &Context::default(),
+                &mut environment
+            ),
+            Ok(true)
+        );
+        assert_eq!(clock.0.get(), 1);
+    }
+}
@@ -1,19 +1,228 @@
-use crate::resource::Attributes;
-use serde::Deserialize;
+use crate::resource::{self, Algorithm, Attributes, Combining, Path, PathOptions};
+use crate::rule::{self, Context, Rule};
+use crate::schema::Schema;
+use crate::scope::Scopes;
+use crate::slo::Slo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub const STARTER_POLICY: &str = include_str!("../res/starter.toml");
+pub const STARTER_CONTEXT: &str = include_str!("../res/starter-context.txt");
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml_edit::TomlError),
+    #[error("Rule error in resource '{0}': {1}")]
+    Rule(String, rule::Error),
+    #[error(transparent)]
+    Resource(#[from] resource::Error),
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
 pub struct Config {
     pub resources: std::collections::HashMap<String, Attributes>,
+    #[serde(default)]
+    pub attributes: Schema,
+    #[serde(default)]
+    pub presets: Presets,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_operators: Option<Vec<String>>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub slo: Slo,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub numeric_coercion: bool,
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub paths: PathOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenancy: Option<Tenancy>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Scopes::is_empty")]
+    pub scopes: Scopes,
+}
+
+/// Mandatory tenant check for every resource under `prefix`, whose last
+/// `:param` segment must match the caller's `attribute`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct Tenancy {
+    pub prefix: String,
+    pub attribute: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inject: bool,
+}
+
+impl Tenancy {
+    #[must_use]
+    pub fn param(&self) -> Option<&str> {
+        self.prefix
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .next_back()
+    }
+
+    /// Whether `path` lies under the prefix, both parsed under `options`. A
+    /// `:param` prefix segment matches any segment. A prefix that does not
+    /// parse covers every path, so that it is reported rather than ignored.
+    #[must_use]
+    pub fn covers(&self, path: &str, options: PathOptions) -> bool {
+        let Ok(prefix) = Path::parse(&self.prefix, options) else {
+            return true;
+        };
+        Path::parse(path, options).is_ok_and(|path| path.starts_with(&prefix))
+    }
+
+    /// The segment of `path` standing where the prefix has its last `:param`.
+    #[must_use]
+    pub fn segment(&self, path: &str, options: PathOptions) -> Option<String> {
+        let prefix = Path::parse(&self.prefix, options).ok()?;
+        let position = prefix
+            .segments()
+            .collect::<Vec<_>>()
+            .iter()
+            .rposition(|segment| segment.starts_with(':'))?;
+        Path::parse(path, options)
+            .ok()?
+            .segments()
+            .nth(position)
+            .map(str::to_string)
+    }
+}
+
+/// How decisions combine along a path, under `[settings]`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub combining: Combining,
+    pub algorithm: Algorithm,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Serialize)]
+pub struct Limits {
+    pub max_resources: Option<usize>,
+    pub max_rule_nodes: Option<usize>,
+    pub max_params: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Serialize)]
+pub struct Presets {
+    #[serde(default)]
+    pub anonymous: BTreeMap<String, String>,
+    #[serde(default)]
+    pub service: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn anonymous_context(&self) -> Result<Context, rule::Error> {
+        customize(Context::anonymous(), &self.presets.anonymous)
+    }
+
+    pub fn service_context(&self, name: &str) -> Result<Context, rule::Error> {
+        customize(Context::service(name), &self.presets.service)
+    }
+
+    pub fn set_access_rule(&mut self, path: &str, access_rule: &str) -> Result<String, Error> {
+        let rule = Rule::from_str(access_rule)
+            .and_then(|rule| {
+                rule.alias_operations(&self.aliases)
+                    .validate()
+                    .map(|()| rule)
+            })
+            .map_err(|e| Error::Rule(path.to_string(), e))?;
+        Attributes {
+            access_rule: Some(rule.clone()),
+            ..self.resources.get(path).cloned().unwrap_or_default()
+        }
+        .load(path, self)?;
+        let mut resource = toml_edit::Table::new();
+        resource.insert("access_rule", toml_edit::value(rule.to_string()));
+        let mut resources = toml_edit::Table::new();
+        resources.set_implicit(true);
+        resources.insert(path, toml_edit::Item::Table(resource));
+        let mut patch = toml_edit::DocumentMut::new();
+        patch.insert("resources", toml_edit::Item::Table(resources));
+        self.resources
+            .entry(path.to_string())
+            .or_default()
+            .access_rule = Some(rule);
+        Ok(patch.to_string())
+    }
+}
+
+/// Rewrites every access rule in canonical form, keeping the rest of the
+/// document as is. Rules holding `;` comments are checked but left as written.
+pub fn format(source: &str) -> Result<String, Error> {
+    let mut document = source.parse::<toml_edit::DocumentMut>()?;
+    let Some(resources) = document
+        .get_mut("resources")
+        .and_then(toml_edit::Item::as_table_like_mut)
+    else {
+        return Ok(document.to_string());
+    };
+    for (path, attributes) in resources.iter_mut() {
+        let Some(value) = attributes
+            .get_mut("access_rule")
+            .and_then(toml_edit::Item::as_value_mut)
+        else {
+            continue;
+        };
+        let Some(access_rule) = value.as_str() else {
+            continue;
+        };
+        let rule =
+            Rule::from_str(access_rule).map_err(|e| Error::Rule(path.get().to_string(), e))?;
+        if rule::has_comment(access_rule) {
+            continue;
+        }
+        let decor = value.decor().clone();
+        *value = toml_edit::Value::from(rule.to_string());
+        *value.decor_mut() = decor;
+    }
+    Ok(document.to_string())
+}
+
+fn customize(
+    mut context: Context,
+    attributes: &BTreeMap<String, String>,
+) -> Result<Context, rule::Error> {
+    for (key, value) in attributes {
+        context.insert(key, Rule::from_literal(value)?);
+    }
+    Ok(context)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::resource::Attributes;
-    use crate::rule::Rule;
-    use std::str::FromStr;
     use toml;
 
+    #[test]
+    fn test_config_tenancy_ok() {
+        let tenancy = Tenancy {
+            prefix: String::from("/t/:tenant_id/"),
+            attribute: String::from("tenant_id"),
+            inject: false,
+        };
+        let options = PathOptions::default();
+        assert_eq!(tenancy.param(), Some("tenant_id"));
+        assert!(tenancy.covers("/t/:tenant_id", options));
+        assert!(tenancy.covers("/t/:tenant_id/docs", options));
+        assert!(tenancy.covers("//t/:tid/docs", options));
+        assert!(!tenancy.covers("/t", options));
+        assert!(!tenancy.covers("/tenants/:tenant_id", options));
+        assert_eq!(
+            tenancy.segment("//t/:tid/docs", options),
+            Some(String::from(":tid"))
+        );
+        assert_eq!(tenancy.segment("/t", options), None);
+    }
+
     #[test]
     fn test_config_deserialization_ok() {
         let left: Result<Config, toml::de::Error> = toml::from_str::<Config>(
@@ -28,8 +237,25 @@ mod tests {
                 Attributes {
                     access_rule: Some(Rule::from_str("()").unwrap()),
                     description: Some("Root".to_string()),
+                    public: false,
+                    rules: BTreeMap::new(),
+                    list_filter_rule: None,
+                    properties: BTreeMap::new(),
+                    operations: None,
+                    combining: None,
                 },
             )]),
+            attributes: Schema::default(),
+            presets: Presets::default(),
+            allowed_operators: None,
+            limits: Limits::default(),
+            slo: Slo::default(),
+            numeric_coercion: false,
+            settings: Settings::default(),
+            paths: PathOptions::default(),
+            tenancy: None,
+            aliases: BTreeMap::new(),
+            scopes: Scopes::default(),
         });
         assert_eq!(left, right);
 
@@ -48,6 +274,12 @@ mod tests {
                     Attributes {
                         access_rule: Some(Rule::from_str("()").unwrap()),
                         description: Some("Root".to_string()),
+                        public: false,
+                        rules: BTreeMap::new(),
+                        list_filter_rule: None,
+                        properties: BTreeMap::new(),
+                        operations: None,
+                        combining: None,
                     },
                 ),
                 (
@@ -55,6 +287,12 @@ mod tests {
                     Attributes {
                         access_rule: Some(Rule::from_str("()").unwrap()),
                         description: Some("Root".to_string()),
+                        public: false,
+                        rules: BTreeMap::new(),
+                        list_filter_rule: None,
+                        properties: BTreeMap::new(),
+                        operations: None,
+                        combining: None,
                     },
                 ),
                 (
@@ -62,9 +300,26 @@ mod tests {
                     Attributes {
                         access_rule: Some(Rule::from_str("()").unwrap()),
                         description: Some("Root".to_string()),
+                        public: false,
+                        rules: BTreeMap::new(),
+                        list_filter_rule: None,
+                        properties: BTreeMap::new(),
+                        operations: None,
+                        combining: None,
                     },
                 ),
             ]),
+            attributes: Schema::default(),
+            presets: Presets::default(),
+            allowed_operators: None,
+            limits: Limits::default(),
+            slo: Slo::default(),
+            numeric_coercion: false,
+            settings: Settings::default(),
+            paths: PathOptions::default(),
+            tenancy: None,
+            aliases: BTreeMap::new(),
+            scopes: Scopes::default(),
         });
         assert_eq!(left, right);
     }
@@ -85,4 +340,239 @@ mod tests {
         };
         assert_eq!(left, right);
     }
+
+    #[test]
+    fn test_config_round_trip_ok() {
+        let config = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))", description = "Root"}
+            "/test" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            toml::from_str::<Config>(&toml::to_string(&config).unwrap()),
+            Ok(config)
+        );
+    }
+
+    #[test]
+    fn test_config_presets_ok() {
+        let config = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list)"}
+
+            [presets.anonymous]
+            role = "guest"
+
+            [presets.service]
+            role = "system"
+            authenticated = "false"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.anonymous_context(),
+            Context::from_str("principal:anonymous,authenticated:false,role:guest")
+        );
+        assert_eq!(
+            config.service_context("billing"),
+            Context::from_str("principal:service,service:billing,authenticated:false,role:system")
+        );
+    }
+
+    #[test]
+    fn test_config_format_ok() {
+        assert_eq!(
+            format(
+                r#"# Policy for the API
+[resources]
+# Everyone may read the root
+"/" = {access_rule = "(  list   read )", description = "Root"}
+"/admin" = { access_rule = "(if (eq $role admin)   (list all) (list))" } # admins only
+"/team" = {access_rule = """(if (eq $role admin)
+    (list all) ; admins get everything
+    (list read))"""}
+"/quoted" = {access_rule = "(eq $name   \"a;b\")"}
+
+[resources."/private"]
+description = "Private" # no rule yet
+"#
+            )
+            .unwrap(),
+            r#"# Policy for the API
+[resources]
+# Everyone may read the root
+"/" = {access_rule = "(list read)", description = "Root"}
+"/admin" = { access_rule = "(if (eq $role admin) (list all) (list))" } # admins only
+"/team" = {access_rule = """(if (eq $role admin)
+    (list all) ; admins get everything
+    (list read))"""}
+"/quoted" = {access_rule = '(eq $name "a;b")'}
+
+[resources."/private"]
+description = "Private" # no rule yet
+"#
+        );
+    }
+
+    #[test]
+    fn test_config_format_err() {
+        assert!(matches!(
+            format(
+                r#"
+                [resources]
+                "/" = {access_rule = "(list read))"}
+            "#
+            ),
+            Err(Error::Rule(path, _)) if path == "/"
+        ));
+        assert!(matches!(format("[resources"), Err(Error::Toml(_))));
+    }
+
+    #[test]
+    fn test_config_set_access_rule_ok() {
+        let mut config = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "()", description = "Root"}
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.set_access_rule("/", "(list   read)").unwrap(),
+            "[resources.\"/\"]\naccess_rule = \"(list read)\"\n"
+        );
+        assert_eq!(
+            config.resources["/"],
+            Attributes {
+                access_rule: Some(Rule::from_str("(list read)").unwrap()),
+                description: Some("Root".to_string()),
+                public: false,
+                rules: BTreeMap::new(),
+                list_filter_rule: None,
+                properties: BTreeMap::new(),
+                operations: None,
+                combining: None,
+            }
+        );
+        assert!(matches!(
+            config.set_access_rule("/", "(if 1 (list) (list))"),
+            Err(Error::Rule(path, rule::Error::InvalidIfCondition(_))) if path == "/"
+        ));
+    }
+
+    #[test]
+    fn test_config_set_access_rule_err() {
+        let mut config = toml::from_str::<Config>(
+            r#"
+            allowed_operators = ["if", "eq", "list"]
+            tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant"}
+            [attributes]
+            role = "string"
+            [resources]
+            "/" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap();
+        for (path, access_rule, expected) in [
+            (
+                "/",
+                "(if (ne $role admin) (list) (list read))",
+                resource::Error::ForbiddenOperator(String::from("/"), String::from("ne")),
+            ),
+            (
+                "/",
+                "(if (eq $role 3) (list read) (list))",
+                resource::Error::TypeError(
+                    String::from("/"),
+                    rule::Error::TypeMismatch(Rule::from_str("(eq $role 3)").unwrap()),
+                ),
+            ),
+            (
+                "/t/:tenant_id",
+                "(list read)",
+                resource::Error::MissingTenantCheck(
+                    String::from("/t/:tenant_id"),
+                    String::from("subject.tenant"),
+                ),
+            ),
+        ] {
+            assert!(matches!(
+                config.set_access_rule(path, access_rule),
+                Err(Error::Resource(e)) if e == expected
+            ));
+        }
+        assert_eq!(
+            config.resources["/"].access_rule,
+            Some(Rule::from_str("(list read)").unwrap())
+        );
+        assert!(!config.resources.contains_key("/t/:tenant_id"));
+    }
+
+    #[test]
+    fn test_config_structured_rule_ok() {
+        let config = toml::from_str::<Config>(
+            r#"
+            [resources."/admin"]
+            access_rule = {op = "if", cond = {op = "eq", left = "$role", right = "admin"}, then = {op = "list", args = ["all"]}, else = {op = "list"}}
+
+            [resources."/reports"]
+            access_rule = {op = "if", cond = {op = "in", value = "$level", list = [1, 2.5, true]}, then = ["read"], else = []}
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.resources["/admin"].access_rule,
+            Some(Rule::from_str("(if (eq $role admin) (list all) (list))").unwrap())
+        );
+        assert_eq!(
+            config.resources["/reports"].access_rule,
+            Some(Rule::from_str("(if (in $level (list 1 2.5 true)) (list read) (list))").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_config_structured_rule_err() {
+        for access_rule in [
+            r#"{op = "unknown"}"#,
+            r#"{op = "eq", left = "$role", rigth = "admin"}"#,
+            r#"{op = "if", cond = 1, then = ["read"], else = []}"#,
+        ] {
+            assert!(
+                toml::from_str::<Config>(&format!(
+                    "[resources.\"/\"]\naccess_rule = {access_rule}"
+                ))
+                .is_err(),
+                "{access_rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_starter_ok() {
+        use crate::permission::Operation;
+        use crate::resource::{Hierarchy, Path};
+
+        let rh: Hierarchy = toml::from_str::<Config>(STARTER_POLICY)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let context = Context::from_str(STARTER_CONTEXT.trim()).unwrap();
+        for (operation, path, expected) in [
+            (Operation::List, "/documents", true),
+            (Operation::Create, "/documents", true),
+            (Operation::Delete, "/documents", false),
+            (Operation::Delete, "/documents/7", true),
+            (Operation::Delete, "/documents/8", false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(operation.clone(), &Path::from_str(path).unwrap(), &context),
+                Ok(expected),
+                "{operation} {path}"
+            );
+        }
+    }
 }
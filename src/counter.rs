@@ -0,0 +1,88 @@
+use crate::rule::{Error, Rule};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub trait CounterProvider {
+    fn usage(&self, counter: &str, key: &Rule, window: &str) -> Result<i32, Error>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryCounters(Mutex<HashMap<(String, String, String), i32>>);
+
+impl InMemoryCounters {
+    #[must_use]
+    pub fn new() -> Self {
+        InMemoryCounters::default()
+    }
+
+    pub fn increment(&self, counter: &str, key: &Rule, window: &str) -> i32 {
+        let mut counters = self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let count = counters
+            .entry((counter.to_string(), key.to_string(), window.to_string()))
+            .or_default();
+        *count += 1;
+        *count
+    }
+
+    pub fn reset(&self, window: &str) {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .retain(|(_, _, w), _| w != window);
+    }
+}
+
+impl CounterProvider for InMemoryCounters {
+    fn usage(&self, counter: &str, key: &Rule, window: &str) -> Result<i32, Error> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&(counter.to_string(), key.to_string(), window.to_string()))
+            .copied()
+            .unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{Context, Environment};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_in_memory_counters_ok() {
+        let counters = InMemoryCounters::new();
+        let user = Rule::Integer(1);
+        assert_eq!(counters.usage("exports", &user, "today"), Ok(0));
+        counters.increment("exports", &user, "today");
+        assert_eq!(counters.increment("exports", &user, "today"), 2);
+        assert_eq!(counters.usage("exports", &user, "today"), Ok(2));
+        assert_eq!(counters.usage("exports", &Rule::Integer(2), "today"), Ok(0));
+        counters.reset("today");
+        assert_eq!(counters.usage("exports", &user, "today"), Ok(0));
+    }
+
+    #[test]
+    fn test_eval_rule_usage_ok() {
+        let counters = InMemoryCounters::new();
+        let rule = Rule::from_str(
+            "(if (in (usage exports $user_id today) (list 0 1)) (list create) (list))",
+        )
+        .unwrap();
+        let context = Context::from_str("user_id:1").unwrap();
+        for expected in [true, true, false] {
+            assert_eq!(
+                rule.eval_in(
+                    &context,
+                    &mut Environment::default().with_counters(&counters)
+                ) == Ok(Rule::Tuple(vec![Rule::String(String::from("create"))])),
+                expected
+            );
+            counters.increment("exports", &Rule::Integer(1), "today");
+        }
+    }
+}
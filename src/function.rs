@@ -0,0 +1,89 @@
+use crate::rule::{Error, Rule};
+use std::collections::HashMap;
+
+type HostFunction = Box<dyn Fn(&[Rule]) -> Result<Rule, Error> + Send + Sync>;
+
+#[derive(Default)]
+pub struct Functions(HashMap<String, HostFunction>);
+
+impl Functions {
+    #[must_use]
+    pub fn new() -> Self {
+        Functions::default()
+    }
+
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Rule]) -> Result<Rule, Error> + Send + Sync + 'static,
+    ) {
+        self.0.insert(name.to_string(), Box::new(function));
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn call(&self, name: &str, arguments: &[Rule]) -> Option<Result<Rule, Error>> {
+        self.0.get(name).map(|function| function(arguments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{Context, Environment};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_functions_eval_ok() {
+        let mut functions = Functions::new();
+        functions.register("is-blacklisted", |arguments| {
+            Ok(Rule::Bool(arguments == [Rule::Integer(13)]))
+        });
+        let rule = Rule::from_str("(if (is-blacklisted $user_id) (list) (list read))").unwrap();
+        let eval = |context: &str| {
+            rule.eval_in(
+                &Context::from_str(context).unwrap(),
+                &mut Environment::default().with_functions(&functions),
+            )
+        };
+        assert_eq!(eval("user_id:13"), Ok(Rule::Tuple(vec![])));
+        assert_eq!(
+            eval("user_id:1"),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("read"))]))
+        );
+        assert_eq!(rule.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_functions_eval_err() {
+        let mut functions = Functions::new();
+        functions.register("fail", |arguments| {
+            Err(Error::CannotParse(
+                Rule::Tuple(arguments.to_vec()).to_string(),
+            ))
+        });
+        assert_eq!(
+            Rule::from_str("(eq (fail 1 2) true)").unwrap().eval_in(
+                &Context::from_str("").unwrap(),
+                &mut Environment::default().with_functions(&functions),
+            ),
+            Err(Error::CannotParse(String::from("(1 2)")))
+        );
+        assert_eq!(
+            Rule::from_str("(unknown 1)").unwrap().eval_in(
+                &Context::from_str("").unwrap(),
+                &mut Environment::default().with_functions(&functions),
+            ),
+            Ok(Rule::Tuple(vec![]))
+        );
+        assert_eq!(
+            Rule::from_str("(if (is-blacklisted (eq 1)) (list) (list read))")
+                .unwrap()
+                .validate(),
+            Err(Error::InvalidEqStatement(Rule::from_str("(eq 1)").unwrap()))
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use crate::clock::Clock;
+use crate::rule::{self, Context};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("Token expired")]
+    Expired,
+    #[error("Token not yet valid")]
+    NotYetValid,
+    #[error("Invalid issuer '{0}'")]
+    InvalidIssuer(String),
+    #[error("Invalid audience")]
+    InvalidAudience,
+    #[error("Claims rejected: {0}")]
+    Rejected(String),
+    #[error("Rule error: {0}")]
+    Rule(#[from] rule::Error),
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|one| one == audience),
+        }
+    }
+}
+
+/// Decoded and signature-checked JWT payload. Claims other than the registered
+/// ones end up in `custom`.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct Claims {
+    pub sub: Option<String>,
+    pub iss: Option<String>,
+    pub aud: Option<Audience>,
+    pub exp: Option<u64>,
+    pub nbf: Option<u64>,
+    pub iat: Option<u64>,
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub custom: serde_json::Map<String, serde_json::Value>,
+}
+
+pub trait ClaimsValidator {
+    fn validate(&self, claims: &Claims, now: SystemTime) -> Result<(), Error>;
+}
+
+impl<F: Fn(&Claims, SystemTime) -> Result<(), Error>> ClaimsValidator for F {
+    fn validate(&self, claims: &Claims, now: SystemTime) -> Result<(), Error> {
+        self(claims, now)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Validation {
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+}
+
+impl Validation {
+    #[must_use]
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+}
+
+impl ClaimsValidator for Validation {
+    fn validate(&self, claims: &Claims, now: SystemTime) -> Result<(), Error> {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let leeway = self.leeway.as_secs();
+        if claims
+            .exp
+            .is_some_and(|exp| now > exp.saturating_add(leeway))
+        {
+            return Err(Error::Expired);
+        }
+        if claims
+            .nbf
+            .is_some_and(|nbf| now.saturating_add(leeway) < nbf)
+        {
+            return Err(Error::NotYetValid);
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err(Error::InvalidIssuer(claims.iss.clone().unwrap_or_default()));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            if !claims
+                .aud
+                .as_ref()
+                .is_some_and(|aud| aud.contains(audience))
+            {
+                return Err(Error::InvalidAudience);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Context {
+    /// Maps claims under the `subject` category: `sub` to `subject.id`, `iss` to
+    /// `subject.issuer`, `scope` to the `subject.scopes` list and custom claims to
+    /// `subject.<claim>`.
+    pub fn from_jwt_claims(claims: &Claims) -> Result<Context, rule::Error> {
+        let mut subject = claims.custom.clone();
+        if let Some(sub) = &claims.sub {
+            subject.insert(String::from("id"), sub.clone().into());
+        }
+        if let Some(iss) = &claims.iss {
+            subject.insert(String::from("issuer"), iss.clone().into());
+        }
+        if let Some(scope) = &claims.scope {
+            subject.insert(
+                String::from("scopes"),
+                scope.split_whitespace().collect::<Vec<&str>>().into(),
+            );
+        }
+        Context::try_from(serde_json::json!({ "subject": subject }))
+    }
+
+    pub fn from_validated_jwt_claims(
+        claims: &Claims,
+        validators: &[&dyn ClaimsValidator],
+        clock: &dyn Clock,
+    ) -> Result<Context, Error> {
+        let now = clock.now();
+        for validator in validators {
+            validator.validate(claims, now)?;
+        }
+        Ok(Context::from_jwt_claims(claims)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::rule::Rule;
+    use std::str::FromStr;
+
+    fn claims() -> Claims {
+        serde_json::from_value(serde_json::json!({
+            "sub": "alice",
+            "iss": "https://auth.example.com",
+            "aud": ["api", "web"],
+            "exp": 1_700_000_600,
+            "nbf": 1_700_000_000,
+            "scope": "reports:read reports:write",
+            "tenant": "acme",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_jwt_claims_ok() {
+        let context = Context::from_validated_jwt_claims(
+            &claims(),
+            &[&Validation::default()
+                .with_issuer("https://auth.example.com")
+                .with_audience("api")],
+            &FixedClock(UNIX_EPOCH + Duration::from_secs(1_700_000_300)),
+        )
+        .unwrap();
+        assert_eq!(
+            context,
+            Context::builder()
+                .str("subject.id", "alice")
+                .str("subject.issuer", "https://auth.example.com")
+                .list("subject.scopes", ["reports:read", "reports:write"])
+                .str("subject.tenant", "acme")
+                .build()
+        );
+        assert_eq!(
+            Rule::from_str("(and (in reports:write $subject.scopes) (eq $subject.tenant acme))")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_jwt_claims_err() {
+        let at = |seconds| FixedClock(UNIX_EPOCH + Duration::from_secs(seconds));
+        let validation = Validation::default().with_leeway(Duration::from_secs(60));
+        for (validator, clock, expected) in [
+            (
+                &validation as &dyn ClaimsValidator,
+                at(1_700_000_700),
+                Error::Expired,
+            ),
+            (&validation, at(1_699_999_900), Error::NotYetValid),
+            (
+                &Validation::default().with_issuer("https://evil.example.com"),
+                at(1_700_000_300),
+                Error::InvalidIssuer(String::from("https://auth.example.com")),
+            ),
+            (
+                &Validation::default().with_audience("admin"),
+                at(1_700_000_300),
+                Error::InvalidAudience,
+            ),
+            (
+                &|claims: &Claims, _| match claims.custom.get("tenant") {
+                    Some(tenant) if tenant == "globex" => Ok(()),
+                    _ => Err(Error::Rejected(String::from("tenant"))),
+                },
+                at(1_700_000_300),
+                Error::Rejected(String::from("tenant")),
+            ),
+        ] {
+            assert_eq!(
+                Context::from_validated_jwt_claims(&claims(), &[validator], &clock),
+                Err(expected)
+            );
+        }
+    }
+}
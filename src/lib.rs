@@ -1,11 +1,39 @@
+#[cfg(test)]
+extern crate self as abac;
+
+pub mod builder;
+pub mod bytecode;
+pub mod clock;
 pub mod config;
+pub mod counter;
+pub mod function;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod mining;
 pub mod permission;
+pub mod request;
+pub mod resolver;
 pub mod resource;
+pub mod review;
 pub mod rule;
+pub mod schema;
+pub mod scope;
+pub mod slo;
+pub mod stats;
+pub mod token;
+
+#[cfg(feature = "derive")]
+pub use abac_derive::IntoContext;
 
 #[cfg(test)]
 mod tests {
-    use crate::{config::Config, resource::Hierarchy};
+    use crate::{
+        config::Config,
+        resource::Hierarchy,
+        rule::{Context, Rule},
+    };
+    use abac_derive::IntoContext;
+    use std::str::FromStr;
 
     #[test]
     fn main_test() {
@@ -22,4 +50,42 @@ mod tests {
         )
         .unwrap();
     }
+
+    #[derive(IntoContext)]
+    struct User {
+        id: i32,
+        #[context(rename = "role")]
+        kind: &'static str,
+        groups: Vec<String>,
+        active: bool,
+        #[context(skip)]
+        #[allow(dead_code)]
+        password: String,
+    }
+
+    #[test]
+    fn into_context_test() {
+        let context = Context::from(User {
+            id: 7,
+            kind: "admin",
+            groups: vec![String::from("staff")],
+            active: true,
+            password: String::from("hunter2"),
+        });
+        assert_eq!(
+            context,
+            Context::builder()
+                .int("id", 7)
+                .str("role", "admin")
+                .list("groups", ["staff"])
+                .bool("active", true)
+                .build()
+        );
+        assert_eq!(
+            Rule::from_str("(and (in staff $groups) (eq $role admin))")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Bool(true))
+        );
+    }
 }
@@ -1,18 +1,104 @@
 use abac::{
-    config::Config,
-    permission::Operation,
-    resource::{Hierarchy, Path},
+    config::{Config, STARTER_CONTEXT, STARTER_POLICY},
+    permission::{Obligation, Operation},
+    request::LogFormat,
+    resource::Hierarchy,
     rule::Context,
+    slo,
 };
-use clap::Parser;
-use std::{fs, io, path::PathBuf, str::FromStr};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{fs, io, path::PathBuf, process::ExitCode, str::FromStr, time::Instant};
 
 /// ABAC CLI
 #[derive(Parser)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Path to the configuration file
     #[arg(short, long, default_value = None)]
     config: Option<PathBuf>,
+    /// Print parse, hierarchy build and evaluation times to stderr
+    #[arg(long)]
+    timing: bool,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a commented starter policy and an example context
+    Init {
+        /// Directory to write policy.toml and context.txt into
+        #[arg(default_value = ".")]
+        directory: PathBuf,
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the policy against its [slo] targets, timing a request log
+    Slo {
+        /// Access log whose requests are replayed against the policy
+        corpus: PathBuf,
+        /// Format of the access log
+        #[arg(long, value_enum, default_value_t = CorpusFormat::Nginx)]
+        format: CorpusFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CorpusFormat {
+    Nginx,
+    Envoy,
+}
+
+impl From<CorpusFormat> for LogFormat {
+    fn from(format: CorpusFormat) -> Self {
+        match format {
+            CorpusFormat::Nginx => LogFormat::Nginx,
+            CorpusFormat::Envoy => LogFormat::Envoy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+    Yaml,
+    Exitcode,
+}
+
+#[derive(Serialize)]
+struct Decision {
+    allowed: bool,
+    operation: String,
+    resource: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    obligations: Vec<Obligation>,
+}
+
+#[derive(Serialize)]
+struct Diagnostic {
+    error: String,
+}
+
+impl Output {
+    fn print<T: Serialize>(self, value: &T, text: &str) {
+        match self {
+            Output::Text => println!("{text}"),
+            Output::Json => println!("{}", serde_json::to_string(value).unwrap_or_default()),
+            Output::Yaml => {
+                if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(value) {
+                    for (key, value) in fields {
+                        println!("{key}: {value}");
+                    }
+                }
+            }
+            Output::Exitcode => {}
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -23,29 +109,130 @@ enum Error {
     TomlDe(#[from] toml::de::Error),
     #[error("No configuration file")]
     NoConf,
+    #[error("Refusing to overwrite '{0}', use --force")]
+    AlreadyExists(PathBuf),
     #[error("Resource error: {0}")]
     Resource(#[from] abac::resource::Error),
     #[error("Rule error: {0}")]
     Rule(#[from] abac::rule::Error),
+    #[error("Request error: {0}")]
+    Request(#[from] abac::request::Error),
+    #[error("SLO error: {0}")]
+    Slo(#[from] slo::Error),
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Init { directory, force }) => {
+            return match init(directory, *force) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    ExitCode::from(2)
+                }
+            };
+        }
+        Some(Command::Slo { corpus, format }) => {
+            return match check_slo(args.config.as_deref(), corpus, *format) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(Error::Slo(slo::Error::Exceeded(violations))) => {
+                    for violation in violations {
+                        eprintln!("SLO violation: {violation}");
+                    }
+                    ExitCode::from(1)
+                }
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    ExitCode::from(2)
+                }
+            };
+        }
+        None => {}
+    }
+    let output = args.output;
+    match run(args) {
+        Ok(decision) => {
+            output.print(&decision, &decision.allowed.to_string());
+            if decision.allowed {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            }
+        }
+        Err(e) => {
+            let diagnostic = Diagnostic {
+                error: e.to_string(),
+            };
+            match output {
+                Output::Text | Output::Exitcode => eprintln!("Error: {e}"),
+                output => output.print(&diagnostic, &diagnostic.error),
+            }
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn init(directory: &std::path::Path, force: bool) -> Result<(), Error> {
+    let files = [
+        (directory.join("policy.toml"), STARTER_POLICY),
+        (directory.join("context.txt"), STARTER_CONTEXT),
+    ];
+    if !force {
+        if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+            return Err(Error::AlreadyExists(path.clone()));
+        }
+    }
+    fs::create_dir_all(directory)?;
+    for (path, contents) in &files {
+        fs::write(path, contents)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+fn check_slo(
+    config: Option<&std::path::Path>,
+    corpus: &std::path::Path,
+    format: CorpusFormat,
+) -> Result<(), Error> {
+    let config: Config = toml::from_str(&fs::read_to_string(config.ok_or(Error::NoConf)?)?)?;
+    let slo = config.slo.clone();
+    let rh = <Config as TryInto<Hierarchy>>::try_into(config)?;
+    let corpus = LogFormat::from(format).parse_all(&fs::read_to_string(corpus)?)?;
+    slo.validate(&rh, &corpus)?;
+    Ok(())
 }
 
-fn main() -> Result<(), Error> {
-    let Some(config) = Args::parse().config else {
+fn run(args: Args) -> Result<Decision, Error> {
+    let Some(config) = args.config else {
         return Err(Error::NoConf);
     };
 
-    let rh =
-        <Config as TryInto<Hierarchy>>::try_into(toml::from_str(&fs::read_to_string(&config)?)?)?;
+    let start = Instant::now();
+    let config: Config = toml::from_str(&fs::read_to_string(&config)?)?;
+    let parsed = Instant::now();
+    let rh = <Config as TryInto<Hierarchy>>::try_into(config)?;
+    let built = Instant::now();
 
-    println!(
-        "{}",
-        rh.is_allowed(
-            Operation::Create,
-            &mut Path::from_str("/private/2")?,
-            &Context::from_str("user_id:1,role:admin")?,
-        )
-        .unwrap()
-    );
+    let (operation, resource) = (Operation::Create, "/private/2");
+    let obligations = rh.obligations(
+        operation.clone(),
+        &rh.path(resource)?,
+        &Context::from_str("user_id:1,role:admin")?,
+    )?;
+    let evaluated = Instant::now();
 
-    Ok(())
+    if args.timing {
+        eprintln!("parse: {:?}", parsed - start);
+        eprintln!("build: {:?}", built - parsed);
+        eprintln!("eval: {:?}", evaluated - built);
+    }
+
+    Ok(Decision {
+        allowed: obligations.is_some(),
+        operation: operation.to_string(),
+        resource: resource.to_string(),
+        obligations: obligations.unwrap_or_default(),
+    })
 }
@@ -0,0 +1,130 @@
+use crate::builder::RuleBuilder;
+use crate::permission::{Operation, Permission};
+use crate::request::AccessRequest;
+use crate::rule::Rule;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoleCandidate {
+    pub attribute: String,
+    pub value: Rule,
+    pub operations: Vec<Operation>,
+    pub support: usize,
+}
+
+impl RoleCandidate {
+    #[must_use]
+    pub fn rule(&self) -> Rule {
+        RuleBuilder::iff(RuleBuilder::eq(
+            RuleBuilder::var(&self.attribute),
+            self.value.clone(),
+        ))
+        .then(RuleBuilder::list(
+            self.operations.iter().map(ToString::to_string),
+        ))
+        .otherwise(RuleBuilder::list::<Rule>([]))
+    }
+}
+
+#[derive(Default)]
+struct Observation {
+    allowed: Permission,
+    denied: Permission,
+    support: usize,
+}
+
+#[must_use]
+pub fn mine_roles(requests: &[AccessRequest], min_support: usize) -> Vec<RoleCandidate> {
+    let mut observations: Vec<(&str, &Rule, Observation)> = Vec::new();
+    for request in requests {
+        let allowed = (200..400).contains(&request.status);
+        let operation = Permission::from(request.operation.clone());
+        for attribute in request.context.keys() {
+            let Ok(value) = request.context.get(attribute) else {
+                continue;
+            };
+            let index = observations
+                .iter()
+                .position(|(a, v, _)| *a == attribute && *v == value)
+                .unwrap_or_else(|| {
+                    observations.push((attribute, value, Observation::default()));
+                    observations.len() - 1
+                });
+            let observation = &mut observations[index].2;
+            if allowed {
+                observation.allowed |= operation;
+                observation.support += 1;
+            } else {
+                observation.denied |= operation;
+            }
+        }
+    }
+
+    let mut candidates: Vec<RoleCandidate> = observations
+        .into_iter()
+        .filter(|(_, _, observation)| observation.support >= min_support)
+        .filter_map(|(attribute, value, observation)| {
+            let granted = observation.allowed & !observation.denied;
+            let operations = granted.operations();
+            (!operations.is_empty()).then(|| RoleCandidate {
+                attribute: attribute.to_string(),
+                value: value.clone(),
+                operations,
+                support: observation.support,
+            })
+        })
+        .collect();
+    candidates.sort_by(|l, r| {
+        r.support
+            .cmp(&l.support)
+            .then_with(|| l.attribute.cmp(&r.attribute))
+    });
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::LogFormat;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_mine_roles_ok() {
+        let requests = LogFormat::Nginx
+            .parse_all(
+                r#"10.0.0.1 - alice [10/Oct/2024:13:55:36 +0000] "GET /reports HTTP/1.1" 200 0 "-" "-"
+10.0.0.1 - alice [10/Oct/2024:13:55:37 +0000] "DELETE /reports/1 HTTP/1.1" 204 0 "-" "-"
+10.0.0.2 - bob [10/Oct/2024:13:55:38 +0000] "GET /reports HTTP/1.1" 200 0 "-" "-"
+10.0.0.2 - bob [10/Oct/2024:13:55:39 +0000] "DELETE /reports/1 HTTP/1.1" 403 0 "-" "-"
+10.0.0.3 - - [10/Oct/2024:13:55:40 +0000] "GET /reports HTTP/1.1" 401 0 "-" "-""#,
+            )
+            .unwrap();
+        let candidates = mine_roles(&requests, 2);
+        assert_eq!(
+            candidates,
+            vec![
+                RoleCandidate {
+                    attribute: String::from("authenticated"),
+                    value: Rule::Bool(true),
+                    operations: vec![Operation::Read],
+                    support: 3,
+                },
+                RoleCandidate {
+                    attribute: String::from("principal"),
+                    value: Rule::String(String::from("user")),
+                    operations: vec![Operation::Read],
+                    support: 3,
+                },
+                RoleCandidate {
+                    attribute: String::from("user"),
+                    value: Rule::String(String::from("alice")),
+                    operations: vec![Operation::Read, Operation::Delete],
+                    support: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            candidates[2].rule(),
+            Rule::from_str("(if (eq $user alice) (list read delete) (list))").unwrap()
+        );
+    }
+}
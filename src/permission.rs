@@ -1,34 +1,297 @@
-use crate::rule::Rule;
-use std::{fmt, str::FromStr};
+use crate::rule::{self, Rule};
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops, str::FromStr};
 
-pub type Permission = u8;
+const CUSTOM_OFFSET: u32 = 5;
 
-impl From<Rule> for Permission {
-    fn from(rule: Rule) -> Self {
+/// Set of operations. The five built-in operations use the low bits, the
+/// remaining bits are available to custom operations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Permission(u32);
+
+impl Permission {
+    pub const NONE: Permission = Permission(0);
+    pub const CREATE: Permission = Permission(0b00001);
+    pub const READ: Permission = Permission(0b00010);
+    pub const UPDATE: Permission = Permission(0b00100);
+    pub const DELETE: Permission = Permission(0b01000);
+    pub const LIST: Permission = Permission(0b10000);
+    pub const ALL: Permission = Permission(0b11111);
+    pub const MAX_CUSTOM: u32 = u32::BITS - CUSTOM_OFFSET;
+
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Self {
+        Permission(bits)
+    }
+
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Permission for the custom operation number `index`, if it fits.
+    #[must_use]
+    pub const fn custom(index: u32) -> Option<Self> {
+        if index < Self::MAX_CUSTOM {
+            Some(Permission(1 << (CUSTOM_OFFSET + index)))
+        } else {
+            None
+        }
+    }
+
+    /// Parses a single operation token: an operation name, `all`, `none` or
+    /// `custom.N`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "all" => Some(Permission::ALL),
+            "none" => Some(Permission::NONE),
+            _ => match name.strip_prefix("custom.") {
+                Some(index) => index.parse().ok().and_then(Permission::custom),
+                None => Operation::from_str(name).ok().map(Permission::from),
+            },
+        }
+    }
+
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    #[must_use]
+    pub const fn contains(self, other: Permission) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[must_use]
+    pub const fn intersects(self, other: Permission) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    #[must_use]
+    pub const fn union(self, other: Permission) -> Self {
+        Permission(self.0 | other.0)
+    }
+
+    #[must_use]
+    pub const fn intersection(self, other: Permission) -> Self {
+        Permission(self.0 & other.0)
+    }
+
+    #[must_use]
+    pub const fn difference(self, other: Permission) -> Self {
+        Permission(self.0 & !other.0)
+    }
+
+    pub fn insert(&mut self, other: Permission) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Permission) {
+        self.0 &= !other.0;
+    }
+
+    #[must_use]
+    pub fn operations(self) -> Vec<Operation> {
+        Operation::ALL
+            .into_iter()
+            .filter(|operation| operation.allowed_for(self))
+            .collect()
+    }
+
+    fn custom_indexes(self) -> impl Iterator<Item = u32> {
+        (0..Self::MAX_CUSTOM).filter(move |index| self.0 & (1 << (CUSTOM_OFFSET + index)) != 0)
+    }
+}
+
+impl PartialEq<u32> for Permission {
+    fn eq(&self, other: &u32) -> bool {
+        self.0 == *other
+    }
+}
+
+impl ops::BitOr for Permission {
+    type Output = Permission;
+
+    fn bitor(self, rhs: Permission) -> Permission {
+        self.union(rhs)
+    }
+}
+
+impl ops::BitOrAssign for Permission {
+    fn bitor_assign(&mut self, rhs: Permission) {
+        self.insert(rhs);
+    }
+}
+
+impl ops::BitAnd for Permission {
+    type Output = Permission;
+
+    fn bitand(self, rhs: Permission) -> Permission {
+        self.intersection(rhs)
+    }
+}
+
+impl ops::Not for Permission {
+    type Output = Permission;
+
+    fn not(self) -> Permission {
+        Permission(!self.0)
+    }
+}
+
+impl From<Permission> for Vec<Operation> {
+    fn from(permission: Permission) -> Self {
+        permission.operations()
+    }
+}
+
+impl FromIterator<Operation> for Permission {
+    fn from_iter<I: IntoIterator<Item = Operation>>(operations: I) -> Self {
+        operations
+            .into_iter()
+            .fold(Permission::default(), |permission, operation| {
+                permission | Permission::from(operation)
+            })
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operations: Vec<String> = self
+            .operations()
+            .iter()
+            .map(Operation::to_string)
+            .chain(self.custom_indexes().map(|index| format!("custom.{index}")))
+            .collect();
+        write!(f, "{}", operations.join("|"))
+    }
+}
+
+impl FromStr for Permission {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('|')
+            .filter(|operation| !operation.is_empty())
+            .try_fold(Permission::default(), |permission, operation| {
+                Permission::from_name(operation)
+                    .map(|operation| permission | operation)
+                    .ok_or(())
+            })
+    }
+}
+
+impl Serialize for Permission {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Permission {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Permission::from_str(&s)
+            .map_err(|()| serde::de::Error::custom(format!("invalid permission '{s}'")))
+    }
+}
+
+/// Operations granted by an evaluated access rule. Obligations and deny items
+/// are skipped, anything else that is not an operation token is an error.
+impl TryFrom<Rule> for Permission {
+    type Error = rule::Error;
+
+    fn try_from(rule: Rule) -> Result<Self, Self::Error> {
         let Rule::Tuple(items) = rule else {
-            return 0;
+            return Ok(Permission::default());
         };
+        if let Some(Rule::Deny(_)) = items.first() {
+            return Ok(Permission::default());
+        }
 
-        let mut permission = 0;
+        let mut permission = Permission::default();
         for item in items {
-            let Rule::String(operation) = item else {
-                return 0;
-            };
-
-            let Ok(operation) = Operation::from_str(&operation) else {
-                if operation == "all" {
-                    return 0b11111;
-                }
-                return 0;
-            };
-
-            permission |= <Operation as Into<Permission>>::into(operation);
+            if Obligation::try_from(&item).is_ok() || is_deny(&item) {
+                continue;
+            }
+            permission |= match &item {
+                Rule::String(operation) => Permission::from_name(operation),
+                _ => None,
+            }
+            .ok_or(rule::Error::InvalidPermission(item))?;
         }
-        permission
+        Ok(permission)
     }
 }
 
-#[derive(Debug, Clone)]
+pub(crate) fn is_deny(rule: &Rule) -> bool {
+    matches!(rule, Rule::Tuple(children) if matches!(children.first(), Some(Rule::Deny(_))))
+}
+
+/// Operations explicitly denied by an evaluated access rule, either returned
+/// directly as `(deny ...)` or as an item of the granted list.
+#[must_use]
+pub fn denied(rule: &Rule) -> Permission {
+    let Rule::Tuple(items) = rule else {
+        return Permission::default();
+    };
+    if let Some(Rule::Deny(_)) = items.first() {
+        return Permission::try_from(Rule::Tuple(items[1..].to_vec())).unwrap_or_default();
+    }
+    items
+        .iter()
+        .filter(|item| is_deny(item))
+        .map(denied)
+        .fold(Permission::default(), |permission, denied| {
+            permission | denied
+        })
+}
+
+/// Duty returned next to the granted operations by `(require ...)` or
+/// `(obligation ...)`, which the caller must enforce (audit, masking, step-up).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Obligation {
+    pub name: String,
+    pub arguments: Vec<Rule>,
+}
+
+impl TryFrom<&Rule> for Obligation {
+    type Error = ();
+
+    fn try_from(rule: &Rule) -> Result<Self, Self::Error> {
+        let Rule::Tuple(children) = rule else {
+            return Err(());
+        };
+        match children.as_slice() {
+            [Rule::Require(_), Rule::String(name), arguments @ ..] => Ok(Obligation {
+                name: name.clone(),
+                arguments: arguments.to_vec(),
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Obligation {
+    #[must_use]
+    pub fn from_permission_rule(rule: &Rule) -> Vec<Obligation> {
+        let Rule::Tuple(items) = rule else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .filter_map(|item| Obligation::try_from(item).ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     Create,
     Read,
@@ -40,11 +303,11 @@ pub enum Operation {
 impl From<Operation> for Permission {
     fn from(val: Operation) -> Self {
         match val {
-            Operation::Create => 0b00001,
-            Operation::Read => 0b00010,
-            Operation::Update => 0b00100,
-            Operation::Delete => 0b01000,
-            Operation::List => 0b10000,
+            Operation::Create => Permission::CREATE,
+            Operation::Read => Permission::READ,
+            Operation::Update => Permission::UPDATE,
+            Operation::Delete => Permission::DELETE,
+            Operation::List => Permission::LIST,
         }
     }
 }
@@ -81,6 +344,14 @@ impl FromStr for Operation {
 }
 
 impl Operation {
+    pub const ALL: [Operation; 5] = [
+        Operation::Create,
+        Operation::Read,
+        Operation::Update,
+        Operation::Delete,
+        Operation::List,
+    ];
+
     #[must_use]
     pub fn allowed_for(&self, permission: Permission) -> bool {
         match self {
@@ -88,9 +359,7 @@ impl Operation {
             | Operation::Read
             | Operation::Update
             | Operation::Delete
-            | Operation::List => {
-                permission & <Operation as Into<Permission>>::into(self.clone()) != 0
-            }
+            | Operation::List => permission.intersects(self.clone().into()),
         }
     }
 }
@@ -105,88 +374,97 @@ mod tests {
     #[test]
     fn test_permission_from_rule_ok() {
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("()")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             0
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list create)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Create)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list read)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Read)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list update)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Update)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list delete)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Delete)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list list)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::List)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list delete update)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Delete)
                 | <Operation as Into<Permission>>::into(Operation::Update)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list create read update delete)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Create)
                 | <Operation as Into<Permission>>::into(Operation::Read)
                 | <Operation as Into<Permission>>::into(Operation::Update)
                 | <Operation as Into<Permission>>::into(Operation::Delete)
         );
         assert_eq!(
-            Permission::from(
+            Permission::try_from(
                 Rule::from_str("(list all)")
                     .unwrap()
                     .eval(&Context::from_str("").unwrap())
                     .unwrap()
-            ),
+            )
+            .unwrap(),
             <Operation as Into<Permission>>::into(Operation::Create)
                 | <Operation as Into<Permission>>::into(Operation::Read)
                 | <Operation as Into<Permission>>::into(Operation::Update)
@@ -195,6 +473,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_permission_from_rule_tokens_ok() {
+        for (rule, expected) in [
+            ("(list none)", Permission::NONE),
+            ("(list read all)", Permission::ALL),
+            (
+                "(list none read custom.2)",
+                Permission::READ | Permission::custom(2).unwrap(),
+            ),
+        ] {
+            assert_eq!(
+                Permission::try_from(
+                    Rule::from_str(rule)
+                        .unwrap()
+                        .eval(&Context::default())
+                        .unwrap()
+                ),
+                Ok(expected),
+                "{rule}"
+            );
+        }
+        assert_eq!(Permission::from_str("none|read"), Ok(Permission::READ));
+    }
+
+    #[test]
+    fn test_permission_from_rule_err() {
+        for (rule, invalid) in [
+            ("(list all craete)", Rule::String(String::from("craete"))),
+            (
+                "(list read custom.99)",
+                Rule::String(String::from("custom.99")),
+            ),
+            ("(list read 1)", Rule::Integer(1)),
+        ] {
+            assert_eq!(
+                Permission::try_from(
+                    Rule::from_str(rule)
+                        .unwrap()
+                        .eval(&Context::default())
+                        .unwrap()
+                ),
+                Err(rule::Error::InvalidPermission(invalid)),
+                "{rule}"
+            );
+        }
+    }
+
     #[test]
     fn test_operation_into_permission() {
         let create: Permission = Operation::Create.into();
@@ -212,7 +537,7 @@ mod tests {
 
     #[test]
     fn test_operation_allowed() {
-        let permission: Permission = 0b11111;
+        let permission = Permission::from_bits(0b11111);
 
         assert!(Operation::Create.allowed_for(permission));
         assert!(Operation::Read.allowed_for(permission));
@@ -220,4 +545,82 @@ mod tests {
         assert!(Operation::Delete.allowed_for(permission));
         assert!(Operation::List.allowed_for(permission));
     }
+
+    #[test]
+    fn test_obligation_from_permission_rule_ok() {
+        let rule = Rule::from_str("(list read update (require mfa) (require reauth 5m))")
+            .unwrap()
+            .eval(&Context::from_str("").unwrap())
+            .unwrap();
+        assert_eq!(
+            Permission::try_from(rule.clone()).unwrap(),
+            <Operation as Into<Permission>>::into(Operation::Read)
+                | <Operation as Into<Permission>>::into(Operation::Update)
+        );
+        assert_eq!(
+            Obligation::from_permission_rule(&rule),
+            vec![
+                Obligation {
+                    name: String::from("mfa"),
+                    arguments: vec![],
+                },
+                Obligation {
+                    name: String::from("reauth"),
+                    arguments: vec![Rule::String(String::from("5m"))],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_permission_display_ok() {
+        let permission: Permission = [Operation::Read, Operation::List].into_iter().collect();
+        assert_eq!(permission.to_string(), "read|list");
+        assert_eq!(
+            Vec::<Operation>::from(permission),
+            vec![Operation::Read, Operation::List]
+        );
+        assert_eq!(Permission::from_str("list|read"), Ok(permission));
+        assert_eq!(Permission::from_str("all"), Ok(Permission::ALL));
+        assert_eq!(Permission::from_str(""), Ok(Permission::default()));
+        assert_eq!(Permission::default().to_string(), "");
+        assert_eq!(
+            serde_json::to_string(&permission).unwrap(),
+            r#""read|list""#
+        );
+        assert_eq!(
+            serde_json::from_str::<Permission>(r#""read|list""#).unwrap(),
+            permission
+        );
+    }
+
+    #[test]
+    fn test_permission_display_err() {
+        assert_eq!(Permission::from_str("read|write"), Err(()));
+        assert!(serde_json::from_str::<Permission>(r#""write""#).is_err());
+    }
+
+    #[test]
+    fn test_permission_set_operations_ok() {
+        let mut permission = Permission::READ | Permission::LIST;
+        assert!(permission.contains(Permission::READ));
+        assert!(!permission.contains(Permission::READ | Permission::DELETE));
+        assert!(permission.intersects(Permission::READ | Permission::DELETE));
+        assert_eq!(permission.difference(Permission::LIST), Permission::READ);
+        permission.remove(Permission::READ);
+        assert_eq!(permission, Permission::LIST);
+        assert!(Permission::NONE.is_empty());
+
+        let export = Permission::custom(0).unwrap();
+        permission.insert(export);
+        assert!(!Permission::ALL.contains(export));
+        assert_eq!(permission.to_string(), "list|custom.0");
+        assert_eq!(Permission::from_str("list|custom.0"), Ok(permission));
+        assert_eq!(
+            Permission::custom(Permission::MAX_CUSTOM - 1).map(Permission::bits),
+            Some(1 << 31)
+        );
+        assert_eq!(Permission::custom(Permission::MAX_CUSTOM), None);
+        assert_eq!(Permission::from_str("custom.27"), Err(()));
+    }
 }
@@ -0,0 +1,160 @@
+use crate::permission::Operation;
+use crate::rule::{self, Context, Rule};
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("Cannot parse log line '{0}'")]
+    InvalidLine(String),
+    #[error("Unsupported HTTP method '{0}'")]
+    UnsupportedMethod(String),
+    #[error("Rule error: {0}")]
+    Rule(#[from] rule::Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AccessRequest {
+    pub operation: Operation,
+    pub path: String,
+    pub context: Context,
+    pub status: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Nginx,
+    Envoy,
+}
+
+impl LogFormat {
+    pub fn parse(self, line: &str) -> Result<AccessRequest, Error> {
+        let invalid = || Error::InvalidLine(line.to_string());
+        let (context, rest) = match self {
+            LogFormat::Nginx => {
+                let mut fields = line.splitn(4, ' ');
+                let (Some(_), Some(_), Some(user), Some(rest)) =
+                    (fields.next(), fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(invalid());
+                };
+                (user_context(user)?, rest)
+            }
+            LogFormat::Envoy => (Context::anonymous(), line),
+        };
+        let mut quoted = rest.splitn(3, '"');
+        let (Some(_), Some(request), Some(rest)) = (quoted.next(), quoted.next(), quoted.next())
+        else {
+            return Err(invalid());
+        };
+        let mut request = request.split(' ');
+        let (Some(method), Some(target)) = (request.next(), request.next()) else {
+            return Err(invalid());
+        };
+        let status = rest
+            .split_whitespace()
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(invalid)?;
+        Ok(AccessRequest {
+            operation: operation(method)?,
+            path: target
+                .split(['?', '#'])
+                .next()
+                .unwrap_or(target)
+                .to_string(),
+            context,
+            status,
+        })
+    }
+
+    pub fn parse_all(self, input: &str) -> Result<Vec<AccessRequest>, Error> {
+        input
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| self.parse(line))
+            .collect()
+    }
+}
+
+fn operation(method: &str) -> Result<Operation, Error> {
+    match method {
+        "GET" | "HEAD" => Ok(Operation::Read),
+        "POST" => Ok(Operation::Create),
+        "PUT" | "PATCH" => Ok(Operation::Update),
+        "DELETE" => Ok(Operation::Delete),
+        _ => Err(Error::UnsupportedMethod(method.to_string())),
+    }
+}
+
+fn user_context(user: &str) -> Result<Context, Error> {
+    if user == "-" {
+        return Ok(Context::anonymous());
+    }
+    let mut context = Context::from_str("")?;
+    context.insert("principal", Rule::String(String::from("user")));
+    context.insert("user", Rule::from_literal(user)?);
+    context.insert("authenticated", Rule::Bool(true));
+    Ok(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_format_nginx_ok() {
+        let request = LogFormat::Nginx
+            .parse(
+                r#"10.0.0.1 - alice [10/Oct/2024:13:55:36 +0000] "DELETE /private/42?force=true HTTP/1.1" 204 0 "-" "curl/8.0""#,
+            )
+            .unwrap();
+        assert_eq!(request.operation, Operation::Delete);
+        assert_eq!(request.path, "/private/42");
+        assert_eq!(request.status, 204);
+        assert_eq!(
+            request.context.get("user"),
+            Ok(&Rule::String(String::from("alice")))
+        );
+        assert_eq!(request.context.get("authenticated"), Ok(&Rule::Bool(true)));
+
+        let request = LogFormat::Nginx
+            .parse(r#"10.0.0.1 - - [10/Oct/2024:13:55:36 +0000] "GET / HTTP/1.1" 403 12 "-" "-""#)
+            .unwrap();
+        assert_eq!(request.context, Context::anonymous());
+    }
+
+    #[test]
+    fn test_log_format_envoy_ok() {
+        let requests = LogFormat::Envoy
+            .parse_all(
+                r#"[2024-10-10T13:55:36.000Z] "POST /teams/7/members HTTP/1.1" 201 - 120 0 5 4 "10.0.0.1" "curl/8.0" "id-1" "api" "10.0.1.1:80"
+
+[2024-10-10T13:55:37.000Z] "PATCH /teams/7 HTTP/2" 401 - 0 0 1 - "10.0.0.1" "curl/8.0" "id-2" "api" "-""#,
+            )
+            .unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].operation, Operation::Create);
+        assert_eq!(requests[0].path, "/teams/7/members");
+        assert_eq!(requests[0].status, 201);
+        assert_eq!(requests[1].operation, Operation::Update);
+        assert_eq!(requests[1].status, 401);
+    }
+
+    #[test]
+    fn test_log_format_err() {
+        assert_eq!(
+            LogFormat::Nginx.parse("garbage"),
+            Err(Error::InvalidLine(String::from("garbage")))
+        );
+        assert_eq!(
+            LogFormat::Envoy.parse(r#"[2024-10-10T13:55:36.000Z] "OPTIONS / HTTP/1.1" 200"#),
+            Err(Error::UnsupportedMethod(String::from("OPTIONS")))
+        );
+        assert_eq!(
+            LogFormat::Envoy.parse(r#"[2024-10-10T13:55:36.000Z] "GET / HTTP/1.1" -"#),
+            Err(Error::InvalidLine(String::from(
+                r#"[2024-10-10T13:55:36.000Z] "GET / HTTP/1.1" -"#
+            )))
+        );
+    }
+}
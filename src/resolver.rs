@@ -0,0 +1,155 @@
+use crate::rule::{Context, Error, Rule};
+use std::collections::HashMap;
+
+/// Policy information point consulted for attributes a rule references but the
+/// context does not carry.
+pub trait AttributeResolver {
+    fn resolve(&self, key: &str, context: &Context) -> Result<Option<Rule>, Error>;
+}
+
+impl<F: Fn(&str, &Context) -> Result<Option<Rule>, Error>> AttributeResolver for F {
+    fn resolve(&self, key: &str, context: &Context) -> Result<Option<Rule>, Error> {
+        self(key, context)
+    }
+}
+
+impl AttributeResolver for HashMap<String, Rule> {
+    fn resolve(&self, key: &str, _: &Context) -> Result<Option<Rule>, Error> {
+        Ok(self.get(key).cloned())
+    }
+}
+
+#[cfg(feature = "async")]
+pub trait AsyncAttributeResolver {
+    fn resolve(
+        &self,
+        key: &str,
+        context: &Context,
+    ) -> impl std::future::Future<Output = Result<Option<Rule>, Error>> + Send;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::permission::Operation;
+    use crate::resource::{Hierarchy, Path};
+    use crate::rule::Environment;
+    use std::cell::Cell;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_attribute_resolver_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (contains-any $user.groups admins) (list all) (list))"}
+            "/reports" = {access_rule = "(if (and (eq $action read) (contains-any $user.groups analysts)) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let calls = Cell::new(0);
+        let directory = |key: &str, context: &Context| {
+            calls.set(calls.get() + 1);
+            if key == "action" {
+                return Err(Error::KeyNotInContext(key.to_string()));
+            }
+            Ok(match (key, context.get("user.id")) {
+                ("user.groups", Ok(Rule::String(id))) if id == "alice" => {
+                    Some(Rule::from_str("(analysts staff)")?)
+                }
+                _ => None,
+            })
+        };
+        assert_eq!(
+            rh.is_allowed_with_resolver(
+                Operation::Read,
+                &Path::from_str("/reports").unwrap(),
+                &Context::from_str("user.id:alice").unwrap(),
+                &directory,
+            ),
+            Ok(true)
+        );
+        assert_eq!(calls.get(), 1, "resolved values are cached per evaluation");
+        assert_eq!(
+            rh.is_allowed_with_resolver(
+                Operation::Read,
+                &Path::from_str("/reports").unwrap(),
+                &Context::from_str("user.id:alice,user.groups:[admins]").unwrap(),
+                &directory,
+            ),
+            Ok(true),
+            "context attributes take precedence"
+        );
+        assert_eq!(calls.get(), 1);
+
+        let resolver = HashMap::from([(String::from("role"), Rule::String(String::from("admin")))]);
+        assert_eq!(
+            Rule::from_str("(eq $role admin)").unwrap().eval_in(
+                &Context::default(),
+                &mut Environment::default().with_resolver(&resolver)
+            ),
+            Ok(Rule::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_attribute_resolver_err() {
+        let failing = |key: &str, _: &Context| Err(Error::KeyNotInContext(key.to_string()));
+        assert_eq!(
+            Rule::from_str("(eq $role admin)").unwrap().eval_in(
+                &Context::default(),
+                &mut Environment::default().with_resolver(&failing)
+            ),
+            Err(Error::KeyNotInContext(String::from("role")))
+        );
+    }
+
+    #[cfg(feature = "async")]
+    struct Directory;
+
+    #[cfg(feature = "async")]
+    impl AsyncAttributeResolver for Directory {
+        async fn resolve(&self, key: &str, context: &Context) -> Result<Option<Rule>, Error> {
+            Ok(match (key, context.get("user.id")) {
+                ("user.groups", Ok(Rule::String(id))) if id == "alice" => {
+                    Some(Rule::from_str("(analysts staff)")?)
+                }
+                ("user.groups", _) => Some(Rule::Tuple(vec![])),
+                _ => None,
+            })
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_attribute_resolver_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/reports/:report_id" = {access_rule = "(if (contains-any $user.groups analysts) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let is_allowed = |context: &str| {
+            let (path, context) = (
+                Path::from_str("/reports/7").unwrap(),
+                Context::from_str(context).unwrap(),
+            );
+            let future = rh.is_allowed_async(Operation::Read, &path, &context, &Directory);
+            let mut future = std::pin::pin!(future);
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            match std::future::Future::poll(future.as_mut(), &mut cx) {
+                std::task::Poll::Ready(result) => result,
+                std::task::Poll::Pending => unreachable!(),
+            }
+        };
+        assert_eq!(is_allowed("report_id:7,user.id:alice"), Ok(true));
+        assert_eq!(is_allowed("report_id:7,user.id:bob"), Ok(false));
+    }
+}
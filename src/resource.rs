@@ -1,9 +1,21 @@
-use crate::config::Config;
-use crate::permission::{Operation, Permission};
-use crate::rule::{self, Context, Rule};
+use crate::builder::RuleBuilder;
+use crate::bytecode::Program;
+use crate::config::{Config, Limits, Presets, Settings, Tenancy};
+use crate::permission::{self, Obligation, Operation, Permission};
+#[cfg(feature = "async")]
+use crate::resolver::AsyncAttributeResolver;
+use crate::resolver::AttributeResolver;
+use crate::rule::{self, Budget, Category, Context, Environment, Rule};
+use crate::schema::{self, AttributeType, Schema};
+use crate::scope::Scopes;
+use crate::slo::Slo;
+use crate::stats::{NodeStats, ResourceStats};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, thiserror::Error, PartialEq)]
@@ -12,14 +24,649 @@ pub enum Error {
     FormatError(String),
     #[error("Duplicate resource definition '{0}'")]
     DuplicateResource(String),
-    #[error("Ambiguous resource definition '{0}'. {1} is already defined")]
-    AmbiguousResource(String, String),
+    #[error("Type error in resource '{0}': {1}")]
+    TypeError(String, rule::Error),
+    #[error("Operator '{1}' is not allowed in resource '{0}'")]
+    ForbiddenOperator(String, String),
+    #[error("Policy exceeds the limit of {1} {0}")]
+    LimitExceeded(String, usize),
+    #[error("Unknown resource '{0}'")]
+    UnknownResource(String),
+    #[error("Invalid rule in resource '{0}': {1}")]
+    InvalidRule(String, rule::Error),
+    #[error("Cyclic resource-rule reference: {}", .0.join(" -> "))]
+    CyclicResourceRule(Vec<String>),
+    #[error("Unknown operation '{1}' in resource '{0}'")]
+    UnknownOperation(String, String),
+    #[error("Operation '{1}' is not declared by resource '{0}'")]
+    UndeclaredOperation(String, String),
+    #[error("Invalid operation alias '{0}' = '{1}'")]
+    InvalidAlias(String, String),
+    #[error("Tenancy prefix '{0}' has no :param segment")]
+    InvalidTenancy(String),
+    #[error("Resource '{0}' does not check tenant attribute '{1}'")]
+    MissingTenantCheck(String, String),
+    #[error("Dot segment in path '{0}'")]
+    DotSegment(String),
+    #[error("Invalid percent-encoding in path '{0}'")]
+    InvalidEncoding(String),
+    #[error("Cannot fingerprint policy: {0}")]
+    Fingerprint(String),
+}
+
+/// How a resource's access rule combines with the decision reached by its
+/// ancestors. Under `deny-overrides`, deny rules override every mode.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Combining {
+    /// The rule can only add to what the ancestors granted.
+    #[default]
+    Union,
+    /// The rule is ignored once an ancestor had a rule of its own.
+    FirstApplicable,
+    /// The rule replaces what the ancestors granted, tightening or loosening it.
+    ChildOverrides,
+}
+
+/// How explicit denials weigh against grants along the matched path.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    /// A denial anywhere on the path wins over every grant.
+    #[default]
+    DenyOverrides,
+    /// A grant anywhere on the path wins; denials only apply when nothing grants.
+    PermitOverrides,
+}
+
+impl fmt::Display for Combining {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Combining::Union => "union",
+            Combining::FirstApplicable => "first-applicable",
+            Combining::ChildOverrides => "child-overrides",
+        })
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Serialize, Default)]
 pub struct Attributes {
     pub access_rule: Option<Rule>,
     pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub public: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<String, Rule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list_filter_rule: Option<Rule>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "rule::literals"
+    )]
+    pub properties: BTreeMap<String, Rule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operations: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub combining: Option<Combining>,
+}
+
+impl Attributes {
+    fn resource_context<'a>(&self, with: &'a Context) -> Cow<'a, Context> {
+        if self.description.is_none() && self.properties.is_empty() {
+            return Cow::Borrowed(with);
+        }
+        let mut context = with.clone();
+        if let Some(description) = &self.description {
+            context.insert_in(
+                Category::Resource,
+                "description",
+                Rule::String(description.clone()),
+            );
+        }
+        for (key, value) in &self.properties {
+            context.insert_in(Category::Resource, key, value.clone());
+        }
+        Cow::Owned(context)
+    }
+
+    /// The schema the rules of the resource at `path` are typechecked with:
+    /// `path.<param>` takes the type declared for `<param>`, or string.
+    fn resource_schema(&self, path: &str, schema: &Schema) -> Schema {
+        let mut schema = schema.clone();
+        for param in path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+        {
+            let name = format!("path.{param}");
+            if !schema.contains(&name) {
+                let attribute_type = schema.get(param).unwrap_or(AttributeType::String);
+                schema.insert(&name, attribute_type);
+            }
+        }
+        for (name, attribute_type) in schema::BUILTINS {
+            if !schema.contains(name) {
+                schema.insert(name, attribute_type);
+            }
+        }
+        if self.description.is_some() {
+            schema.insert("resource.description", AttributeType::String);
+        }
+        for (key, value) in &self.properties {
+            let attribute_type = match value {
+                Rule::Bool(_) => AttributeType::Bool,
+                Rule::Integer(_) => AttributeType::Integer,
+                Rule::Float(_) => AttributeType::Float,
+                Rule::Tuple(_) => AttributeType::List,
+                _ => AttributeType::String,
+            };
+            schema.insert(&format!("resource.{key}"), attribute_type);
+        }
+        schema
+    }
+
+    fn prepare(&mut self, path: &str, aliases: &BTreeMap<String, String>) -> Result<(), Error> {
+        if !aliases.is_empty() {
+            self.rules = std::mem::take(&mut self.rules)
+                .into_iter()
+                .map(|(operation, rule)| match aliases.get(&operation) {
+                    Some(canonical) => (canonical.clone(), rule),
+                    None => (operation, rule),
+                })
+                .collect();
+            if let Some(access_rule) = &self.access_rule {
+                self.access_rule = Some(access_rule.alias_operations(aliases));
+            }
+        }
+        self.merge_rules(path)?;
+        self.check_operations(path, aliases)?;
+        for rule in [&self.access_rule, &self.list_filter_rule]
+            .into_iter()
+            .flatten()
+        {
+            rule.validate()
+                .map_err(|e| Error::InvalidRule(path.to_string(), e))?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, path: &str, schema: &Schema) -> Result<(), Error> {
+        if let Some(operation) = self
+            .access_rule
+            .iter()
+            .flat_map(Rule::granted_operations)
+            .find(|operation| Permission::from_name(operation).is_none())
+        {
+            return Err(Error::UnknownOperation(path.to_string(), operation));
+        }
+        if *schema != Schema::default() {
+            if let Some(access_rule) = &self.access_rule {
+                access_rule
+                    .typecheck(&self.resource_schema(path, schema))
+                    .map_err(|e| Error::TypeError(path.to_string(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares and checks the resource at `path` as config loading does, short
+    /// of resolving references to other resources.
+    pub(crate) fn load(&mut self, path: &str, config: &Config) -> Result<(), Error> {
+        self.check_operators(path, config.allowed_operators.as_ref())?;
+        self.prepare(path, &config.aliases)?;
+        self.enforce_tenancy(path, config.tenancy.as_ref(), config.paths)?;
+        self.check(path, &config.attributes)
+    }
+
+    /// Checks the rules as written, before the engine merges `rules` or
+    /// injects tenant checks with operators of its own.
+    fn check_operators(&self, path: &str, allowed: Option<&Vec<String>>) -> Result<(), Error> {
+        let Some(allowed) = allowed else {
+            return Ok(());
+        };
+        if let Some(operator) = [&self.access_rule, &self.list_filter_rule]
+            .into_iter()
+            .flatten()
+            .chain(self.rules.values())
+            .flat_map(Rule::operators)
+            .find(|operator| !allowed.contains(operator))
+        {
+            return Err(Error::ForbiddenOperator(path.to_string(), operator));
+        }
+        Ok(())
+    }
+
+    fn enforce_tenancy(
+        &mut self,
+        path: &str,
+        tenancy: Option<&Tenancy>,
+        options: PathOptions,
+    ) -> Result<(), Error> {
+        let Some(tenancy) = tenancy.filter(|tenancy| tenancy.covers(path, options)) else {
+            return Ok(());
+        };
+        let segment = tenancy
+            .segment(path, options)
+            .ok_or_else(|| Error::InvalidTenancy(tenancy.prefix.clone()))?;
+        let tenant = match segment.strip_prefix(':') {
+            Some(param) => RuleBuilder::var(&format!("path.{param}")),
+            None => {
+                Rule::from_literal(&segment).map_err(|e| Error::InvalidRule(path.to_string(), e))?
+            }
+        };
+        let missing = || Error::MissingTenantCheck(path.to_string(), tenancy.attribute.clone());
+        if self.public {
+            return Err(missing());
+        }
+        let Some(access_rule) = self.access_rule.take() else {
+            return Ok(());
+        };
+        let check = RuleBuilder::eq(tenant, RuleBuilder::var(&tenancy.attribute));
+        if tenancy.inject {
+            self.access_rule = Some(
+                RuleBuilder::iff(check)
+                    .then(access_rule)
+                    .otherwise(RuleBuilder::list::<Rule>([])),
+            );
+            return Ok(());
+        }
+        if !access_rule.grants_only_if(&check) {
+            return Err(missing());
+        }
+        self.access_rule = Some(access_rule);
+        Ok(())
+    }
+
+    fn denies(&self) -> bool {
+        [&self.access_rule, &self.list_filter_rule]
+            .into_iter()
+            .flatten()
+            .any(|rule| rule.operators().iter().any(|op| op == "deny"))
+    }
+
+    /// Fields that differ from `other`, with `properties` flattened to
+    /// `properties.<key>`.
+    fn differences(&self, other: &Attributes) -> Vec<AttributeDifference> {
+        let operations = |attributes: &Attributes| {
+            attributes
+                .operations
+                .as_ref()
+                .map(|operations| RuleBuilder::list(operations.iter().map(String::as_str)))
+        };
+        let mut fields = vec![
+            (
+                String::from("access_rule"),
+                self.access_rule.clone(),
+                other.access_rule.clone(),
+            ),
+            (
+                String::from("list_filter_rule"),
+                self.list_filter_rule.clone(),
+                other.list_filter_rule.clone(),
+            ),
+            (
+                String::from("public"),
+                Some(Rule::Bool(self.public)),
+                Some(Rule::Bool(other.public)),
+            ),
+            (
+                String::from("description"),
+                self.description.clone().map(Rule::String),
+                other.description.clone().map(Rule::String),
+            ),
+            (
+                String::from("operations"),
+                operations(self),
+                operations(other),
+            ),
+            (
+                String::from("combining"),
+                self.combining
+                    .map(|combining| Rule::String(combining.to_string())),
+                other
+                    .combining
+                    .map(|combining| Rule::String(combining.to_string())),
+            ),
+        ];
+        let mut keys: Vec<&String> = self
+            .properties
+            .keys()
+            .chain(other.properties.keys())
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            fields.push((
+                format!("properties.{key}"),
+                self.properties.get(key).cloned(),
+                other.properties.get(key).cloned(),
+            ));
+        }
+        fields
+            .into_iter()
+            .filter(|(_, left, right)| left != right)
+            .map(|(name, left, right)| AttributeDifference { name, left, right })
+            .collect()
+    }
+
+    fn references_resources(&self) -> bool {
+        self.access_rule
+            .as_ref()
+            .is_some_and(|rule| rule.operators().iter().any(|op| op == "resource-rule"))
+    }
+
+    fn map_rules(&mut self, f: &impl Fn(&Rule) -> Rule) {
+        if let Some(access_rule) = &self.access_rule {
+            self.access_rule = Some(f(access_rule));
+        }
+        if let Some(list_filter_rule) = &self.list_filter_rule {
+            self.list_filter_rule = Some(f(list_filter_rule));
+        }
+    }
+
+    fn check_operations(
+        &mut self,
+        path: &str,
+        aliases: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        let Some(declared) = &mut self.operations else {
+            return Ok(());
+        };
+        for operation in declared.iter_mut() {
+            if let Some(canonical) = aliases.get(operation) {
+                *operation = canonical.clone();
+            }
+            if Permission::from_name(operation).is_none_or(|p| p.bits().count_ones() != 1) {
+                return Err(Error::UnknownOperation(path.to_string(), operation.clone()));
+            }
+        }
+        if let Some(operation) = self
+            .access_rule
+            .iter()
+            .flat_map(Rule::granted_operations)
+            .find(|operation| !declared.contains(operation))
+        {
+            return Err(Error::UndeclaredOperation(path.to_string(), operation));
+        }
+        if let Some(access_rule) = &self.access_rule {
+            self.access_rule = Some(access_rule.expand_all(declared));
+        }
+        Ok(())
+    }
+
+    fn merge_rules(&mut self, path: &str) -> Result<(), Error> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+        let mut merged = vec![self
+            .access_rule
+            .take()
+            .unwrap_or_else(|| RuleBuilder::list::<Rule>([]))];
+        for (operation, condition) in std::mem::take(&mut self.rules) {
+            if Operation::from_str(&operation).is_err() {
+                return Err(Error::UnknownOperation(path.to_string(), operation));
+            }
+            merged.push(
+                RuleBuilder::iff(condition)
+                    .then(RuleBuilder::list([operation]))
+                    .otherwise(RuleBuilder::list::<Rule>([])),
+            );
+        }
+        self.access_rule = Some(RuleBuilder::concat(merged));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeUsage {
+    pub name: String,
+    pub resources: Vec<String>,
+    pub declared: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeadAttributes {
+    pub unreferenced: Vec<String>,
+    pub usually_missing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    Granted,
+    Denied,
+    NotGranted,
+}
+
+/// Outcome of [`Hierarchy::evaluate`]. `matched_path` is the resource that
+/// granted the operation, or the one that explicitly denied it, and
+/// `effective_permission` is everything the granting rule returned.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decision {
+    pub allowed: bool,
+    pub matched_path: Option<String>,
+    pub effective_permission: Permission,
+    pub obligations: Vec<Obligation>,
+    pub reason: Reason,
+}
+
+/// Access rule run on one resource while explaining a decision. `trace`
+/// lists the expressions it applied with their values, innermost first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub resource: String,
+    pub rule: Option<Rule>,
+    pub trace: Vec<(Rule, Rule)>,
+    pub result: Result<Rule, rule::Error>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub steps: Vec<Step>,
+    pub decision: Result<Decision, rule::Error>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            match &step.rule {
+                Some(rule) => writeln!(f, "{} {rule}", step.resource)?,
+                None => writeln!(f, "{} public", step.resource)?,
+            }
+            for (expression, value) in &step.trace {
+                writeln!(f, "  {expression} => {value}")?;
+            }
+            match &step.result {
+                Ok(value) => writeln!(f, "  = {value}")?,
+                Err(e) => writeln!(f, "  error: {e}")?,
+            }
+        }
+        match &self.decision {
+            Ok(decision) => match (&decision.reason, &decision.matched_path) {
+                (Reason::Granted, Some(path)) => write!(f, "granted by {path}"),
+                (Reason::Denied, Some(path)) => write!(f, "denied by {path}"),
+                _ => write!(f, "not granted"),
+            },
+            Err(e) => write!(f, "error: {e}"),
+        }
+    }
+}
+
+/// First granting rule found along a path, with the resource it belongs to.
+/// `steps` is only recorded when explaining.
+#[derive(Default)]
+struct Grant {
+    rule: Option<Rule>,
+    applicable: bool,
+    permission: Permission,
+    path: String,
+    denied_by: Option<String>,
+    algorithm: Algorithm,
+    steps: Option<Vec<Step>>,
+}
+
+impl Grant {
+    fn is_some(&self) -> bool {
+        self.rule.is_some()
+    }
+
+    fn decision(self) -> Decision {
+        match self {
+            Grant {
+                denied_by: Some(path),
+                ..
+            } => Decision {
+                allowed: false,
+                matched_path: Some(path),
+                effective_permission: Permission::NONE,
+                obligations: Vec::new(),
+                reason: Reason::Denied,
+            },
+            Grant {
+                rule: Some(rule),
+                permission,
+                path,
+                ..
+            } => Decision {
+                allowed: true,
+                matched_path: Some(path),
+                effective_permission: permission,
+                obligations: Obligation::from_permission_rule(&rule),
+                reason: Reason::Granted,
+            },
+            Grant { .. } => Decision {
+                allowed: false,
+                matched_path: None,
+                effective_permission: Permission::NONE,
+                obligations: Vec::new(),
+                reason: Reason::NotGranted,
+            },
+        }
+    }
+
+    fn record(
+        &mut self,
+        trail: &[&str],
+        rule: Option<&Rule>,
+        environment: &mut Environment,
+        result: &Result<Rule, rule::Error>,
+    ) {
+        if let Some(steps) = self.steps.as_mut() {
+            steps.push(Step {
+                resource: trail_path(trail),
+                rule: rule.cloned(),
+                trace: environment.take_trace(),
+                result: result.clone(),
+            });
+        }
+    }
+}
+
+/// Hashes the canonical JSON form of the config, whose maps are key-sorted.
+fn fingerprint(config: &Config) -> Result<u64, Error> {
+    let canonical = serde_json::to_value(config)
+        .and_then(|value| serde_json::to_vec(&value))
+        .map_err(|e| Error::Fingerprint(e.to_string()))?;
+    let digest = Sha256::digest(canonical);
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = rest
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn trail_path(trail: &[&str]) -> String {
+    trail.iter().map(|segment| format!("/{segment}")).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub resource: String,
+    pub allowed: Result<bool, rule::Error>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeDifference {
+    pub name: String,
+    pub left: Option<Rule>,
+    pub right: Option<Rule>,
+}
+
+/// Resources added, removed or changed between two hierarchies, by path.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HierarchyDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, Vec<AttributeDifference>)>,
+}
+
+impl HierarchyDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for HierarchyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = |rule: &Option<Rule>| rule.as_ref().map_or(String::from("-"), Rule::to_string);
+        for path in &self.added {
+            writeln!(f, "+ {path}")?;
+        }
+        for path in &self.removed {
+            writeln!(f, "- {path}")?;
+        }
+        for (path, differences) in &self.changed {
+            writeln!(f, "~ {path}")?;
+            for difference in differences {
+                writeln!(
+                    f,
+                    "    {}: {} -> {}",
+                    difference.name,
+                    field(&difference.left),
+                    field(&difference.right)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a raw path is cleaned before being split into segments. Segments are
+/// percent-decoded one by one, so `%2F` never acts as a separator. Dot
+/// segments are resolved as in RFC 3986 unless rejected.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct PathOptions {
+    pub decode: bool,
+    pub collapse_slashes: bool,
+    pub reject_dot_segments: bool,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        PathOptions {
+            decode: false,
+            collapse_slashes: true,
+            reject_dot_segments: true,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,20 +676,63 @@ impl FromStr for Path {
     type Err = Error;
 
     fn from_str(path: &str) -> Result<Self, Self::Err> {
-        if !path.starts_with('/') {
+        Path::parse(path, PathOptions::default())
+    }
+}
+
+impl Path {
+    pub fn parse(path: &str, options: PathOptions) -> Result<Self, Error> {
+        let Some(raw) = path.strip_prefix('/') else {
             return Err(Error::FormatError(path.to_string()));
+        };
+        let raw: Vec<&str> = raw.split('/').collect();
+        let mut segments = Vec::new();
+        for (index, segment) in raw.iter().enumerate() {
+            if segment.is_empty() && options.collapse_slashes && index + 1 < raw.len() {
+                continue;
+            }
+            let segment = if options.decode {
+                percent_decode(segment).ok_or_else(|| Error::InvalidEncoding(path.to_string()))?
+            } else {
+                (*segment).to_string()
+            };
+            match segment.as_str() {
+                "." | ".." if options.reject_dot_segments => {
+                    return Err(Error::DotSegment(path.to_string()));
+                }
+                "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                _ => segments.push(segment),
+            }
+        }
+        if segments.is_empty() {
+            segments.push(String::new());
         }
-        let mut clean_path: Vec<char> = path.chars().collect();
-        clean_path.dedup();
-        let clean_path = clean_path.iter().skip(1).collect::<String>();
+        segments.reverse();
+        Ok(Path(segments))
+    }
 
-        Ok(Path(
-            clean_path
-                .split('/')
-                .map(std::string::ToString::to_string)
-                .rev()
-                .collect::<Vec<String>>(),
-        ))
+    /// Whether the path is `prefix` or lies under it. A `:param` segment of
+    /// `prefix` matches any segment.
+    #[must_use]
+    pub fn starts_with(&self, prefix: &Path) -> bool {
+        let mut segments = self.segments();
+        prefix.segments().all(|expected| {
+            segments
+                .next()
+                .is_some_and(|segment| segment == expected || expected.starts_with(':'))
+        })
+    }
+
+    /// The non-empty segments, from the root.
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .rev()
+            .map(String::as_str)
+            .filter(|segment| !segment.is_empty())
     }
 }
 
@@ -51,7 +741,49 @@ pub struct Hierarchy {
     name: String,
     attributes: Attributes,
     children: BTreeMap<String, Hierarchy>,
-    special_child_name: Option<String>,
+    #[serde(skip)]
+    stats: NodeStats,
+    #[serde(skip)]
+    numeric_coercion: bool,
+    #[serde(skip)]
+    combining: Combining,
+    #[serde(skip)]
+    algorithm: Algorithm,
+    #[serde(skip)]
+    path_options: PathOptions,
+    #[serde(skip)]
+    version: u64,
+    #[serde(skip)]
+    restricts: bool,
+    #[serde(skip)]
+    aliases: BTreeMap<String, String>,
+    #[serde(skip)]
+    scopes: Scopes,
+    #[serde(skip)]
+    constraints: Constraints,
+    #[serde(skip)]
+    program: Compiled,
+}
+
+/// Config settings that resources added or changed at runtime are checked
+/// against, as they were at load time.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Constraints {
+    schema: Schema,
+    allowed_operators: Option<Vec<String>>,
+    limits: Limits,
+    tenancy: Option<Tenancy>,
+}
+
+/// The node's access rule compiled to bytecode, rebuilt whenever the rule
+/// changes. Rules that fail to compile are evaluated as they are.
+#[derive(Debug, Clone, Default)]
+struct Compiled(Option<Program>);
+
+impl PartialEq for Compiled {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
 impl Hierarchy {
@@ -61,451 +793,4279 @@ impl Hierarchy {
             name,
             attributes,
             children: BTreeMap::new(),
-            special_child_name: None,
+            stats: NodeStats::default(),
+            numeric_coercion: false,
+            combining: Combining::Union,
+            algorithm: Algorithm::DenyOverrides,
+            path_options: PathOptions::default(),
+            version: 0,
+            restricts: false,
+            aliases: BTreeMap::new(),
+            scopes: Scopes::default(),
+            constraints: Constraints::default(),
+            program: Compiled::default(),
         }
     }
 
     pub fn is_allowed(
         &self,
         to: Operation,
-        on: &mut Path,
+        on: &Path,
         with: &Context,
     ) -> Result<bool, rule::Error> {
-        if let Some(access_rule) = &self.attributes.access_rule {
-            let permission: Permission = access_rule.eval(with)?.into();
-            if to.allowed_for(permission) {
-                return Ok(true);
-            }
-        }
+        self.is_allowed_with_budget(to, on, with, &mut Budget::default())
+    }
 
-        let Some(child_name) = on.0.pop() else {
+    /// Like [`Hierarchy::is_allowed`], but one of the space-separated OAuth2
+    /// `scopes` must also grant the operation on the path.
+    pub fn is_allowed_with_scopes(
+        &self,
+        scopes: &str,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+    ) -> Result<bool, rule::Error> {
+        if !self.scopes.permits(scopes, &to, on, self.path_options) {
             return Ok(false);
-        };
-
-        if let Some(child) = self.children.get("") {
-            if let Some(access_rule) = &child.attributes.access_rule {
-                let permission: Permission = access_rule.eval(with)?.into();
-                if to.allowed_for(permission) {
-                    return Ok(true);
-                }
-            }
         }
+        self.is_allowed(to, on, with)
+    }
 
-        let child_name = if let Some(spechial_child_name) = &self.special_child_name {
-            let attribute_value = with.get(spechial_child_name)?;
+    pub fn is_allowed_impersonating(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        impersonation: &Path,
+    ) -> Result<bool, rule::Error> {
+        Ok(self.is_allowed(to.clone(), impersonation, with)? && self.is_allowed(to, on, with)?)
+    }
 
-            if !match (attribute_value, &Rule::from_literal(child_name.as_str())?) {
-                (Rule::String(l), Rule::String(r)) => Ok(l == r),
-                (Rule::Float(l), Rule::Float(r)) => Ok(l == r),
-                (Rule::Integer(l), Rule::Integer(r)) => Ok(l == r),
-                (Rule::Bool(l), Rule::Bool(r)) => Ok(l == r),
-                (l, r) => Err(rule::Error::CannotCompare(l.clone(), r.clone())),
-            }? {
-                return Ok(false);
-            }
+    pub fn is_allowed_with_budget(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        budget: &mut Budget,
+    ) -> Result<bool, rule::Error> {
+        let mut environment = self.environment().with_budget(std::mem::take(budget));
+        let result = self.is_allowed_in(to, on, with, &mut environment);
+        *budget = std::mem::take(environment.budget());
+        result
+    }
 
-            spechial_child_name.clone()
-        } else {
-            child_name
-        };
+    pub fn is_allowed_with_resolver(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        resolver: &dyn AttributeResolver,
+    ) -> Result<bool, rule::Error> {
+        self.is_allowed_in(
+            to,
+            on,
+            with,
+            &mut self.environment().with_resolver(resolver),
+        )
+    }
 
-        if let Some(child) = self.children.get(&child_name) {
-            return child.is_allowed(to, on, with);
+    /// Resolves the attributes missing from `with` that rules along `on`
+    /// reference, then evaluates against the augmented context. Attributes the
+    /// engine supplies itself are never resolved.
+    #[cfg(feature = "async")]
+    pub async fn is_allowed_async<R: AsyncAttributeResolver>(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        resolver: &R,
+    ) -> Result<bool, rule::Error> {
+        let mut missing = Vec::new();
+        self.missing_attributes(&on.0, with, &mut missing)?;
+        let mut context = Cow::Borrowed(with);
+        for key in missing {
+            if let Some(value) = resolver.resolve(&key, &context).await? {
+                context.to_mut().insert(&key, value);
+            }
         }
-
-        Ok(false)
+        self.is_allowed(to, on, &context)
     }
 
-    fn insert(
-        &mut self,
-        full_path: &str,
-        path: &mut Path,
-        attributes: Attributes,
-    ) -> Result<(), Error> {
-        if path.0.is_empty() {
-            if self.attributes.access_rule.is_some() {
-                return Err(Error::DuplicateResource(full_path.to_string()));
+    #[cfg(feature = "async")]
+    fn missing_attributes(
+        &self,
+        on: &[String],
+        with: &Context,
+        missing: &mut Vec<String>,
+    ) -> Result<(), rule::Error> {
+        let mut collect = |node: &Hierarchy| {
+            let Some(access_rule) = &node.attributes.access_rule else {
+                return;
+            };
+            let context = node.attributes.resource_context(with);
+            for key in access_rule.variables() {
+                if context.get(&key).is_err()
+                    && !schema::is_builtin(&key)
+                    && !missing.contains(&key)
+                {
+                    missing.push(key);
+                }
             }
-            self.attributes = attributes;
+        };
+        collect(self);
+        let Some((child_name, on)) = on.split_last() else {
             return Ok(());
+        };
+        if let Some(child) = self.children.get("") {
+            collect(child);
         }
-
-        let mut child_name = path.0.pop().unwrap();
-
-        if child_name.starts_with(':') {
-            if self.special_child_name.is_some() {
-                return Err(Error::AmbiguousResource(
-                    full_path.to_string(),
-                    self.special_child_name.clone().unwrap(),
-                ));
-            }
-            self.special_child_name = Some(child_name[1..].to_string());
-            child_name = self.special_child_name.clone().unwrap();
+        if let Some((child, with)) = self.next_child(child_name, with)? {
+            child.missing_attributes(on, &with, missing)?;
         }
-
-        let child = self
-            .children
-            .entry(child_name.clone())
-            .or_insert_with(|| Hierarchy::new(child_name.clone(), Attributes::default()));
-
-        child.insert(full_path, path, attributes)?;
-
         Ok(())
     }
-}
 
-impl TryFrom<Config> for Hierarchy {
+    pub fn is_allowed_in(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        environment: &mut Environment,
+    ) -> Result<bool, rule::Error> {
+        Ok(self.grant(to, on, with, environment)?.is_some())
+    }
+
+    /// Like [`Hierarchy::is_allowed`], but reports which resource decided and
+    /// why, along with the granted permission and obligations.
+    pub fn evaluate(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+    ) -> Result<Decision, rule::Error> {
+        Ok(self
+            .grant(to, on, with, &mut self.environment())?
+            .decision())
+    }
+
+    /// Evaluates like [`Hierarchy::evaluate`] while recording every rule run
+    /// along the path and the intermediate values it computed.
+    #[must_use]
+    pub fn explain(&self, to: Operation, on: &Path, with: &Context) -> Explanation {
+        let mut grant = Grant {
+            steps: Some(Vec::new()),
+            ..Grant::default()
+        };
+        let result = self.walk(
+            to,
+            on,
+            with,
+            &mut self.environment().with_trace(),
+            &mut grant,
+        );
+        Explanation {
+            steps: grant.steps.take().unwrap_or_default(),
+            decision: result.map(|()| grant.decision()),
+        }
+    }
+
+    pub fn obligations(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+    ) -> Result<Option<Vec<Obligation>>, rule::Error> {
+        Ok(self
+            .grant(to, on, with, &mut self.environment())?
+            .rule
+            .map(|granted| Obligation::from_permission_rule(&granted)))
+    }
+
+    pub fn permission(&self, on: &Path, with: &Context) -> Result<Permission, rule::Error> {
+        let mut permission = Permission::default();
+        for operation in Operation::ALL {
+            if self.is_allowed(operation.clone(), on, with)? {
+                permission |= Permission::from(operation);
+            }
+        }
+        Ok(permission)
+    }
+
+    /// Every operation allowed on `on`, evaluating each rule along the path
+    /// once. Falls back to one walk per operation when a rule reads `$action`.
+    /// Like [`Hierarchy::is_allowed`], stops at nodes that cannot take a grant
+    /// back once every operation is granted.
+    pub fn list_permissions(&self, on: &Path, with: &Context) -> Result<Permission, rule::Error> {
+        let (mut granted, mut denied) = (None, Permission::NONE);
+        let environment = &mut self.environment();
+        if !self.collect_permissions(&on.0, with, environment, &mut granted, &mut denied)? {
+            return self.permission(on, with);
+        }
+        let granted = granted.unwrap_or_default();
+        Ok(match self.algorithm {
+            Algorithm::DenyOverrides => granted.difference(denied),
+            Algorithm::PermitOverrides => granted,
+        })
+    }
+
+    /// Returns `false` as soon as a rule depends on the operation being checked.
+    /// `granted` stays `None` until a node with a rule is reached.
+    fn collect_permissions(
+        &self,
+        on: &[String],
+        with: &Context,
+        environment: &mut Environment,
+        granted: &mut Option<Permission>,
+        denied: &mut Permission,
+    ) -> Result<bool, rule::Error> {
+        let settled =
+            |granted: &Option<Permission>| granted.is_some_and(|g| g.contains(Permission::ALL));
+        if settled(granted) && !self.restricts {
+            return Ok(true);
+        }
+        if !self.node_permissions(with, environment, granted, denied)? {
+            return Ok(false);
+        }
+        let Some((child_name, on)) = on.split_last() else {
+            return Ok(true);
+        };
+        if let Some(child) = self.children.get("") {
+            if (!settled(granted) || child.restricts)
+                && !child.node_permissions(with, environment, granted, denied)?
+            {
+                return Ok(false);
+            }
+        }
+        if settled(granted)
+            && !self
+                .children
+                .iter()
+                .any(|(name, child)| !name.is_empty() && child.restricts)
+        {
+            return Ok(true);
+        }
+        match self.next_child(child_name, with)? {
+            Some((child, with)) => {
+                child.collect_permissions(on, &with, environment, granted, denied)
+            }
+            None => Ok(true),
+        }
+    }
+
+    fn node_permissions(
+        &self,
+        with: &Context,
+        environment: &mut Environment,
+        granted: &mut Option<Permission>,
+        denied: &mut Permission,
+    ) -> Result<bool, rule::Error> {
+        let permission = if self.attributes.public {
+            Permission::ALL
+        } else if let Some(access_rule) = &self.attributes.access_rule {
+            if access_rule.variables().iter().any(|key| key == "action") {
+                return Ok(false);
+            }
+            let outcome = self
+                .eval_rule(access_rule, with, environment)
+                .and_then(|result| {
+                    Ok((permission::denied(&result), Permission::try_from(result)?))
+                });
+            self.stats.record(
+                &outcome
+                    .as_ref()
+                    .map(|(_, permission)| (*permission != Permission::NONE).then_some(())),
+            );
+            let (denies, permission) = outcome?;
+            *denied |= denies;
+            permission
+        } else {
+            return Ok(true);
+        };
+        *granted = Some(match (self.combining, *granted) {
+            (Combining::Union, Some(granted)) => granted | permission,
+            (Combining::FirstApplicable, Some(granted)) => granted,
+            _ => permission,
+        });
+        Ok(true)
+    }
+
+    /// Parses a request path with the normalization options of the config.
+    pub fn path(&self, path: &str) -> Result<Path, Error> {
+        Path::parse(path, self.path_options)
+    }
+
+    /// Parses an operation name, accepting the aliases declared in the config.
+    #[must_use]
+    pub fn operation(&self, name: &str) -> Option<Operation> {
+        Operation::from_str(self.aliases.get(name).map_or(name, String::as_str)).ok()
+    }
+
+    /// Fingerprint of the effective config, covering the global settings as
+    /// well as the resources. Computed when the hierarchy is built or changed.
+    #[must_use]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn list_filter(&self, on: &Path, with: &Context) -> Result<Option<Rule>, rule::Error> {
+        if !self.is_allowed(Operation::List, on, with)? {
+            return Ok(None);
+        }
+        let Some((node, with)) = self.node(&on.0, &with_action(&Operation::List, with))? else {
+            return Ok(Some(Rule::Bool(true)));
+        };
+        Ok(Some(node.attributes.list_filter_rule.as_ref().map_or(
+            Rule::Bool(true),
+            |filter| {
+                filter
+                    .bind(&node.attributes.resource_context(&with))
+                    .optimize()
+            },
+        )))
+    }
+
+    /// The node at `on`, with the context its rules are evaluated in once the
+    /// `:param` segments along the way are captured.
+    fn node(
+        &self,
+        on: &[String],
+        with: &Context,
+    ) -> Result<Option<(&Hierarchy, Context)>, rule::Error> {
+        let Some((child_name, on)) = on.split_last() else {
+            return Ok(Some((self, with.clone())));
+        };
+        match self.next_child(child_name, with)? {
+            Some((child, with)) => child.node(on, &with),
+            None => Ok(None),
+        }
+    }
+
+    fn environment<'a>(&self) -> Environment<'a> {
+        Environment::default().with_numeric_coercion(self.numeric_coercion)
+    }
+
+    fn grant(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        environment: &mut Environment,
+    ) -> Result<Grant, rule::Error> {
+        let mut grant = Grant::default();
+        self.walk(to, on, with, environment, &mut grant)?;
+        Ok(grant)
+    }
+
+    fn walk(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+        environment: &mut Environment,
+        grant: &mut Grant,
+    ) -> Result<(), rule::Error> {
+        let with = with_action(&to, with);
+        grant.algorithm = self.algorithm;
+        if self.decide(&to, &on.0, &with, environment, &mut Vec::new(), grant)? {
+            grant.rule = None;
+        } else if grant.is_some() {
+            grant.denied_by = None;
+        }
+        Ok(())
+    }
+
+    /// Walks `on` recording the granting rule and returns whether a rule along
+    /// the way denies `to`. Once granted, only subtrees holding rules that can
+    /// take the grant back are visited.
+    fn decide<'a>(
+        &'a self,
+        to: &Operation,
+        on: &[String],
+        with: &Context,
+        environment: &mut Environment,
+        trail: &mut Vec<&'a str>,
+        granted: &mut Grant,
+    ) -> Result<bool, rule::Error> {
+        if granted.is_some() && !self.restricts {
+            return Ok(false);
+        }
+        if self.evaluate_node(to, with, environment, trail, granted)? {
+            return Ok(true);
+        }
+
+        let Some((child_name, on)) = on.split_last() else {
+            return Ok(false);
+        };
+
+        if let Some(child) = self.children.get("") {
+            trail.push("");
+            if (granted.rule.is_none() || child.restricts)
+                && child.evaluate_node(to, with, environment, trail, granted)?
+            {
+                return Ok(true);
+            }
+            trail.pop();
+        }
+
+        if granted.is_some()
+            && !self
+                .children
+                .iter()
+                .any(|(name, child)| !name.is_empty() && child.restricts)
+        {
+            return Ok(false);
+        }
+        if let Some((child, with)) = self.next_child(child_name, with)? {
+            trail.push(&child.name);
+            return child.decide(to, on, &with, environment, trail, granted);
+        }
+
+        Ok(false)
+    }
+
+    /// Literal children take precedence over `:param` children, which are
+    /// tried in name order. A `:param` child is only followed when the context
+    /// binds its parameter to the path segment.
+    fn next_child<'a>(
+        &self,
+        child_name: &str,
+        with: &'a Context,
+    ) -> Result<Option<(&Hierarchy, Cow<'a, Context>)>, rule::Error> {
+        if !child_name.starts_with(':') {
+            if let Some(child) = self.children.get(child_name) {
+                return Ok(Some((child, Cow::Borrowed(with))));
+            }
+        }
+        let mut params = self
+            .children
+            .iter()
+            .filter_map(|(name, child)| Some((name.strip_prefix(':')?, child)))
+            .peekable();
+        if params.peek().is_none() {
+            return Ok(None);
+        }
+        let captured = Rule::from_literal(child_name)?;
+
+        let mut error = None;
+        for (param, child) in params {
+            let bound =
+                with.get(param)
+                    .and_then(|attribute_value| match (attribute_value, &captured) {
+                        (Rule::String(l), Rule::String(r)) => Ok(l == r),
+                        (Rule::Float(l), Rule::Float(r)) => Ok(l == r),
+                        (Rule::Integer(l), Rule::Integer(r)) => Ok(l == r),
+                        (Rule::Bool(l), Rule::Bool(r)) => Ok(l == r),
+                        (l, r) => Err(rule::Error::CannotCompare(l.clone(), r.clone())),
+                    });
+            match bound {
+                Ok(true) => {
+                    let mut with = with.clone();
+                    with.insert(&format!("path.{param}"), captured);
+                    return Ok(Some((child, Cow::Owned(with))));
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        error.map_or(Ok(None), Err)
+    }
+
+    pub fn verdicts(
+        &self,
+        to: Operation,
+        on: &Path,
+        with: &Context,
+    ) -> Result<Vec<Verdict>, rule::Error> {
+        let mut verdicts = Vec::new();
+        let with = with_action(&to, with);
+        self.collect_verdicts(
+            String::new(),
+            &to,
+            &on.0,
+            &with,
+            &mut self.environment(),
+            &mut verdicts,
+        )?;
+        Ok(verdicts)
+    }
+
+    fn collect_verdicts(
+        &self,
+        path: String,
+        to: &Operation,
+        on: &[String],
+        with: &Context,
+        environment: &mut Environment,
+        verdicts: &mut Vec<Verdict>,
+    ) -> Result<(), rule::Error> {
+        self.push_verdict(path.clone(), to, with, environment, verdicts);
+
+        let Some((child_name, on)) = on.split_last() else {
+            return Ok(());
+        };
+
+        if let Some(child) = self.children.get("") {
+            child.push_verdict(format!("{path}/"), to, with, environment, verdicts);
+        }
+
+        if let Some((child, with)) = self.next_child(child_name, with)? {
+            let path = format!("{path}/{}", child.name);
+            child.collect_verdicts(path, to, on, &with, environment, verdicts)?;
+        }
+        Ok(())
+    }
+
+    pub fn why_different(
+        &self,
+        to: Operation,
+        on: &Path,
+        left: &Context,
+        right: &Context,
+    ) -> Result<Vec<AttributeDifference>, rule::Error> {
+        let left_verdicts = self.verdicts(to.clone(), on, left)?;
+        let right_verdicts = self.verdicts(to, on, right)?;
+        let allowed = |verdicts: &[Verdict], resource: &str| {
+            verdicts
+                .iter()
+                .find(|verdict| verdict.resource == resource)
+                .map(|verdict| verdict.allowed.clone())
+        };
+        let resources = self.resources();
+        let mut names: Vec<String> = Vec::new();
+        for verdict in left_verdicts.iter().chain(&right_verdicts) {
+            let resource = verdict.resource.as_str();
+            if allowed(&left_verdicts, resource) == allowed(&right_verdicts, resource) {
+                continue;
+            }
+            let params = resource
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix(':'))
+                .map(ToString::to_string);
+            let variables = resources
+                .iter()
+                .find(|(path, _)| path == resource)
+                .and_then(|(_, attributes)| attributes.access_rule.as_ref())
+                .map(Rule::variables)
+                .unwrap_or_default();
+            for name in params.chain(variables) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                let (l, r) = (left.get(&name).ok(), right.get(&name).ok());
+                (l != r).then(|| AttributeDifference {
+                    left: l.cloned(),
+                    right: r.cloned(),
+                    name,
+                })
+            })
+            .collect())
+    }
+
+    fn push_verdict(
+        &self,
+        path: String,
+        to: &Operation,
+        with: &Context,
+        environment: &mut Environment,
+        verdicts: &mut Vec<Verdict>,
+    ) {
+        let allowed = if self.attributes.public {
+            Ok(true)
+        } else if let Some(access_rule) = &self.attributes.access_rule {
+            self.eval_rule(access_rule, with, environment)
+                .and_then(|granted| {
+                    let denied = to.allowed_for(permission::denied(&granted));
+                    Ok(!denied && to.allowed_for(Permission::try_from(granted)?))
+                })
+        } else {
+            return;
+        };
+        verdicts.push(Verdict {
+            resource: path,
+            allowed,
+        });
+    }
+
+    /// Records the node's rule in `granted` according to its combining mode.
+    /// Returns whether the rule explicitly denies `to` and the denial settles
+    /// the decision.
+    fn evaluate_node(
+        &self,
+        to: &Operation,
+        with: &Context,
+        environment: &mut Environment,
+        trail: &[&str],
+        granted: &mut Grant,
+    ) -> Result<bool, rule::Error> {
+        if self.attributes.public {
+            granted.record(trail, None, environment, &Ok(Rule::Tuple(vec![])));
+            self.combine(granted, trail, Some((Rule::Tuple(vec![]), Permission::ALL)));
+            return Ok(false);
+        }
+        let Some(access_rule) = &self.attributes.access_rule else {
+            return Ok(false);
+        };
+        let result = self.eval_rule(access_rule, with, environment);
+        granted.record(trail, Some(access_rule), environment, &result);
+        let denied = result
+            .as_ref()
+            .is_ok_and(|result| to.allowed_for(permission::denied(result)));
+        let outcome = result.and_then(|result| {
+            let permission = Permission::try_from(result.clone())?;
+            Ok((!denied && to.allowed_for(permission)).then_some((result, permission)))
+        });
+        self.stats.record(&outcome);
+        if denied {
+            granted.denied_by = Some(trail_path(trail));
+        } else {
+            self.combine(granted, trail, outcome?);
+        }
+        Ok(denied && granted.algorithm == Algorithm::DenyOverrides)
+    }
+
+    fn combine(&self, granted: &mut Grant, trail: &[&str], outcome: Option<(Rule, Permission)>) {
+        let applicable = std::mem::replace(&mut granted.applicable, true);
+        let replace = match self.combining {
+            Combining::Union => granted.rule.is_none() && outcome.is_some(),
+            Combining::FirstApplicable => !applicable,
+            Combining::ChildOverrides => true,
+        };
+        if !replace {
+            return;
+        }
+        if let Some((rule, permission)) = outcome {
+            granted.rule = Some(rule);
+            granted.permission = permission;
+            granted.path = trail_path(trail);
+        } else {
+            granted.rule = None;
+        }
+    }
+
+    fn eval_rule(
+        &self,
+        access_rule: &Rule,
+        with: &Context,
+        environment: &mut Environment,
+    ) -> Result<Rule, rule::Error> {
+        let context = self.attributes.resource_context(with);
+        match &self.program.0 {
+            Some(program) => program.eval_in(&context, environment),
+            None => access_rule.eval_in(&context, environment),
+        }
+    }
+
+    fn compile(&mut self) {
+        self.program = Compiled(
+            self.attributes
+                .access_rule
+                .as_ref()
+                .and_then(|rule| Program::compile(rule).ok()),
+        );
+        for child in self.children.values_mut() {
+            child.compile();
+        }
+    }
+
+    fn refresh_restricts(&mut self, default: Combining) -> bool {
+        self.combining = self.attributes.combining.unwrap_or(default);
+        self.restricts = self.attributes.denies()
+            || (self.combining == Combining::ChildOverrides
+                && (self.attributes.public || self.attributes.access_rule.is_some()));
+        for child in self.children.values_mut() {
+            self.restricts |= child.refresh_restricts(default);
+        }
+        self.restricts
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> Vec<(String, ResourceStats)> {
+        let mut stats = Vec::new();
+        self.collect_stats(String::new(), &mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, path: String, stats: &mut Vec<(String, ResourceStats)>) {
+        if self.attributes.access_rule.is_some() {
+            stats.push((path.clone(), self.stats.snapshot()));
+        }
+        for (name, child) in &self.children {
+            child.collect_stats(format!("{path}/{name}"), stats);
+        }
+    }
+
+    #[must_use]
+    pub fn resources(&self) -> Vec<(String, &Attributes)> {
+        let mut resources = Vec::new();
+        self.collect_resources(
+            String::new(),
+            &|attributes| attributes.access_rule.is_some() || attributes.public,
+            &mut resources,
+        );
+        resources
+    }
+
+    fn collect_resources<'a>(
+        &'a self,
+        path: String,
+        keep: &impl Fn(&Attributes) -> bool,
+        resources: &mut Vec<(String, &'a Attributes)>,
+    ) {
+        if keep(&self.attributes) {
+            resources.push((path.clone(), &self.attributes));
+        }
+        for (name, child) in &self.children {
+            child.collect_resources(format!("{path}/{name}"), keep, resources);
+        }
+    }
+
+    /// What changes when `self` is replaced by `other`.
+    #[must_use]
+    pub fn diff(&self, other: &Hierarchy) -> HierarchyDiff {
+        let left: BTreeMap<String, &Attributes> = self.resources().into_iter().collect();
+        let right: BTreeMap<String, &Attributes> = other.resources().into_iter().collect();
+        let mut diff = HierarchyDiff::default();
+        for (path, attributes) in &left {
+            match right.get(path) {
+                None => diff.removed.push(path.clone()),
+                Some(other) => {
+                    let differences = attributes.differences(other);
+                    if !differences.is_empty() {
+                        diff.changed.push((path.clone(), differences));
+                    }
+                }
+            }
+        }
+        diff.added = right
+            .into_keys()
+            .filter(|path| !left.contains_key(path))
+            .collect();
+        diff
+    }
+
+    #[must_use]
+    pub fn duplicate_rules(&self) -> Vec<(Rule, Vec<String>)> {
+        let mut groups: Vec<(Rule, Vec<String>)> = Vec::new();
+        for (path, attributes) in self.resources() {
+            let Some(access_rule) = &attributes.access_rule else {
+                continue;
+            };
+            match groups.iter_mut().find(|(rule, _)| rule == access_rule) {
+                Some((_, paths)) => paths.push(path),
+                None => groups.push((access_rule.clone(), vec![path])),
+            }
+        }
+        groups.retain(|(_, paths)| paths.len() > 1);
+        groups
+    }
+
+    #[must_use]
+    pub fn attribute_usage(&self, schema: &Schema) -> Vec<AttributeUsage> {
+        let mut usage: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (path, attributes) in self.resources() {
+            let mut names: Vec<String> = path
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix(':'))
+                .map(str::to_string)
+                .collect();
+            if let Some(access_rule) = &attributes.access_rule {
+                names.extend(access_rule.variables());
+            }
+            for name in names {
+                let resources = usage.entry(name).or_default();
+                if !resources.contains(&path) {
+                    resources.push(path.clone());
+                }
+            }
+        }
+        usage
+            .into_iter()
+            .map(|(name, resources)| AttributeUsage {
+                declared: schema.contains(&name),
+                name,
+                resources,
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn dead_attributes(&self, contexts: &[Context]) -> DeadAttributes {
+        let referenced: Vec<String> = self
+            .attribute_usage(&Schema::default())
+            .into_iter()
+            .map(|usage| usage.name)
+            .collect();
+        let mut supplied: BTreeMap<&str, usize> = BTreeMap::new();
+        for context in contexts {
+            for key in context.keys() {
+                *supplied.entry(key).or_default() += 1;
+            }
+        }
+        DeadAttributes {
+            unreferenced: supplied
+                .keys()
+                .filter(|key| !referenced.iter().any(|name| name == *key))
+                .map(|key| (*key).to_string())
+                .collect(),
+            usually_missing: referenced
+                .into_iter()
+                .filter(|name| {
+                    supplied.get(name.as_str()).copied().unwrap_or(0) * 2 < contexts.len()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn simplify(&mut self) {
+        self.map_rules(&Rule::simplify);
+        self.compile();
+    }
+
+    pub fn optimize(&mut self) {
+        self.map_rules(&Rule::optimize);
+        self.compile();
+    }
+
+    fn map_rules(&mut self, f: &impl Fn(&Rule) -> Rule) {
+        self.attributes.map_rules(f);
+        for child in self.children.values_mut() {
+            child.map_rules(f);
+        }
+    }
+
+    /// Adds a resource at runtime, with the same preparation and checks as
+    /// config loading.
+    pub fn insert_resource(&mut self, path: &str, mut attributes: Attributes) -> Result<(), Error> {
+        self.prepare_runtime(path, &mut attributes)?;
+        self.insert(path, &self.path(path)?.0, attributes)?;
+        self.refresh()
+    }
+
+    /// Replaces the access rule of a declared resource. Intermediate nodes the
+    /// config never declared are unknown.
+    pub fn update_rule(&mut self, path: &str, rule: Rule) -> Result<(), Error> {
+        let segments = self.path(path)?;
+        let node = self
+            .node_mut(&segments.0)
+            .filter(|node| node.attributes.access_rule.is_some() || node.attributes.public)
+            .ok_or(Error::UnknownResource(path.to_string()))?;
+        let mut attributes = Attributes {
+            access_rule: Some(rule),
+            ..node.attributes.clone()
+        };
+        self.prepare_runtime(path, &mut attributes)?;
+        if let Some(node) = self.node_mut(&segments.0) {
+            node.attributes = attributes;
+        }
+        self.refresh()
+    }
+
+    #[deprecated(note = "use `Hierarchy::update_rule`")]
+    pub fn set_rule(&mut self, path: &str, rule: Rule) -> Result<(), Error> {
+        self.update_rule(path, rule)
+    }
+
+    pub fn remove_resource(&mut self, path: &str) -> Result<(), Error> {
+        let segments = self.path(path)?;
+        if !self
+            .node_mut(&segments.0)
+            .is_some_and(|node| node.attributes.access_rule.is_some() || node.attributes.public)
+        {
+            return Err(Error::UnknownResource(path.to_string()));
+        }
+        self.remove(&segments.0);
+        self.refresh()
+    }
+
+    /// Recomputes what depends on the whole tree after it was built or changed.
+    fn refresh(&mut self) -> Result<(), Error> {
+        self.refresh_restricts(self.combining);
+        self.compile();
+        self.version = fingerprint(&Config::from(&*self))?;
+        Ok(())
+    }
+
+    fn prepare_runtime(&self, path: &str, attributes: &mut Attributes) -> Result<(), Error> {
+        let constraints = &self.constraints;
+        attributes.check_operators(path, constraints.allowed_operators.as_ref())?;
+        attributes.prepare(path, &self.aliases)?;
+        let mut resources: BTreeMap<String, Attributes> = self
+            .resources()
+            .into_iter()
+            .map(|(path, attributes)| (path, attributes.clone()))
+            .collect();
+        resources.insert(path.to_string(), attributes.clone());
+        if attributes.references_resources() {
+            let resources = resources.clone().into_iter().collect();
+            attributes.access_rule =
+                resolve_rule(path, &resources, &mut Vec::new(), &mut HashMap::new())?;
+        }
+        check_limits(
+            &constraints.limits,
+            resources
+                .iter()
+                .map(|(path, attributes)| (path.as_str(), attributes)),
+        )?;
+        attributes.enforce_tenancy(path, constraints.tenancy.as_ref(), self.path_options)?;
+        attributes.check(path, &constraints.schema)?;
+        for rule in [&attributes.access_rule, &attributes.list_filter_rule]
+            .into_iter()
+            .flatten()
+        {
+            rule.validate()
+                .map_err(|e| Error::InvalidRule(path.to_string(), e))?;
+        }
+        attributes.map_rules(&Rule::optimize);
+        Ok(())
+    }
+
+    /// Applies the difference between two configurations, rebuilding only the
+    /// resources that changed. Falls back to a full rebuild when a global setting
+    /// changed, limits are configured, or a resource references another resource.
+    pub fn reload(&mut self, previous: &Config, next: Config) -> Result<(), Error> {
+        let global_change = previous.attributes != next.attributes
+            || previous.allowed_operators != next.allowed_operators
+            || previous.limits != next.limits
+            || previous.numeric_coercion != next.numeric_coercion
+            || previous.settings != next.settings
+            || previous.paths != next.paths
+            || previous.tenancy != next.tenancy
+            || previous.aliases != next.aliases
+            || previous.scopes != next.scopes;
+        if global_change
+            || next.limits != Limits::default()
+            || next
+                .resources
+                .values()
+                .any(Attributes::references_resources)
+        {
+            *self = next.try_into()?;
+            return Ok(());
+        }
+        let mut declared = BTreeSet::new();
+        for path in next.resources.keys().collect::<BTreeSet<_>>() {
+            if !declared.insert(Path::parse(path, next.paths)?.0) {
+                return Err(Error::DuplicateResource(path.clone()));
+            }
+        }
+        let removed: Vec<&String> = previous
+            .resources
+            .keys()
+            .filter(|path| !next.resources.contains_key(*path))
+            .collect();
+        let mut changed = Vec::new();
+        for (path, attributes) in &next.resources {
+            if previous.resources.get(path) == Some(attributes) {
+                continue;
+            }
+            let mut attributes = attributes.clone();
+            attributes.load(path, &next)?;
+            attributes.map_rules(&Rule::optimize);
+            changed.push((path, attributes));
+        }
+        let apply = || -> Result<(), Error> {
+            for path in &removed {
+                self.remove(&self.path(path)?.0);
+            }
+            for (path, attributes) in changed {
+                let segments = self.path(path)?;
+                match self.node_mut(&segments.0) {
+                    Some(node) => node.attributes = attributes,
+                    None => self.insert(path, &segments.0, attributes)?,
+                }
+            }
+            Ok(())
+        };
+        apply().inspect_err(|_| {
+            if let Ok(restored) = previous.clone().try_into() {
+                *self = restored;
+            }
+        })?;
+        self.refresh()
+    }
+
+    fn remove(&mut self, path: &[String]) {
+        let Some((child_name, path)) = path.split_last() else {
+            self.attributes = Attributes::default();
+            return;
+        };
+        let Some(child) = self.children.get_mut(child_name) else {
+            return;
+        };
+        child.remove(path);
+        if child.attributes == Attributes::default() && child.children.is_empty() {
+            self.children.remove(child_name);
+        }
+    }
+
+    fn node_mut(&mut self, path: &[String]) -> Option<&mut Hierarchy> {
+        let Some((child_name, path)) = path.split_last() else {
+            return Some(self);
+        };
+        self.children.get_mut(child_name)?.node_mut(path)
+    }
+
+    fn insert(
+        &mut self,
+        full_path: &str,
+        path: &[String],
+        attributes: Attributes,
+    ) -> Result<(), Error> {
+        let Some((child_name, path)) = path.split_last() else {
+            if self.attributes.access_rule.is_some() || self.attributes.public {
+                return Err(Error::DuplicateResource(full_path.to_string()));
+            }
+            self.attributes = attributes;
+            return Ok(());
+        };
+
+        let child = self
+            .children
+            .entry(child_name.clone())
+            .or_insert_with(|| Hierarchy::new(child_name.clone(), Attributes::default()));
+
+        child.insert(full_path, path, attributes)?;
+
+        Ok(())
+    }
+}
+
+fn check_limits<'a, I>(limits: &Limits, resources: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = (&'a str, &'a Attributes)>,
+    I::IntoIter: Clone,
+{
+    let resources = resources.into_iter();
+    let exceeds = |limit: Option<usize>, value: usize| limit.is_some_and(|limit| value > limit);
+    if exceeds(limits.max_resources, resources.clone().count()) {
+        return Err(Error::LimitExceeded(
+            "resources".to_string(),
+            limits.max_resources.unwrap_or_default(),
+        ));
+    }
+    let rule_nodes = resources
+        .clone()
+        .flat_map(|(_, attributes)| [&attributes.access_rule, &attributes.list_filter_rule])
+        .flatten()
+        .map(Rule::node_count)
+        .sum();
+    if exceeds(limits.max_rule_nodes, rule_nodes) {
+        return Err(Error::LimitExceeded(
+            "rule nodes".to_string(),
+            limits.max_rule_nodes.unwrap_or_default(),
+        ));
+    }
+    let mut params: Vec<&str> = resources
+        .flat_map(|(path, _)| path.split('/').filter(|segment| segment.starts_with(':')))
+        .collect();
+    params.sort_unstable();
+    params.dedup();
+    if exceeds(limits.max_params, params.len()) {
+        return Err(Error::LimitExceeded(
+            "params".to_string(),
+            limits.max_params.unwrap_or_default(),
+        ));
+    }
+    Ok(())
+}
+
+/// Exposes the operation being checked to rules as `$action`, overriding any
+/// caller-supplied value.
+fn with_action(to: &Operation, with: &Context) -> Context {
+    let mut context = with.clone();
+    context.insert("action", Rule::String(to.to_string()));
+    context
+}
+
+fn resolve_rule(
+    path: &str,
+    resources: &HashMap<String, Attributes>,
+    visiting: &mut Vec<String>,
+    resolved: &mut HashMap<String, Rule>,
+) -> Result<Option<Rule>, Error> {
+    if let Some(rule) = resolved.get(path) {
+        return Ok(Some(rule.clone()));
+    }
+    if let Some(start) = visiting.iter().position(|p| p == path) {
+        let mut cycle = visiting[start..].to_vec();
+        cycle.push(path.to_string());
+        return Err(Error::CyclicResourceRule(cycle));
+    }
+    let Some(access_rule) = resources
+        .get(path)
+        .and_then(|attributes| attributes.access_rule.as_ref())
+    else {
+        return Ok(None);
+    };
+    visiting.push(path.to_string());
+    let mut failure = None;
+    let rule = access_rule.resolve(&mut |reference| match resolve_rule(
+        reference, resources, visiting, resolved,
+    ) {
+        Ok(Some(rule)) => Ok(rule),
+        Ok(None) => {
+            failure = Some(Error::UnknownResource(reference.to_string()));
+            Err(rule::Error::UnresolvedResourceRule(reference.to_string()))
+        }
+        Err(e) => {
+            failure = Some(e);
+            Err(rule::Error::UnresolvedResourceRule(reference.to_string()))
+        }
+    });
+    visiting.pop();
+    let rule = match (rule, failure) {
+        (_, Some(e)) => return Err(e),
+        (Err(e), None) => return Err(Error::InvalidRule(path.to_string(), e)),
+        (Ok(rule), None) => rule,
+    };
+    resolved.insert(path.to_string(), rule.clone());
+    Ok(Some(rule))
+}
+
+impl TryFrom<Config> for Hierarchy {
     type Error = Error;
 
-    fn try_from(config: Config) -> Result<Self, Error> {
-        let mut root = Hierarchy::new(String::new(), Attributes::default());
+    fn try_from(mut config: Config) -> Result<Self, Error> {
+        if let Some((alias, canonical)) = config.aliases.iter().find(|(alias, canonical)| {
+            *alias == "all"
+                || Operation::from_str(alias).is_ok()
+                || (*canonical != "all" && Operation::from_str(canonical).is_err())
+        }) {
+            return Err(Error::InvalidAlias(alias.clone(), canonical.clone()));
+        }
+        config.scopes.validate(config.paths)?;
+        for (path, attributes) in &mut config.resources {
+            attributes.check_operators(path, config.allowed_operators.as_ref())?;
+            attributes.prepare(path, &config.aliases)?;
+        }
+        let mut root = Hierarchy::new(String::new(), Attributes::default());
+        root.numeric_coercion = config.numeric_coercion;
+        root.combining = config.settings.combining;
+        root.algorithm = config.settings.algorithm;
+        root.path_options = config.paths;
+        root.aliases = config.aliases.clone();
+        root.scopes = std::mem::take(&mut config.scopes);
+        root.constraints = Constraints {
+            schema: config.attributes.clone(),
+            allowed_operators: config.allowed_operators.clone(),
+            limits: config.limits.clone(),
+            tenancy: config.tenancy.clone(),
+        };
+
+        check_limits(
+            &config.limits,
+            config
+                .resources
+                .iter()
+                .map(|(path, attributes)| (path.as_str(), attributes)),
+        )?;
+
+        let mut resolved = HashMap::new();
+        for path in config.resources.keys() {
+            resolve_rule(path, &config.resources, &mut Vec::new(), &mut resolved)?;
+        }
+
+        for (path, mut attributes) in std::mem::take(&mut config.resources) {
+            if let Some(access_rule) = resolved.remove(&path) {
+                attributes.access_rule = Some(access_rule);
+            }
+            attributes.enforce_tenancy(&path, config.tenancy.as_ref(), config.paths)?;
+            attributes.check(&path, &config.attributes)?;
+            root.insert(path.as_str(), &root.path(path.as_str())?.0, attributes)?;
+        }
+        root.optimize();
+        root.refresh()?;
+        Ok(root)
+    }
+}
+
+/// Rebuilds a config from the hierarchy, with every rule already merged,
+/// aliased and resolved. Tenant checks are already in the rules, so tenancy
+/// is exported without `inject`, and operators the engine added while merging
+/// are allowed. Presets and SLOs are not kept and are left at their defaults.
+impl From<&Hierarchy> for Config {
+    fn from(hierarchy: &Hierarchy) -> Self {
+        let mut resources = Vec::new();
+        hierarchy.collect_resources(
+            String::new(),
+            &|attributes| *attributes != Attributes::default(),
+            &mut resources,
+        );
+        let constraints = &hierarchy.constraints;
+        let allowed_operators = constraints.allowed_operators.clone().map(|mut allowed| {
+            for attributes in resources.iter().map(|(_, attributes)| attributes) {
+                for operator in [&attributes.access_rule, &attributes.list_filter_rule]
+                    .into_iter()
+                    .flatten()
+                    .flat_map(Rule::operators)
+                {
+                    if !allowed.contains(&operator) {
+                        allowed.push(operator);
+                    }
+                }
+            }
+            allowed
+        });
+        Config {
+            resources: resources
+                .into_iter()
+                .map(|(path, attributes)| (path, attributes.clone()))
+                .collect(),
+            attributes: constraints.schema.clone(),
+            presets: Presets::default(),
+            allowed_operators,
+            limits: constraints.limits.clone(),
+            slo: Slo::default(),
+            numeric_coercion: hierarchy.numeric_coercion,
+            settings: Settings {
+                combining: hierarchy.combining,
+                algorithm: hierarchy.algorithm,
+            },
+            paths: hierarchy.path_options,
+            tenancy: constraints.tenancy.clone().map(|tenancy| Tenancy {
+                inject: false,
+                ..tenancy
+            }),
+            aliases: hierarchy.aliases.clone(),
+            scopes: hierarchy.scopes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::rule::Rule;
+    use std::str::FromStr;
+    use toml;
+
+    #[test]
+    fn test_resource_path_from_str_ok() {
+        let left: Result<Path, Error> = Path::from_str("/");
+        let right: Result<Path, Error> = Ok(Path(vec![String::new()]));
+        assert_eq!(left, right);
+
+        let left: Result<Path, Error> = Path::from_str("/test1/test2");
+        let right: Result<Path, Error> = Ok(Path(vec!["test2".to_string(), "test1".to_string()]));
+        assert_eq!(left, right);
+
+        let left: Result<Path, Error> = Path::from_str("/test1/test2/");
+        let right: Result<Path, Error> = Ok(Path(vec![
+            String::new(),
+            "test2".to_string(),
+            "test1".to_string(),
+        ]));
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn test_resource_path_parse_ok() {
+        let segments = |path: &str, options: PathOptions| {
+            let mut segments = Path::parse(path, options).unwrap().0;
+            segments.reverse();
+            segments
+        };
+        let default = PathOptions::default();
+        assert_eq!(segments("/aabb//cc", default), vec!["aabb", "cc"]);
+        assert_eq!(segments("//", default), vec![""]);
+        assert_eq!(segments("/a%2Fb", default), vec!["a%2Fb"]);
+        let options = PathOptions {
+            decode: true,
+            collapse_slashes: false,
+            reject_dot_segments: false,
+        };
+        assert_eq!(segments("/a%2Fb/%C3%A9", options), vec!["a/b", "é"]);
+        assert_eq!(segments("/a//b", options), vec!["a", "", "b"]);
+        assert_eq!(segments("/a/./b/../c/%2E%2E", options), vec!["a"]);
+        assert_eq!(segments("/../a", options), vec!["a"]);
+
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [paths]
+            decode = true
+            collapse_slashes = false
+
+            [scopes]
+            "files:write" = {operations = "update", prefixes = ["/files/c%20d"]}
+
+            [resources]
+            "/files/a b" = {access_rule = "(list read)"}
+            "/files/c%20d" = {access_rule = "(list update)"}
+            "/files//e" = {access_rule = "(list delete)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        rh.insert_resource(
+            "/files/f%20g",
+            Attributes {
+                access_rule: Some(Rule::from_str("(list create)").unwrap()),
+                ..Attributes::default()
+            },
+        )
+        .unwrap();
+        let context = Context::from_str("").unwrap();
+        for (operation, path, expected) in [
+            (Operation::Read, "/files/a%20b", true),
+            (Operation::Update, "/files/c d", true),
+            (Operation::Delete, "/files//e", true),
+            (Operation::Delete, "/files/e", false),
+            (Operation::Create, "/files/f g", true),
+        ] {
+            assert_eq!(
+                rh.is_allowed(operation.clone(), &rh.path(path).unwrap(), &context),
+                Ok(expected),
+                "{operation} {path}"
+            );
+        }
+        assert_eq!(
+            rh.is_allowed_with_scopes(
+                "files:write",
+                Operation::Update,
+                &rh.path("/files/c%20d").unwrap(),
+                &context
+            ),
+            Ok(true)
+        );
+        assert_eq!(rh.remove_resource("/files/f g"), Ok(()));
+    }
+
+    #[test]
+    fn test_resource_path_parse_err() {
+        let options = PathOptions {
+            decode: true,
+            ..PathOptions::default()
+        };
+        assert_eq!(
+            Path::parse("/a/../b", options),
+            Err(Error::DotSegment(String::from("/a/../b")))
+        );
+        assert_eq!(
+            Path::parse("/a/%2e", options),
+            Err(Error::DotSegment(String::from("/a/%2e")))
+        );
+        for path in ["/a%2", "/a%zz", "/a%+1", "/a%FF"] {
+            assert_eq!(
+                Path::parse(path, options),
+                Err(Error::InvalidEncoding(path.to_string()))
+            );
+        }
+        assert_eq!(
+            Path::parse("a", options),
+            Err(Error::FormatError(String::from("a")))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_insert_err() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/:a" = {access_rule = "()"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.insert(
+                "/:a",
+                &Path::from_str("/:a").unwrap().0,
+                Attributes {
+                    access_rule: Some(Rule::Tuple(vec![])),
+                    ..Attributes::default()
+                }
+            ),
+            Err(Error::DuplicateResource("/:a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_ok() {
+        let left: Result<Hierarchy, Error> = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "()", description = "Root"}
+        "#,
+        )
+        .unwrap()
+        .try_into();
+        let right: Result<Hierarchy, Error> = Ok(Hierarchy {
+            name: String::new(),
+            attributes: Attributes {
+                access_rule: None,
+                description: None,
+                public: false,
+                rules: BTreeMap::new(),
+                list_filter_rule: None,
+                properties: BTreeMap::new(),
+                operations: None,
+                combining: None,
+            },
+            children: BTreeMap::from([(
+                String::new(),
+                Hierarchy {
+                    name: String::new(),
+                    attributes: Attributes {
+                        access_rule: Some(Rule::from_str("()").unwrap()),
+                        description: Some("Root".to_string()),
+                        public: false,
+                        rules: BTreeMap::new(),
+                        list_filter_rule: None,
+                        properties: BTreeMap::new(),
+                        operations: None,
+                        combining: None,
+                    },
+                    children: BTreeMap::new(),
+                    stats: NodeStats::default(),
+                    numeric_coercion: false,
+                    combining: Combining::Union,
+                    algorithm: Algorithm::DenyOverrides,
+                    path_options: PathOptions::default(),
+                    version: 0,
+                    restricts: false,
+                    aliases: BTreeMap::new(),
+                    scopes: Scopes::default(),
+                    constraints: Constraints::default(),
+                    program: Compiled::default(),
+                },
+            )]),
+            stats: NodeStats::default(),
+            numeric_coercion: false,
+            combining: Combining::Union,
+            algorithm: Algorithm::DenyOverrides,
+            path_options: PathOptions::default(),
+            version: left.as_ref().map_or(0, Hierarchy::version),
+            restricts: false,
+            aliases: BTreeMap::new(),
+            scopes: Scopes::default(),
+            constraints: Constraints::default(),
+            program: Compiled::default(),
+        });
+        assert_eq!(left, right);
+
+        let left: Result<Hierarchy, Error> = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/test" = {access_rule = """(
+                list create
+            )""", description = "Root"}
+            "/test/" = {access_rule = "(list read)", description = "Root"}
+        "#,
+        )
+        .unwrap()
+        .try_into();
+        let right: Result<Hierarchy, Error> = Ok(Hierarchy {
+            name: String::new(),
+            attributes: Attributes {
+                access_rule: None,
+                description: None,
+                public: false,
+                rules: BTreeMap::new(),
+                list_filter_rule: None,
+                properties: BTreeMap::new(),
+                operations: None,
+                combining: None,
+            },
+            children: BTreeMap::from([(
+                "test".to_string(),
+                Hierarchy {
+                    name: "test".to_string(),
+                    attributes: Attributes {
+                        access_rule: Some(Rule::from_str("(list create)").unwrap()),
+                        description: Some("Root".to_string()),
+                        public: false,
+                        rules: BTreeMap::new(),
+                        list_filter_rule: None,
+                        properties: BTreeMap::new(),
+                        operations: None,
+                        combining: None,
+                    },
+                    children: BTreeMap::from([(
+                        String::new(),
+                        Hierarchy {
+                            name: String::new(),
+                            attributes: Attributes {
+                                access_rule: Some(Rule::from_str("(list read)").unwrap()),
+                                description: Some("Root".to_string()),
+                                public: false,
+                                rules: BTreeMap::new(),
+                                list_filter_rule: None,
+                                properties: BTreeMap::new(),
+                                operations: None,
+                                combining: None,
+                            },
+                            children: BTreeMap::new(),
+                            stats: NodeStats::default(),
+                            numeric_coercion: false,
+                            combining: Combining::Union,
+                            algorithm: Algorithm::DenyOverrides,
+                            path_options: PathOptions::default(),
+                            version: 0,
+                            restricts: false,
+                            aliases: BTreeMap::new(),
+                            scopes: Scopes::default(),
+                            constraints: Constraints::default(),
+                            program: Compiled::default(),
+                        },
+                    )]),
+                    stats: NodeStats::default(),
+                    numeric_coercion: false,
+                    combining: Combining::Union,
+                    algorithm: Algorithm::DenyOverrides,
+                    path_options: PathOptions::default(),
+                    version: 0,
+                    restricts: false,
+                    aliases: BTreeMap::new(),
+                    scopes: Scopes::default(),
+                    constraints: Constraints::default(),
+                    program: Compiled::default(),
+                },
+            )]),
+            stats: NodeStats::default(),
+            numeric_coercion: false,
+            combining: Combining::Union,
+            algorithm: Algorithm::DenyOverrides,
+            path_options: PathOptions::default(),
+            version: left.as_ref().map_or(0, Hierarchy::version),
+            restricts: false,
+            aliases: BTreeMap::new(),
+            scopes: Scopes::default(),
+            constraints: Constraints::default(),
+            program: Compiled::default(),
+        });
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // Sometimes it's ok for tests to be really f-cking long
+    fn test_is_allowed_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list)", description = "Root"}
+            "/test1" = {access_rule = "(list create)", description = "Root"}
+            "/test1/" = {access_rule = "(list read)", description = "Root"}
+            "/test2/test3" = {access_rule = "(list read)", description = "Root"}
+            "/all" = {access_rule = "(list all)", description = "Root"}
+            "/private/:user_id" = {access_rule = "(list all)", description = "Root"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        assert!(!rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test1").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test1").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test1/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test1/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test1/test").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test1/test").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test2").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test2").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test2/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test2/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test2/test").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test2/test").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Create,
+                &Path::from_str("/test2/test3").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test2/test3").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test2/test3/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/test2/test3/test").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Delete,
+                &Path::from_str("/all").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Delete,
+                &Path::from_str("/all/").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Delete,
+                &Path::from_str("/all/1").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+        assert!(rh
+            .is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/1").unwrap(),
+                &Context::from_str("user_id:1").unwrap()
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/2").unwrap(),
+                &Context::from_str("user_id:1").unwrap()
+            )
+            .unwrap());
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // Sometimes it's ok for tests to be really f-cking long
+    fn test_is_allowed_err() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list)", description = "Root"}
+            "/test1" = {access_rule = "(list create)", description = "Root"}
+            "/test1/" = {access_rule = "(list read)", description = "Root"}
+            "/test2/test3" = {access_rule = "(list read)", description = "Root"}
+            "/all" = {access_rule = "(list all)", description = "Root"}
+            "/private/:user_id" = {access_rule = "(list all)", description = "Root"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/").unwrap(),
+                &Context::from_str("user_id:1").unwrap()
+            ),
+            Err(rule::Error::CannotCompare(
+                Rule::Integer(1),
+                Rule::String("".to_string())
+            ))
+        );
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/").unwrap(),
+                &Context::from_str("").unwrap()
+            ),
+            Err(rule::Error::KeyNotInContext("user_id".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_simplify_ok() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (or true (eq $a b)) (list read) (list))"}
+            "/test" = {access_rule = "(if (and true (eq $a b)) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        rh.simplify();
+        assert_eq!(
+            rh.children[""].attributes.access_rule,
+            Some(Rule::from_str("(list read)").unwrap())
+        );
+        assert_eq!(
+            rh.children["test"].attributes.access_rule,
+            Some(Rule::from_str("(if (eq $a b) (list all) (list))").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_duplicate_rules_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list read)"}
+            "/test1" = {access_rule = "(list   create)"}
+            "/test1/" = {access_rule = "(list read)"}
+            "/private/:user_id" = {access_rule = "(list create)"}
+            "/other" = {access_rule = "(list delete)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.duplicate_rules(),
+            vec![
+                (
+                    Rule::from_str("(list read)").unwrap(),
+                    vec!["/".to_string(), "/test1/".to_string()]
+                ),
+                (
+                    Rule::from_str("(list create)").unwrap(),
+                    vec!["/private/:user_id".to_string(), "/test1".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_attribute_usage_ok() {
+        let config = toml::from_str::<Config>(
+            r#"
+            [attributes]
+            role = "string"
+
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/test" = {access_rule = "(list read)"}
+            "/private/:user_id" = {access_rule = "(if (in $role (list user admin)) (list all) (list))"}
+        "#,
+        )
+        .unwrap();
+        let schema = config.attributes.clone();
+        let rh: Hierarchy = config.try_into().unwrap();
+        assert_eq!(
+            rh.attribute_usage(&schema),
+            vec![
+                AttributeUsage {
+                    name: "role".to_string(),
+                    resources: vec!["/".to_string(), "/private/:user_id".to_string()],
+                    declared: true,
+                },
+                AttributeUsage {
+                    name: "user_id".to_string(),
+                    resources: vec!["/private/:user_id".to_string()],
+                    declared: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_typecheck_ok() {
+        let rh: Result<Hierarchy, Error> = toml::from_str::<Config>(
+            r#"
+            [attributes]
+            age = "integer"
+
+            [resources]
+            "/" = {access_rule = "(if (and (eq $action read) (between $env.hour 8 18)) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into();
+        assert!(rh.is_ok());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_typecheck_err() {
+        let rh: Result<Hierarchy, Error> = toml::from_str::<Config>(
+            r#"
+            [attributes]
+            age = "integer"
+
+            [resources]
+            "/" = {access_rule = "(if (eq $age adult) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into();
+        assert_eq!(
+            rh,
+            Err(Error::TypeError(
+                "/".to_string(),
+                rule::Error::TypeMismatch(Rule::from_str("(eq $age adult)").unwrap())
+            ))
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_dead_attributes_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/private/:user_id" = {access_rule = "(if (eq $team core) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.dead_attributes(&[
+                Context::from_str("role:admin,user_id:1,email:a@b.c").unwrap(),
+                Context::from_str("role:user,user_id:2").unwrap(),
+                Context::from_str("role:user,team:core").unwrap(),
+            ]),
+            DeadAttributes {
+                unreferenced: vec!["email".to_string()],
+                usually_missing: vec!["team".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_allowed_with_budget_err() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list)"}
+            "/test" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.is_allowed_with_budget(
+                Operation::Read,
+                &Path::from_str("/test").unwrap(),
+                &Context::from_str("").unwrap(),
+                &mut Budget::default().with_nodes(3)
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            rh.is_allowed_with_budget(
+                Operation::Read,
+                &Path::from_str("/test").unwrap(),
+                &Context::from_str("").unwrap(),
+                &mut Budget::default().with_nodes(2)
+            ),
+            Err(rule::Error::BudgetExhausted)
+        );
+    }
+
+    #[test]
+    fn test_is_allowed_public_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list)"}
+            "/health" = {public = true, description = "Health check"}
+            "/login/" = {public = true}
+            "/private" = {access_rule = "(list)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for path in ["/health", "/health/live", "/login/"] {
+            assert!(rh
+                .is_allowed(
+                    Operation::Read,
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str("").unwrap()
+                )
+                .unwrap());
+        }
+        assert!(!rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/private").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_optimize_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (and (eq 1 1) (eq $role admin)) (list all) (list))"}
+            "/test" = {access_rule = "(if (eq (concat a b) ab) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.children[""].attributes.access_rule,
+            Some(Rule::from_str("(if (eq $role admin) (list all) (list))").unwrap())
+        );
+        assert_eq!(
+            rh.children["test"].attributes.access_rule,
+            Some(Rule::from_str("(list read)").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_is_allowed_impersonating_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/impersonate/:on_behalf_of.user_id" = {access_rule = "(if (eq $subject.role support) (list all) (list))"}
+            "/private/:user_id" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let support = Context::impersonation(
+            &Context::from_str("user_id:1,role:support").unwrap(),
+            &Context::from_str("user_id:2,role:user").unwrap(),
+        );
+        assert!(rh
+            .is_allowed_impersonating(
+                Operation::Read,
+                &Path::from_str("/private/2").unwrap(),
+                &support,
+                &Path::from_str("/impersonate/2").unwrap(),
+            )
+            .unwrap());
+        assert!(!rh
+            .is_allowed_impersonating(
+                Operation::Read,
+                &Path::from_str("/private/2").unwrap(),
+                &support,
+                &Path::from_str("/impersonate/3").unwrap(),
+            )
+            .unwrap());
+        let user = Context::impersonation(
+            &Context::from_str("user_id:3,role:user").unwrap(),
+            &Context::from_str("user_id:2,role:user").unwrap(),
+        );
+        assert!(!rh
+            .is_allowed_impersonating(
+                Operation::Read,
+                &Path::from_str("/private/2").unwrap(),
+                &user,
+                &Path::from_str("/impersonate/2").unwrap(),
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn test_obligations_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list read)"}
+            "/admin" = {access_rule = "(if (eq $mfa yes) (list all) (list read update (require mfa)))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.obligations(
+                Operation::Update,
+                &Path::from_str("/admin").unwrap(),
+                &Context::from_str("mfa:no").unwrap()
+            ),
+            Ok(Some(vec![Obligation {
+                name: String::from("mfa"),
+                arguments: vec![],
+            }]))
+        );
+        assert_eq!(
+            rh.obligations(
+                Operation::Update,
+                &Path::from_str("/admin").unwrap(),
+                &Context::from_str("mfa:yes").unwrap()
+            ),
+            Ok(Some(vec![]))
+        );
+        assert_eq!(
+            rh.obligations(
+                Operation::Delete,
+                &Path::from_str("/admin").unwrap(),
+                &Context::from_str("mfa:no").unwrap()
+            ),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn test_hierarchy_stats_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list)"}
+            "/private/:user_id" = {access_rule = "(if (eq $role admin) (list all) (list read))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (operation, context) in [
+            (Operation::Delete, "user_id:1,role:admin"),
+            (Operation::Delete, "user_id:1,role:user"),
+            (Operation::Read, "user_id:1,role:user"),
+            (Operation::Read, "user_id:1,role:1"),
+        ] {
+            let _ = rh.is_allowed(
+                operation,
+                &Path::from_str("/private/1").unwrap(),
+                &Context::from_str(context).unwrap(),
+            );
+        }
+        assert_eq!(
+            rh.stats(),
+            vec![
+                (
+                    "/".to_string(),
+                    ResourceStats {
+                        allowed: 0,
+                        denied: 4,
+                        errors: 0,
+                    }
+                ),
+                (
+                    "/private/:user_id".to_string(),
+                    ResourceStats {
+                        allowed: 2,
+                        denied: 1,
+                        errors: 1,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn test_hierarchy_list_permissions_stats_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/private/:user_id" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for context in ["user_id:1,role:admin", "user_id:1,role:user"] {
+            let _ = rh.list_permissions(
+                &Path::from_str("/private/1").unwrap(),
+                &Context::from_str(context).unwrap(),
+            );
+        }
+        assert_eq!(
+            rh.stats(),
+            vec![(
+                "/private/:user_id".to_string(),
+                ResourceStats {
+                    allowed: 1,
+                    denied: 1,
+                    errors: 0,
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_verdicts_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/private/" = {access_rule = "(list read)"}
+            "/private/:user_id" = {access_rule = "(if (eq $role $level) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.verdicts(
+                Operation::Read,
+                &Path::from_str("/private/1").unwrap(),
+                &Context::from_str("user_id:1,role:admin,level:1").unwrap()
+            ),
+            Ok(vec![
+                Verdict {
+                    resource: "/".to_string(),
+                    allowed: Ok(true),
+                },
+                Verdict {
+                    resource: "/private/".to_string(),
+                    allowed: Ok(true),
+                },
+                Verdict {
+                    resource: "/private/:user_id".to_string(),
+                    allowed: Err(rule::Error::CannotCompare(
+                        Rule::String("admin".to_string()),
+                        Rule::Integer(1)
+                    )),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_verdicts_match_decisions_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            numeric_coercion = true
+            [resources]
+            "/docs" = {access_rule = "(concat (list read) (deny read))"}
+            "/levels" = {access_rule = "(if (eq $level 1.0) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let context = Context::from_str("level:1").unwrap();
+        for (path, expected) in [("/docs", false), ("/levels", true)] {
+            let path = Path::from_str(path).unwrap();
+            assert_eq!(
+                rh.is_allowed(Operation::Read, &path, &context),
+                Ok(expected)
+            );
+            assert_eq!(
+                rh.verdicts(Operation::Read, &path, &context)
+                    .unwrap()
+                    .into_iter()
+                    .map(|verdict| verdict.allowed)
+                    .collect::<Vec<_>>(),
+                vec![Ok(expected)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_allowed_operators_err() {
+        let rh: Result<Hierarchy, Error> = toml::from_str::<Config>(
+            r#"
+            allowed_operators = ["if", "eq", "list"]
+
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/exports" = {access_rule = "(if (in (usage exports $user_id today) (list 0 1)) (list create) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into();
+        assert_eq!(
+            rh,
+            Err(Error::ForbiddenOperator(
+                "/exports".to_string(),
+                "in".to_string()
+            ))
+        );
+        let rh: Result<Hierarchy, Error> = toml::from_str::<Config>(
+            r#"
+            allowed_operators = ["if", "eq", "list"]
+
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into();
+        assert!(rh.is_ok());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_host_function_err() {
+        let config = |allowed_operators: &str| {
+            toml::from_str::<Config>(&format!(
+                r#"
+                allowed_operators = {allowed_operators}
+
+                [resources]
+                "/" = {{access_rule = "(if (in admin (remote-roles $user_id)) (list all) (list))"}}
+                "/users" = {{access_rule = "(if (is-blacklisted $user_id) (list) (list read))"}}
+            "#
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            Hierarchy::try_from(config(r#"["if", "in", "list", "is-blacklisted"]"#)),
+            Err(Error::ForbiddenOperator(
+                "/".to_string(),
+                "remote-roles".to_string()
+            ))
+        );
+        assert!(Hierarchy::try_from(config(
+            r#"["if", "in", "list", "remote-roles", "is-blacklisted"]"#
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_memoization_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/a" = {access_rule = "(if (eq $resource.owner bob) (list update) (list))", properties = {owner = "alice"}}
+            "/a/b" = {access_rule = "(if (eq $resource.owner bob) (list update) (list))", properties = {owner = "bob"}}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let path = Path::from_str("/a/b").unwrap();
+        let context = Context::from_str("").unwrap();
+        assert_eq!(rh.is_allowed(Operation::Update, &path, &context), Ok(true));
+        assert_eq!(
+            rh.is_allowed_in(
+                Operation::Update,
+                &path,
+                &context,
+                &mut Environment::default().with_memoization()
+            ),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_compiled_program_ok() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/docs" = {access_rule = "(if (is-blacklisted $user_id) (list) (list read))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let mut functions = crate::function::Functions::new();
+        functions.register("is-blacklisted", |arguments| {
+            Ok(Rule::Bool(arguments == [Rule::Integer(13)]))
+        });
+        let path = Path::from_str("/docs").unwrap();
+        let is_allowed = |rh: &Hierarchy, context: &str| {
+            rh.is_allowed_in(
+                Operation::Read,
+                &path,
+                &Context::from_str(context).unwrap(),
+                &mut Environment::default().with_functions(&functions),
+            )
+        };
+        let program = |rh: &Hierarchy| {
+            rh.children["docs"]
+                .program
+                .0
+                .as_ref()
+                .map(|program| program.rule().clone())
+        };
+        assert_eq!(program(&rh), rh.children["docs"].attributes.access_rule);
+        assert_eq!(is_allowed(&rh, "user_id:13"), Ok(false));
+        assert_eq!(is_allowed(&rh, "user_id:7"), Ok(true));
+        rh.update_rule(
+            "/docs",
+            Rule::from_str("(if (eq $user_id 13) (list read) (list))").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(program(&rh), rh.children["docs"].attributes.access_rule);
+        assert_eq!(is_allowed(&rh, "user_id:13"), Ok(true));
+        rh.optimize();
+        assert_eq!(program(&rh), rh.children["docs"].attributes.access_rule);
+        assert_eq!(is_allowed(&rh, "user_id:7"), Ok(false));
+    }
+
+    #[test]
+    fn test_hierarchy_version_ok() {
+        let hierarchy = |settings: &str| -> Hierarchy {
+            toml::from_str::<Config>(&format!(
+                r#"
+                {settings}
+
+                [resources]
+                "/docs" = {{access_rule = "(list read)"}}
+                "/docs/a" = {{access_rule = "(list update)"}}
+            "#
+            ))
+            .unwrap()
+            .try_into()
+            .unwrap()
+        };
+        let mut rh = hierarchy("");
+        assert_eq!(rh.version(), hierarchy("").version());
+        for settings in [
+            "numeric_coercion = true",
+            "[settings]\nalgorithm = \"permit-overrides\"",
+            r#"aliases = {view = "read"}"#,
+        ] {
+            assert_ne!(rh.version(), hierarchy(settings).version(), "{settings}");
+        }
+        let version = rh.version();
+        rh.update_rule("/docs/a", Rule::from_str("(list delete)").unwrap())
+            .unwrap();
+        assert_ne!(rh.version(), version);
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_variable_operands_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/adults" = {access_rule = "(if (between $age 18 65) (list read) (list))"}
+            "/shards" = {access_rule = "(if (eq (mod $user_id 10) 3) (list read) (list))"}
+            "/ops" = {access_rule = "(perms read $op)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (path, context, expected) in [
+            ("/adults", "age:30", Ok(true)),
+            ("/adults", "age:70", Ok(false)),
+            (
+                "/adults",
+                "age:old",
+                Err(rule::Error::InvalidBetweenStatement(
+                    Rule::from_str("(between $age 18 65)").unwrap(),
+                )),
+            ),
+            ("/shards", "user_id:13", Ok(true)),
+            ("/shards", "user_id:14", Ok(false)),
+            (
+                "/shards",
+                "user_id:alice",
+                Err(rule::Error::InvalidArithmeticStatement(
+                    Rule::from_str("(mod $user_id 10)").unwrap(),
+                )),
+            ),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    Operation::Read,
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str(context).unwrap()
+                ),
+                expected,
+                "{path} {context}"
+            );
+        }
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Update,
+                &Path::from_str("/ops").unwrap(),
+                &Context::from_str("op:update").unwrap()
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Update,
+                &Path::from_str("/ops").unwrap(),
+                &Context::from_str("op:bogus").unwrap()
+            ),
+            Err(rule::Error::InvalidPermission(Rule::String(String::from(
+                "bogus"
+            ))))
+        );
+        assert!(toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/adults" = {access_rule = "(if (between 18 $age) (list read) (list))"}
+        "#,
+        )
+        .is_err());
+        assert_eq!(
+            Hierarchy::try_from(
+                toml::from_str::<Config>(
+                    r#"
+                    [resources]
+                    "/ops" = {access_rule = "(perms readd $op)"}
+                "#,
+                )
+                .unwrap()
+            ),
+            Err(Error::InvalidRule(
+                String::from("/ops"),
+                rule::Error::InvalidPermission(Rule::String(String::from("readd")))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_allowed_operators_rules_ok() {
+        let config = |rule: &str| {
+            toml::from_str::<Config>(&format!(
+                r#"
+                allowed_operators = ["list", "eq"]
+
+                [resources]
+                "/d" = {{access_rule = "(list read)", rules = {{update = "{rule}"}}}}
+            "#
+            ))
+            .unwrap()
+        };
+        let rh = Hierarchy::try_from(config("(eq $role editor)")).unwrap();
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Update,
+                &Path::from_str("/d").unwrap(),
+                &Context::from_str("role:editor").unwrap()
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            Hierarchy::try_from(config("(in $role (list editor))")),
+            Err(Error::ForbiddenOperator("/d".to_string(), "in".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_from_config_limits_err() {
+        let config = |limits: &str| {
+            toml::from_str::<Config>(&format!(
+                r#"
+                [limits]
+                {limits}
+
+                [resources]
+                "/" = {{access_rule = "(if (eq $role admin) (list all) (list))"}}
+                "/private/:user_id" = {{access_rule = "(list read)"}}
+                "/teams/:team_id/" = {{access_rule = "(list read)"}}
+            "#
+            ))
+            .unwrap()
+        };
+        assert!(Hierarchy::try_from(config(
+            "max_resources = 3\nmax_rule_nodes = 17\nmax_params = 2"
+        ))
+        .is_ok());
+        assert_eq!(
+            Hierarchy::try_from(config("max_resources = 2")),
+            Err(Error::LimitExceeded("resources".to_string(), 2))
+        );
+        assert_eq!(
+            Hierarchy::try_from(config("max_rule_nodes = 16")),
+            Err(Error::LimitExceeded("rule nodes".to_string(), 16))
+        );
+        assert_eq!(
+            Hierarchy::try_from(config("max_params = 1")),
+            Err(Error::LimitExceeded("params".to_string(), 1))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_numeric_coercion_ok() {
+        let hierarchy = |numeric_coercion: bool| {
+            Hierarchy::try_from(
+                toml::from_str::<Config>(&format!(
+                    r#"
+                    numeric_coercion = {numeric_coercion}
+
+                    [resources]
+                    "/levels" = {{access_rule = "(if (in $level (list 1 2)) (list read) (list))"}}
+                "#
+                ))
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        let context = Context::from_str("level:2.0").unwrap();
+        assert_eq!(
+            hierarchy(false).is_allowed(
+                Operation::Read,
+                &Path::from_str("/levels").unwrap(),
+                &context
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            hierarchy(true).is_allowed(
+                Operation::Read,
+                &Path::from_str("/levels").unwrap(),
+                &context
+            ),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_update_rule_ok() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/private/:user_id" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/1").unwrap(),
+                &Context::from_str("user_id:1").unwrap()
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            rh.update_rule(
+                "/private/:user_id",
+                Rule::from_str("(list read delete)").unwrap()
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/1").unwrap(),
+                &Context::from_str("user_id:1").unwrap()
+            ),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_update_rule_err() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/private/:user_id" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.update_rule("/private/:id", Rule::from_str("(list read)").unwrap()),
+            Err(Error::UnknownResource("/private/:id".to_string()))
+        );
+        #[allow(deprecated)]
+        let result = rh.set_rule("/private/:id", Rule::from_str("(list read)").unwrap());
+        assert_eq!(
+            result,
+            Err(Error::UnknownResource("/private/:id".to_string()))
+        );
+        assert_eq!(
+            rh.update_rule("/public", Rule::from_str("(list read)").unwrap()),
+            Err(Error::UnknownResource("/public".to_string()))
+        );
+        assert_eq!(
+            rh.update_rule("/private", Rule::from_str("(list all)").unwrap()),
+            Err(Error::UnknownResource("/private".to_string()))
+        );
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Delete,
+                &Path::from_str("/private/9").unwrap(),
+                &Context::from_str("user_id:1").unwrap()
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            rh.update_rule(
+                "/private/:user_id",
+                Rule::from_str("(if 1 (list) (list))").unwrap()
+            ),
+            Err(Error::InvalidRule(
+                "/private/:user_id".to_string(),
+                rule::Error::InvalidIfCondition(Rule::Integer(1))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_why_different_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/private/:user_id" = {access_rule = "(if (eq $team $owner_team) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let path = Path::from_str("/private/1").unwrap();
+        let alice =
+            Context::from_str("user_id:1,role:user,team:red,owner_team:red,name:alice").unwrap();
+        let bob =
+            Context::from_str("user_id:2,role:user,team:red,owner_team:red,name:bob").unwrap();
+        assert_eq!(
+            rh.why_different(Operation::Read, &path, &alice, &bob),
+            Ok(vec![AttributeDifference {
+                name: "user_id".to_string(),
+                left: Some(Rule::Integer(1)),
+                right: Some(Rule::Integer(2)),
+            }])
+        );
+        let carol =
+            Context::from_str("user_id:1,role:user,team:blue,owner_team:red,name:carol").unwrap();
+        assert_eq!(
+            rh.why_different(Operation::Read, &path, &alice, &carol),
+            Ok(vec![AttributeDifference {
+                name: "team".to_string(),
+                left: Some(Rule::String("red".to_string())),
+                right: Some(Rule::String("blue".to_string())),
+            }])
+        );
+        assert_eq!(
+            rh.why_different(Operation::Read, &path, &alice, &alice),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_resource_rule_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/shared/base" = {access_rule = "(if (eq $role admin) (list read list) (list))"}
+            "/reports" = {access_rule = "(concat (resource-rule /shared/base) (list create))"}
+            "/reports/exports" = {access_rule = "(if (eq $role admin) (concat (resource-rule /reports) (list delete)) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let context = Context::from_str("role:admin").unwrap();
+        for (path, operation, expected) in [
+            ("/reports", Operation::List, true),
+            ("/reports", Operation::Create, true),
+            ("/reports/exports", Operation::Delete, true),
+            ("/shared/base", Operation::Create, false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(operation, &Path::from_str(path).unwrap(), &context),
+                Ok(expected),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_resource_rule_err() {
+        let hierarchy = |resources: &str| {
+            Hierarchy::try_from(
+                toml::from_str::<Config>(&format!("[resources]\n{resources}")).unwrap(),
+            )
+        };
+        assert_eq!(
+            hierarchy(r#""/a" = {access_rule = "(resource-rule /a)"}"#),
+            Err(Error::CyclicResourceRule(vec![
+                "/a".to_string(),
+                "/a".to_string()
+            ]))
+        );
+        let cyclic = hierarchy(
+            r#"
+            "/a" = {access_rule = "(resource-rule /b)"}
+            "/b" = {access_rule = "(concat (list read) (resource-rule /c))"}
+            "/c" = {access_rule = "(if true (resource-rule /a) (list))"}
+        "#,
+        )
+        .unwrap_err();
+        let Error::CyclicResourceRule(cycle) = &cyclic else {
+            panic!("unexpected error {cyclic:?}");
+        };
+        assert_eq!(cycle.len(), 4);
+        assert_eq!(cycle.first(), cycle.last());
+        assert!(["/a", "/b", "/c"]
+            .iter()
+            .all(|p| cycle.contains(&p.to_string())));
+        assert!(cyclic.to_string().contains(" -> "));
+        assert_eq!(
+            hierarchy(r#""/a" = {access_rule = "(resource-rule /missing)"}"#),
+            Err(Error::UnknownResource("/missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_per_operation_rules_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources."/documents"]
+            access_rule = "(list read)"
+            rules.delete = "(eq $role admin)"
+            rules.update = "(or (eq $role admin) (eq $role editor))"
+
+            [resources."/archive"]
+            rules.read = "(eq $role auditor)"
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (path, operation, role, expected) in [
+            ("/documents", Operation::Read, "guest", true),
+            ("/documents", Operation::Update, "guest", false),
+            ("/documents", Operation::Update, "editor", true),
+            ("/documents", Operation::Delete, "editor", false),
+            ("/documents", Operation::Delete, "admin", true),
+            ("/archive", Operation::Read, "guest", false),
+            ("/archive", Operation::Read, "auditor", true),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str(&format!("role:{role}")).unwrap()
+                ),
+                Ok(expected),
+                "{path} {operation} {role}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_per_operation_rules_err() {
+        assert_eq!(
+            Hierarchy::try_from(
+                toml::from_str::<Config>(
+                    r#"
+                    [resources."/documents"]
+                    rules.erase = "(eq $role admin)"
+                "#,
+                )
+                .unwrap()
+            ),
+            Err(Error::UnknownOperation(
+                "/documents".to_string(),
+                "erase".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_list_filter_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources."/documents"]
+            access_rule = "(if (in $role (list user admin)) (list list read) (list))"
+            list_filter_rule = "(or (eq $role admin) (eq $document.owner $user))"
+
+            [resources."/teams"]
+            access_rule = "(list list)"
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let list_filter = |path: &str, context: &str| {
+            rh.list_filter(
+                &Path::from_str(path).unwrap(),
+                &Context::from_str(context).unwrap(),
+            )
+        };
+        assert_eq!(
+            list_filter("/documents", "role:user,user:alice"),
+            Ok(Some(Rule::from_str("(eq $document.owner alice)").unwrap()))
+        );
+        assert_eq!(
+            list_filter("/documents", "role:admin,user:bob"),
+            Ok(Some(Rule::Bool(true)))
+        );
+        assert_eq!(list_filter("/documents", "role:guest,user:bob"), Ok(None));
+        assert_eq!(list_filter("/teams", ""), Ok(Some(Rule::Bool(true))));
+    }
+
+    #[test]
+    fn test_resource_hierarchy_path_params_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            tenancy = {prefix = "/orgs/:org_id", attribute = "subject.org", inject = true}
+            [attributes]
+            org_id = "integer"
+            "subject.org" = "integer"
+
+            [resources."/orgs/:org_id/items"]
+            access_rule = "(if (eq $path.org_id 7) (list list) (list))"
+            list_filter_rule = "(or (eq $item.org $path.org_id) (eq $item.region $resource.region))"
+            properties = {region = "eu"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.list_filter(
+                &Path::from_str("/orgs/7/items").unwrap(),
+                &Context::from_str("org_id:7,subject.org:7").unwrap(),
+            ),
+            Ok(Some(
+                Rule::from_str("(or (eq $item.org 7) (eq $item.region eu))").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_resource_attributes_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [attributes]
+            user_id = "integer"
+
+            [resources."/projects/apollo"]
+            description = "Apollo"
+            properties = { owner = 7, tags = ["space"] }
+            access_rule = "(if (or (eq $resource.owner $user_id) (eq $resource.description Gemini)) (list all) (if (contains-any $resource.tags space) (list read) (list)))"
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (operation, user_id, expected) in [
+            (Operation::Read, 1, true),
+            (Operation::Delete, 1, false),
+            (Operation::Delete, 7, true),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str("/projects/apollo").unwrap(),
+                    &Context::builder()
+                        .int("user_id", user_id)
+                        .int("resource.owner", user_id)
+                        .build()
+                ),
+                Ok(expected),
+                "{operation} {user_id}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_resource_properties_literal_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources."/docs"]
+            properties = { label = "Team docs", query = "(list all)", level = 3 }
+            access_rule = "(if (and (eq $resource.label \"Team docs\") (eq $resource.query \"(list all)\")) (list read) (list))"
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let config = Config::from(&rh);
+        let properties = &config.resources["/docs"].properties;
+        assert_eq!(properties["label"], Rule::String("Team docs".to_string()));
+        assert_eq!(properties["query"], Rule::String("(list all)".to_string()));
+        assert_eq!(properties["level"], Rule::Integer(3));
+        assert_eq!(
+            rh.is_allowed(
+                Operation::Read,
+                &Path::from_str("/docs").unwrap(),
+                &Context::default()
+            ),
+            Ok(true)
+        );
+        assert!(toml::from_str::<Config>(
+            r#"
+            [resources."/docs"]
+            properties = { owner = { name = "alice" } }
+        "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_path_captures_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources."/teams/:team_id"]
+            access_rule = "(if (contains-any $managed_teams $path.team_id) (list all) (list read))"
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (team_id, operation, expected) in [
+            (3, Operation::Delete, true),
+            (4, Operation::Delete, false),
+            (4, Operation::Read, true),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str(&format!("/teams/{team_id}")).unwrap(),
+                    &Context::from_str(&format!("team_id:{team_id},managed_teams:[1|3]")).unwrap()
+                ),
+                Ok(expected),
+                "{team_id} {operation}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_reload_ok() {
+        let previous = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list read)"}
+            "/reports" = {access_rule = "(list read)"}
+            "/private/:user_id" = {access_rule = "(list read)"}
+            "/archive/2020" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap();
+        let next = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list read)"}
+            "/reports" = {access_rule = "(if (eq $role admin) (list all) (list read))"}
+            "/private/:id" = {access_rule = "(list read update)"}
+            "/teams" = {access_rule = "(list list)", rules = {read = "true"}}
+        "#,
+        )
+        .unwrap();
+        let mut rh: Hierarchy = previous.clone().try_into().unwrap();
+        rh.reload(&previous, next.clone()).unwrap();
+        assert_eq!(rh, Hierarchy::try_from(next).unwrap());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_reload_err() {
+        let previous = toml::from_str::<Config>(
+            r#"
+            allowed_operators = ["list", "if", "eq"]
+            [resources]
+            "/reports" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap();
+        let mut next = previous.clone();
+        next.resources.insert(
+            String::from("/reports"),
+            Attributes {
+                access_rule: Some(
+                    Rule::from_str("(if (ne $role admin) (list) (list read))").unwrap(),
+                ),
+                ..Attributes::default()
+            },
+        );
+        let mut rh: Hierarchy = previous.clone().try_into().unwrap();
+        assert_eq!(
+            rh.reload(&previous, next),
+            Err(Error::ForbiddenOperator(
+                "/reports".to_string(),
+                "ne".to_string()
+            ))
+        );
+        assert_eq!(rh, Hierarchy::try_from(previous.clone()).unwrap());
+
+        let mut next = previous.clone();
+        next.resources.insert(
+            String::from("//reports"),
+            next.resources["/reports"].clone(),
+        );
+        assert!(matches!(
+            Hierarchy::try_from(next.clone()),
+            Err(Error::DuplicateResource(_))
+        ));
+        assert_eq!(
+            rh.reload(&previous, next),
+            Err(Error::DuplicateResource("/reports".to_string()))
+        );
+        assert_eq!(rh, Hierarchy::try_from(previous).unwrap());
+    }
+
+    #[test]
+    fn test_resource_hierarchy_tenancy_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant", inject = true}
+            [resources]
+            "/t/:tenant_id" = {access_rule = "(list read)"}
+            "/t/:tenant_id/admin" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (context, operation, expected) in [
+            ("tenant_id:acme,subject.tenant:acme", Operation::Read, true),
+            ("tenant_id:acme,subject.tenant:evil", Operation::Read, false),
+            (
+                "tenant_id:acme,subject.tenant:evil,role:admin",
+                Operation::Delete,
+                false,
+            ),
+            (
+                "tenant_id:acme,subject.tenant:acme,role:admin",
+                Operation::Delete,
+                true,
+            ),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str("/t/acme/admin").unwrap(),
+                    &Context::from_str(context).unwrap()
+                ),
+                Ok(expected),
+                "{context} {operation}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_tenancy_check_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant"}
+            [resources]
+            "/t/:tenant_id" = {access_rule = "(if (and (eq $role admin) (eq $subject.tenant $path.tenant_id)) (list all) (list))"}
+            "/t/:tenant_id/docs" = {access_rule = "(list)", rules = {read = "(eq $path.tenant_id $subject.tenant)"}}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (context, expected) in [
+            ("tenant_id:acme,subject.tenant:acme,role:user", true),
+            ("tenant_id:acme,subject.tenant:evil,role:admin", false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    Operation::Read,
+                    &Path::from_str("/t/acme/docs").unwrap(),
+                    &Context::from_str(context).unwrap()
+                ),
+                Ok(expected),
+                "{context}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_tenancy_err() {
+        for (policy, expected) in [
+            (
+                r#"
+                tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant"}
+                [resources]
+                "/t/:tenant_id" = {access_rule = "(if (eq $subject.tenant $path.tenant_id) (list read) (list))"}
+                "/t/:tenant_id/docs" = {access_rule = "(list read)"}
+            "#,
+                Error::MissingTenantCheck(
+                    "/t/:tenant_id/docs".to_string(),
+                    "subject.tenant".to_string(),
+                ),
+            ),
+            (
+                r#"
+                tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant"}
+                [resources]
+                "/t/:tenant_id" = {access_rule = "(if (or (eq $role admin) (eq $subject.tenant $subject.tenant)) (list all) (list))"}
+            "#,
+                Error::MissingTenantCheck(
+                    "/t/:tenant_id".to_string(),
+                    "subject.tenant".to_string(),
+                ),
+            ),
+            (
+                r#"
+                tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant", inject = true}
+                [resources]
+                "/t/:tenant_id/status" = {public = true}
+            "#,
+                Error::MissingTenantCheck(
+                    "/t/:tenant_id/status".to_string(),
+                    "subject.tenant".to_string(),
+                ),
+            ),
+            (
+                r#"
+                tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant"}
+                [resources]
+                "//t/:tenant_id/secret" = {access_rule = "(list all)"}
+            "#,
+                Error::MissingTenantCheck(
+                    "//t/:tenant_id/secret".to_string(),
+                    "subject.tenant".to_string(),
+                ),
+            ),
+            (
+                r#"
+                tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant"}
+                [resources]
+                "/t/:tid/secret" = {access_rule = "(list all)"}
+            "#,
+                Error::MissingTenantCheck(
+                    "/t/:tid/secret".to_string(),
+                    "subject.tenant".to_string(),
+                ),
+            ),
+            (
+                r#"
+                tenancy = {prefix = "/t", attribute = "subject.tenant"}
+                [resources]
+                "/t/docs" = {access_rule = "(list read)"}
+            "#,
+                Error::InvalidTenancy("/t".to_string()),
+            ),
+        ] {
+            assert_eq!(
+                Hierarchy::try_from(toml::from_str::<Config>(policy).unwrap()),
+                Err(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_deny_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (perms list (if (eq $role contractor) (deny delete update) (require audit))))"}
+            "/projects" = {access_rule = "(list read update delete)"}
+            "/projects/secret" = {access_rule = "(if (eq $role admin) (deny read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (path, role, operation, expected) in [
+            ("/projects", "user", Operation::Delete, true),
+            ("/projects", "contractor", Operation::Delete, false),
+            ("/projects", "contractor", Operation::Read, true),
+            ("/projects/secret", "contractor", Operation::Update, false),
+            ("/projects/secret", "admin", Operation::Read, false),
+            ("/projects/secret", "admin", Operation::Update, true),
+            ("/projects/secret", "user", Operation::Read, true),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str(&format!("role:{role}")).unwrap()
+                ),
+                Ok(expected),
+                "{path} {role} {operation}"
+            );
+        }
+        assert_eq!(
+            rh.obligations(
+                Operation::List,
+                &Path::from_str("/projects").unwrap(),
+                &Context::from_str("role:user").unwrap()
+            ),
+            Ok(Some(vec![Obligation {
+                name: String::from("audit"),
+                arguments: vec![],
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_deny_concat_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list all)"}
+            "/docs" = {access_rule = "(deny delete)", rules = {update = "(eq $role editor)"}}
+            "/files" = {access_rule = "(concat (list read) (deny delete))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (path, operation, expected) in [
+            ("/docs", Operation::Update, true),
+            ("/docs", Operation::Delete, false),
+            ("/files", Operation::Read, true),
+            ("/files", Operation::Delete, false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str("role:editor").unwrap()
+                ),
+                Ok(expected),
+                "{path} {operation}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_action_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/documents" = {access_rule = "(if (eq $action delete) (if (eq $role owner) (list all) (list)) (list all))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (role, operation, expected) in [
+            ("user", "read", true),
+            ("user", "update", true),
+            ("user", "delete", false),
+            ("owner", "delete", true),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    Operation::from_str(operation).unwrap(),
+                    &Path::from_str("/documents").unwrap(),
+                    &Context::from_str(&format!("role:{role},action:read")).unwrap()
+                ),
+                Ok(expected),
+                "{role} {operation}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_aliases_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            aliases = {view = "read", remove = "delete", everything = "all"}
+            [resources]
+            "/documents" = {access_rule = "(if (eq $role admin) (list everything) (if (in $tab (list view edit)) (list view) (list)))"}
+            "/archive" = {access_rule = "(list)", rules = {remove = "(eq $role owner)"}}
+            "/reports" = {access_rule = "(if (eq $role auditor) (perms view (deny remove)) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let remove = rh.operation("remove").unwrap();
+        assert_eq!(remove, Operation::Delete);
+        assert_eq!(rh.operation("read"), Some(Operation::Read));
+        assert_eq!(rh.operation("destroy"), None);
+        for (path, context, operation, expected) in [
+            ("/documents", "role:user,tab:view", "view", true),
+            ("/documents", "role:user,tab:edit", "view", true),
+            ("/documents", "role:user,tab:none", "view", false),
+            ("/documents", "role:admin,tab:none", "remove", true),
+            ("/archive", "role:owner", "remove", true),
+            ("/archive", "role:user", "remove", false),
+            ("/reports", "role:auditor", "view", true),
+            ("/reports", "role:auditor", "remove", false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    rh.operation(operation).unwrap(),
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str(context).unwrap()
+                ),
+                Ok(expected),
+                "{path} {context} {operation}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_aliases_err() {
+        for (aliases, alias, canonical) in [
+            (r#"{read = "list"}"#, "read", "list"),
+            (r#"{view = "see"}"#, "view", "see"),
+        ] {
+            assert_eq!(
+                Hierarchy::try_from(
+                    toml::from_str::<Config>(&format!("aliases = {aliases}\n[resources]")).unwrap()
+                ),
+                Err(Error::InvalidAlias(
+                    alias.to_string(),
+                    canonical.to_string()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_declared_operations_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            aliases = {view = "read"}
+            [resources]
+            "/jobs" = {access_rule = "(if (eq $role admin) (list all) (list view))", operations = ["view", "custom.0"]}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (operation, role, expected) in [
+            (Operation::Read, "user", true),
+            (Operation::Read, "admin", true),
+            (Operation::Delete, "admin", false),
+            (Operation::Create, "admin", false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation,
+                    &Path::from_str("/jobs").unwrap(),
+                    &Context::from_str(&format!("role:{role}")).unwrap()
+                ),
+                Ok(expected)
+            );
+        }
+        assert_eq!(
+            rh.list_permissions(
+                &Path::from_str("/jobs").unwrap(),
+                &Context::from_str("role:admin").unwrap()
+            ),
+            Ok(Permission::READ | Permission::custom(0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_resource_hierarchy_declared_operations_err() {
+        for (resource, expected) in [
+            (
+                r#"{access_rule = "(if (eq $role admin) (list read craete) (list))", operations = ["read", "create"]}"#,
+                Error::UndeclaredOperation(String::from("/jobs"), String::from("craete")),
+            ),
+            (
+                r#"{access_rule = "(if (eq $role admin) (list read craete) (list))"}"#,
+                Error::UnknownOperation(String::from("/jobs"), String::from("craete")),
+            ),
+            (
+                r#"{access_rule = "(list read)", operations = ["read", "execute"]}"#,
+                Error::UnknownOperation(String::from("/jobs"), String::from("execute")),
+            ),
+        ] {
+            assert_eq!(
+                Hierarchy::try_from(
+                    toml::from_str::<Config>(&format!("[resources]\n\"/jobs\" = {resource}"))
+                        .unwrap()
+                ),
+                Err(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_param_siblings_ok() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/orgs/public" = {access_rule = "(list read)"}
+            "/orgs/:org_id" = {access_rule = "(list read)"}
+            "/orgs/:org_id/users/:user_id" = {access_rule = "(if (eq $path.user_id $me) (list update) (list))"}
+            "/orgs/:slug" = {access_rule = "(if (eq $path.slug $team) (list list) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        rh.insert_resource(
+            "/orgs/:slug/teams",
+            Attributes {
+                access_rule: Some(Rule::from_str("(list create)").unwrap()),
+                ..Attributes::default()
+            },
+        )
+        .unwrap();
+        for (operation, path, context, expected) in [
+            (Operation::Read, "/orgs/public", "slug:docs", true),
+            (Operation::Read, "/orgs/acme", "org_id:acme,slug:docs", true),
+            (
+                Operation::List,
+                "/orgs/acme",
+                "org_id:acme,slug:docs",
+                false,
+            ),
+            (
+                Operation::List,
+                "/orgs/docs",
+                "org_id:acme,slug:docs,team:docs",
+                true,
+            ),
+            (Operation::Read, "/orgs/docs", "slug:docs", false),
+            (Operation::Create, "/orgs/docs/teams", "slug:docs", true),
+            (
+                Operation::Create,
+                "/orgs/acme/teams",
+                "org_id:acme,slug:docs",
+                false,
+            ),
+            (
+                Operation::Update,
+                "/orgs/acme/users/1",
+                "org_id:acme,slug:docs,user_id:1,me:1",
+                true,
+            ),
+            (
+                Operation::Read,
+                "/orgs/globex",
+                "org_id:acme,slug:docs",
+                false,
+            ),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str(context).unwrap()
+                ),
+                Ok(expected),
+                "{operation} {path} {context}"
+            );
+        }
+        assert!(rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/orgs/acme").unwrap(),
+                &Context::from_str("").unwrap()
+            )
+            .is_err());
+    }
 
-        for (path, attributes) in config.resources {
-            root.insert(
-                path.as_str(),
-                &mut Path::from_str(path.as_str())?,
-                attributes,
-            )?;
+    #[test]
+    fn test_resource_hierarchy_literal_and_param_siblings_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/orgs/public" = {access_rule = "(list read)"}
+            "/orgs/org_id" = {access_rule = "(list list)"}
+            "/orgs/:org_id/users/:user_id" = {access_rule = "(if (eq $path.user_id $me) (list read update) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (operation, path, context, expected) in [
+            (
+                Operation::Read,
+                "/orgs/public",
+                "org_id:acme,user_id:1",
+                true,
+            ),
+            (
+                Operation::List,
+                "/orgs/org_id",
+                "org_id:acme,user_id:1",
+                true,
+            ),
+            (
+                Operation::Update,
+                "/orgs/acme/users/1",
+                "org_id:acme,user_id:1,me:1",
+                true,
+            ),
+            (
+                Operation::Update,
+                "/orgs/acme/users/1",
+                "org_id:acme,user_id:1,me:2",
+                false,
+            ),
+            (
+                Operation::Update,
+                "/orgs/globex/users/1",
+                "org_id:acme,user_id:1,me:1",
+                false,
+            ),
+            (
+                Operation::Read,
+                "/orgs/:org_id/users/1",
+                "org_id:acme,user_id:1,me:1",
+                false,
+            ),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    operation.clone(),
+                    &Path::from_str(path).unwrap(),
+                    &Context::from_str(context).unwrap()
+                ),
+                Ok(expected),
+                "{operation} {path} {context}"
+            );
         }
-        Ok(root)
+        assert_eq!(
+            rh.resources()
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<String>>(),
+            vec![
+                "/orgs/:org_id/users/:user_id",
+                "/orgs/org_id",
+                "/orgs/public"
+            ]
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::Config;
-    use crate::rule::Rule;
-    use std::str::FromStr;
-    use toml;
+    #[test]
+    fn test_resource_hierarchy_reuse_path_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/reports/:id" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let path = Path::from_str("/reports/1").unwrap();
+        let context = Context::from_str("id:1").unwrap();
+        assert_eq!(rh.is_allowed(Operation::Read, &path, &context), Ok(true));
+        assert_eq!(rh.is_allowed(Operation::Update, &path, &context), Ok(false));
+        assert_eq!(rh.is_allowed(Operation::Read, &path, &context), Ok(true));
+        assert_eq!(path, Path::from_str("/reports/1").unwrap());
+    }
 
     #[test]
-    fn test_resource_path_from_str_ok() {
-        let left: Result<Path, Error> = Path::from_str("/");
-        let right: Result<Path, Error> = Ok(Path(vec![String::new()]));
-        assert_eq!(left, right);
+    fn test_resource_hierarchy_evaluate_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/docs/:id" = {access_rule = "(list read list (obligation log-access))"}
+            "/public" = {public = true}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let evaluate = |operation, path, context| {
+            rh.evaluate(
+                operation,
+                &Path::from_str(path).unwrap(),
+                &Context::from_str(context).unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            evaluate(Operation::Read, "/docs/1", "role:user,id:1"),
+            Decision {
+                allowed: true,
+                matched_path: Some(String::from("/docs/:id")),
+                effective_permission: Permission::READ | Permission::LIST,
+                obligations: vec![Obligation {
+                    name: String::from("log-access"),
+                    arguments: vec![],
+                }],
+                reason: Reason::Granted,
+            }
+        );
+        assert_eq!(
+            evaluate(Operation::Delete, "/docs/1", "role:admin,id:1"),
+            Decision {
+                allowed: true,
+                matched_path: Some(String::from("/")),
+                effective_permission: Permission::ALL,
+                obligations: vec![],
+                reason: Reason::Granted,
+            }
+        );
+        assert_eq!(
+            evaluate(Operation::Update, "/public", "role:user").matched_path,
+            Some(String::from("/public"))
+        );
+    }
 
-        let left: Result<Path, Error> = Path::from_str("/test1/test2");
-        let right: Result<Path, Error> = Ok(Path(vec!["test2".to_string(), "test1".to_string()]));
-        assert_eq!(left, right);
+    #[test]
+    fn test_resource_hierarchy_evaluate_err() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/docs/secret" = {access_rule = "(deny read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.evaluate(
+                Operation::Read,
+                &Path::from_str("/docs/secret").unwrap(),
+                &Context::from_str("role:admin").unwrap()
+            ),
+            Ok(Decision {
+                allowed: false,
+                matched_path: Some(String::from("/docs/secret")),
+                effective_permission: Permission::NONE,
+                obligations: vec![],
+                reason: Reason::Denied,
+            })
+        );
+        assert_eq!(
+            rh.evaluate(
+                Operation::Read,
+                &Path::from_str("/docs").unwrap(),
+                &Context::from_str("role:user").unwrap()
+            )
+            .map(|decision| (decision.allowed, decision.matched_path, decision.reason)),
+            Ok((false, None, Reason::NotGranted))
+        );
+    }
 
-        let left: Result<Path, Error> = Path::from_str("/test1/test2/");
-        let right: Result<Path, Error> = Ok(Path(vec![
-            String::new(),
-            "test2".to_string(),
-            "test1".to_string(),
-        ]));
-        assert_eq!(left, right);
+    #[test]
+    fn test_resource_hierarchy_explain_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/docs/:id" = {access_rule = "(if (eq $owner $user) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let explanation = rh.explain(
+            Operation::Read,
+            &Path::from_str("/docs/1").unwrap(),
+            &Context::from_str("role:user,id:1,owner:alice,user:alice").unwrap(),
+        );
+        assert_eq!(
+            explanation
+                .steps
+                .iter()
+                .map(|step| step.resource.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["/", "/docs/:id"]
+        );
+        assert_eq!(
+            explanation.steps[1].trace[0],
+            (
+                Rule::from_str("(eq $owner $user)").unwrap(),
+                Rule::Bool(true)
+            )
+        );
+        assert_eq!(
+            explanation.to_string(),
+            "/ (if (eq $role admin) (list all) (list))
+  (eq $role admin) => false
+  (list all) => (all)
+  (list) => ()
+  (if (eq $role admin) (list all) (list)) => ()
+  = ()
+/docs/:id (if (eq $owner $user) (list read) (list))
+  (eq $owner $user) => true
+  (list read) => (read)
+  (list) => ()
+  (if (eq $owner $user) (list read) (list)) => (read)
+  = (read)
+granted by /docs/:id"
+        );
     }
 
     #[test]
-    fn test_resource_hierarchy_insert_err() {
-        let mut rh: Hierarchy = toml::from_str::<Config>(
+    fn test_resource_hierarchy_explain_err() {
+        let rh: Hierarchy = toml::from_str::<Config>(
             r#"
             [resources]
-            "/:a" = {access_rule = "()"}
+            "/" = {access_rule = "(list $role)"}
         "#,
         )
         .unwrap()
         .try_into()
         .unwrap();
+        let explanation = rh.explain(
+            Operation::Read,
+            &Path::from_str("/").unwrap(),
+            &Context::from_str("role:raed").unwrap(),
+        );
+        assert_eq!(explanation.steps.len(), 1);
         assert_eq!(
-            rh.insert(
-                "/:b",
-                &mut Path::from_str("/:b").unwrap(),
-                Attributes::default()
+            explanation.decision,
+            Err(rule::Error::InvalidPermission(Rule::String(String::from(
+                "raed"
+            ))))
+        );
+        assert!(explanation
+            .to_string()
+            .starts_with("/ (list $role)\n  (list $role) => (raed)\n"));
+    }
+
+    #[test]
+    fn test_resource_hierarchy_list_permissions_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/docs" = {access_rule = "(list list)"}
+            "/docs/:id" = {access_rule = "(if (eq $owner $user) (list read update custom.0) (list read))"}
+            "/docs/:id/history" = {access_rule = "(deny update delete)"}
+            "/reports" = {access_rule = "(if (eq $action read) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        for (path, context, expected) in [
+            (
+                "/docs/1",
+                "role:user,id:1,owner:a,user:a",
+                Permission::LIST
+                    | Permission::READ
+                    | Permission::UPDATE
+                    | Permission::custom(0).unwrap(),
+            ),
+            (
+                "/docs/1",
+                "role:user,id:1,owner:a,user:b",
+                Permission::LIST | Permission::READ,
+            ),
+            (
+                "/docs/1/history",
+                "role:admin,id:1,owner:a,user:b",
+                Permission::CREATE | Permission::READ | Permission::LIST,
+            ),
+            ("/reports", "role:user", Permission::READ),
+        ] {
+            let path = Path::from_str(path).unwrap();
+            let context = Context::from_str(context).unwrap();
+            let permissions = rh.list_permissions(&path, &context).unwrap();
+            assert_eq!(permissions, expected, "{path:?} {context}");
+            assert_eq!(
+                permissions.intersection(Permission::ALL),
+                rh.permission(&path, &context).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_resource_hierarchy_list_permissions_settled_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list all)"}
+            "/d" = {access_rule = "(list $op)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let path = Path::from_str("/d").unwrap();
+        let context = Context::from_str("op:bogus").unwrap();
+        assert_eq!(rh.is_allowed(Operation::Read, &path, &context), Ok(true));
+        assert_eq!(rh.list_permissions(&path, &context), Ok(Permission::ALL));
+    }
+
+    #[test]
+    fn test_resource_hierarchy_list_permissions_err() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(list $role)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            rh.list_permissions(
+                &Path::from_str("/").unwrap(),
+                &Context::from_str("role:raed").unwrap()
             ),
-            Err(Error::AmbiguousResource("/:b".to_string(), "a".to_string()))
+            Err(rule::Error::InvalidPermission(Rule::String(String::from(
+                "raed"
+            ))))
         );
     }
 
     #[test]
-    fn test_resource_hierarchy_from_config_ok() {
-        let left: Result<Hierarchy, Error> = toml::from_str::<Config>(
+    fn test_hierarchy_insert_remove_resource_ok() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
             r#"
+            aliases = {view = "read"}
             [resources]
-            "/" = {access_rule = "()", description = "Root"}
+            "/docs" = {access_rule = "(list list)"}
         "#,
         )
         .unwrap()
-        .try_into();
-        let right: Result<Hierarchy, Error> = Ok(Hierarchy {
-            name: String::new(),
-            attributes: Attributes {
-                access_rule: None,
-                description: None,
-            },
-            children: BTreeMap::from([(
-                String::new(),
-                Hierarchy {
-                    name: String::new(),
-                    attributes: Attributes {
-                        access_rule: Some(Rule::from_str("()").unwrap()),
-                        description: Some("Root".to_string()),
-                    },
-                    children: BTreeMap::new(),
-                    special_child_name: None,
-                },
-            )]),
-            special_child_name: None,
-        });
-        assert_eq!(left, right);
+        .try_into()
+        .unwrap();
+        let path = Path::from_str("/docs/1").unwrap();
+        let context = Context::from_str("id:1,role:editor").unwrap();
+        assert_eq!(rh.is_allowed(Operation::Update, &path, &context), Ok(false));
+        assert_eq!(
+            rh.insert_resource(
+                "/docs/:id",
+                Attributes {
+                    access_rule: Some(Rule::from_str("(list view)").unwrap()),
+                    rules: BTreeMap::from([(
+                        String::from("update"),
+                        Rule::from_str("(eq $role editor)").unwrap()
+                    )]),
+                    ..Attributes::default()
+                }
+            ),
+            Ok(())
+        );
+        assert_eq!(rh.is_allowed(Operation::Read, &path, &context), Ok(true));
+        assert_eq!(rh.is_allowed(Operation::Update, &path, &context), Ok(true));
+        assert_eq!(rh.remove_resource("/docs/:id"), Ok(()));
+        assert_eq!(rh.is_allowed(Operation::Read, &path, &context), Ok(false));
+        assert_eq!(
+            rh.resources()
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<String>>(),
+            vec!["/docs"]
+        );
+    }
 
-        let left: Result<Hierarchy, Error> = toml::from_str::<Config>(
+    #[test]
+    fn test_hierarchy_insert_remove_resource_err() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
             r#"
             [resources]
-            "/test" = {access_rule = """(
-                list create
-            )""", description = "Root"}
-            "/test/" = {access_rule = "(list read)", description = "Root"}
+            "/docs/:id" = {access_rule = "(list read)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let attributes = Attributes {
+            access_rule: Some(Rule::from_str("(list read)").unwrap()),
+            ..Attributes::default()
+        };
+        assert_eq!(
+            rh.insert_resource("/docs/:id", attributes.clone()),
+            Err(Error::DuplicateResource(String::from("/docs/:id")))
+        );
+        assert_eq!(
+            rh.insert_resource(
+                "/docs/:id/pages",
+                Attributes {
+                    rules: BTreeMap::from([(String::from("craete"), Rule::Bool(true))]),
+                    ..attributes
+                }
+            ),
+            Err(Error::UnknownOperation(
+                String::from("/docs/:id/pages"),
+                String::from("craete")
+            ))
+        );
+        assert_eq!(
+            rh.remove_resource("/docs"),
+            Err(Error::UnknownResource(String::from("/docs")))
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_diff_ok() {
+        let hierarchy = |resources: &str| -> Hierarchy {
+            toml::from_str::<Config>(&format!("[resources]\n{resources}"))
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        let before = hierarchy(
+            r#"
+            "/docs" = {access_rule = "(list list)", properties = {owner = "alice"}}
+            "/docs/:id" = {access_rule = "(list read)"}
+            "/legacy" = {public = true}
+        "#,
+        );
+        let after = hierarchy(
+            r#"
+            "/docs" = {access_rule = "(list list)", properties = {owner = "bob"}}
+            "/docs/:id" = {access_rule = "(list read)", rules = {update = "(eq $role editor)"}}
+            "/reports" = {access_rule = "(list read)"}
+        "#,
+        );
+        let diff = before.diff(&after);
+        assert_eq!(diff.added, vec!["/reports"]);
+        assert_eq!(diff.removed, vec!["/legacy"]);
+        assert_eq!(
+            diff.changed
+                .iter()
+                .map(|(path, differences)| (
+                    path.as_str(),
+                    differences
+                        .iter()
+                        .map(|difference| difference.name.as_str())
+                        .collect::<Vec<&str>>()
+                ))
+                .collect::<Vec<(&str, Vec<&str>)>>(),
+            vec![
+                ("/docs", vec!["properties.owner"]),
+                ("/docs/:id", vec!["access_rule"]),
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "+ /reports\n- /legacy\n~ /docs\n    properties.owner: alice -> bob\n~ /docs/:id\n    access_rule: (list read) -> (concat (list read) (if (eq $role editor) (list update) (list)))\n"
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_hierarchy_insert_resource_constraints_ok() {
+        let mut rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            allowed_operators = ["if", "eq", "list"]
+            limits = {max_resources = 2}
+            tenancy = {prefix = "/t/:tenant_id", attribute = "subject.tenant", inject = true}
+            [attributes]
+            role = "string"
+            "path.tenant_id" = "string"
+            "subject.tenant" = "string"
+            [resources]
+            "/t/:tenant_id" = {access_rule = "(list read)"}
         "#,
         )
         .unwrap()
-        .try_into();
-        let right: Result<Hierarchy, Error> = Ok(Hierarchy {
-            name: String::new(),
-            attributes: Attributes {
-                access_rule: None,
-                description: None,
-            },
-            children: BTreeMap::from([(
-                "test".to_string(),
-                Hierarchy {
-                    name: "test".to_string(),
-                    attributes: Attributes {
-                        access_rule: Some(Rule::from_str("(list create)").unwrap()),
-                        description: Some("Root".to_string()),
-                    },
-                    children: BTreeMap::from([(
-                        String::new(),
-                        Hierarchy {
-                            name: String::new(),
-                            attributes: Attributes {
-                                access_rule: Some(Rule::from_str("(list read)").unwrap()),
-                                description: Some("Root".to_string()),
-                            },
-                            children: BTreeMap::new(),
-                            special_child_name: None,
-                        },
-                    )]),
-                    special_child_name: None,
-                },
-            )]),
-            special_child_name: None,
-        });
-        assert_eq!(left, right);
+        .try_into()
+        .unwrap();
+        let attributes = |rule: &str| Attributes {
+            access_rule: Some(Rule::from_str(rule).unwrap()),
+            ..Attributes::default()
+        };
+        assert_eq!(
+            rh.insert_resource(
+                "/t/:tenant_id/docs",
+                attributes("(if (remote-admin $user_id) (list all) (list))")
+            ),
+            Err(Error::ForbiddenOperator(
+                String::from("/t/:tenant_id/docs"),
+                String::from("remote-admin")
+            ))
+        );
+        assert!(matches!(
+            rh.insert_resource(
+                "/t/:tenant_id/docs",
+                attributes("(if (eq $role 3) (list all) (list))")
+            ),
+            Err(Error::TypeError(..))
+        ));
+        assert_eq!(
+            rh.insert_resource("/t/:tenant_id/docs", attributes("(list all)")),
+            Ok(())
+        );
+        let path = Path::from_str("/t/acme/docs").unwrap();
+        for (context, expected) in [
+            ("tenant_id:acme,subject.tenant:acme", true),
+            ("tenant_id:acme,subject.tenant:other", false),
+        ] {
+            assert_eq!(
+                rh.is_allowed(
+                    Operation::Delete,
+                    &path,
+                    &Context::from_str(context).unwrap()
+                ),
+                Ok(expected),
+                "{context}"
+            );
+        }
+        assert_eq!(
+            rh.update_rule(
+                "/t/:tenant_id/docs",
+                Rule::from_str("(list (deny read))").unwrap()
+            ),
+            Err(Error::ForbiddenOperator(
+                String::from("/t/:tenant_id/docs"),
+                String::from("deny")
+            ))
+        );
+        assert_eq!(
+            rh.insert_resource("/t/:tenant_id/pages", attributes("(list read)")),
+            Err(Error::LimitExceeded(String::from("resources"), 2))
+        );
+
+        let config = Config::from(&rh);
+        assert_eq!(config.limits.max_resources, Some(2));
+        assert_eq!(
+            config.tenancy.as_ref().map(|tenancy| tenancy.inject),
+            Some(false)
+        );
+        let restored: Hierarchy = toml::from_str::<Config>(&toml::to_string(&config).unwrap())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(Config::from(&restored), config);
+        assert!(rh.diff(&restored).is_empty());
     }
 
     #[test]
-    #[allow(clippy::too_many_lines)] // Sometimes it's ok for tests to be really f-cking long
-    fn test_is_allowed_ok() {
+    fn test_hierarchy_into_config_ok() {
         let rh: Hierarchy = toml::from_str::<Config>(
             r#"
+            numeric_coercion = true
+            aliases = {view = "read"}
+            [scopes]
+            "docs:read" = {operations = "read", prefixes = ["/docs"]}
             [resources]
-            "/" = {access_rule = "(list)", description = "Root"}
-            "/test1" = {access_rule = "(list create)", description = "Root"}
-            "/test1/" = {access_rule = "(list read)", description = "Root"}
-            "/test2/test3" = {access_rule = "(list read)", description = "Root"}
-            "/all" = {access_rule = "(list all)", description = "Root"}
-            "/private/:user_id" = {access_rule = "(list all)", description = "Root"}
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/docs" = {access_rule = "(list list)", list_filter_rule = "(eq $resource.owner $user)", properties = {label = "Team docs (shared)", level = 3}}
+            "/docs/:id" = {access_rule = "(list view (require mfa))", rules = {update = "(eq $name \"John (Jr.)\")"}, operations = ["read", "update"]}
+            "/public" = {public = true, description = "Open to all"}
         "#,
         )
         .unwrap()
         .try_into()
         .unwrap();
+        let config = Config::from(&rh);
+        assert!(config.numeric_coercion);
+        assert_eq!(config.resources.len(), 4);
+        let serialized = toml::to_string(&config).unwrap();
+        let restored: Hierarchy = toml::from_str::<Config>(&serialized)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(restored, rh, "{serialized}");
+        assert!(rh.diff(&restored).is_empty());
+    }
 
-        assert!(!rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test1").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test1").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test1/").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test1/").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test1/test").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test1/test").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test2").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test2").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test2/").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test2/").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test2/test").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test2/test").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Create,
-                &mut Path::from_str("/test2/test3").unwrap(),
-                &Context::from_str("").unwrap()
+    #[test]
+    fn test_resource_hierarchy_combining_ok() {
+        let hierarchy = |combining: &str| -> Hierarchy {
+            toml::from_str::<Config>(&format!(
+                r#"
+                [settings]
+                combining = "{combining}"
+
+                [resources]
+                "/docs" = {{access_rule = "(list read update)"}}
+                "/docs/archive" = {{access_rule = "(list read)"}}
+                "/docs/archive/old" = {{access_rule = "(list read update)", combining = "union"}}
+            "#
+            ))
+            .unwrap()
+            .try_into()
+            .unwrap()
+        };
+        let allowed = |rh: &Hierarchy, to: Operation, on: &str| {
+            rh.is_allowed(
+                to,
+                &Path::from_str(on).unwrap(),
+                &Context::from_str("").unwrap(),
             )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Read,
-                &mut Path::from_str("/test2/test3").unwrap(),
-                &Context::from_str("").unwrap()
+            .unwrap()
+        };
+        let permissions = |rh: &Hierarchy, on: &str| {
+            rh.list_permissions(
+                &Path::from_str(on).unwrap(),
+                &Context::from_str("").unwrap(),
             )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
+            .unwrap()
+        };
+
+        let rh = hierarchy("union");
+        assert!(allowed(&rh, Operation::Update, "/docs/archive"));
+        assert_eq!(
+            permissions(&rh, "/docs/archive"),
+            Permission::from(Operation::Read) | Permission::from(Operation::Update)
+        );
+
+        let rh = hierarchy("child-overrides");
+        assert!(allowed(&rh, Operation::Update, "/docs"));
+        assert!(!allowed(&rh, Operation::Update, "/docs/archive"));
+        assert!(allowed(&rh, Operation::Read, "/docs/archive"));
+        assert!(allowed(&rh, Operation::Update, "/docs/archive/old"));
+        assert_eq!(
+            permissions(&rh, "/docs/archive"),
+            Permission::from(Operation::Read)
+        );
+        assert_eq!(
+            rh.evaluate(
                 Operation::Read,
-                &mut Path::from_str("/test2/test3/").unwrap(),
+                &Path::from_str("/docs/archive").unwrap(),
                 &Context::from_str("").unwrap()
             )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
+            .unwrap()
+            .matched_path,
+            Some(String::from("/docs/archive"))
+        );
+
+        let rh = hierarchy("first-applicable");
+        assert!(allowed(&rh, Operation::Update, "/docs/archive"));
+        assert_eq!(
+            rh.evaluate(
                 Operation::Read,
-                &mut Path::from_str("/test2/test3/test").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/all").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/all/").unwrap(),
-                &Context::from_str("").unwrap()
-            )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/all/1").unwrap(),
+                &Path::from_str("/docs/archive").unwrap(),
                 &Context::from_str("").unwrap()
             )
-            .unwrap());
-        assert!(rh
-            .is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/private/1").unwrap(),
-                &Context::from_str("user_id:1").unwrap()
-            )
-            .unwrap());
-        assert!(!rh
-            .is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/private/2").unwrap(),
-                &Context::from_str("user_id:1").unwrap()
-            )
-            .unwrap());
+            .unwrap()
+            .matched_path,
+            Some(String::from("/docs"))
+        );
+        assert_eq!(
+            Config::from(&rh).settings.combining,
+            Combining::FirstApplicable
+        );
     }
 
     #[test]
-    #[allow(clippy::too_many_lines)] // Sometimes it's ok for tests to be really f-cking long
-    fn test_is_allowed_err() {
+    fn test_resource_hierarchy_combining_err() {
+        assert!(toml::from_str::<Config>(
+            r#"
+            [settings]
+            combining = "deny-overrides"
+
+            [resources]
+            "/docs" = {access_rule = "(list read)"}
+        "#,
+        )
+        .is_err());
         let rh: Hierarchy = toml::from_str::<Config>(
             r#"
             [resources]
-            "/" = {access_rule = "(list)", description = "Root"}
-            "/test1" = {access_rule = "(list create)", description = "Root"}
-            "/test1/" = {access_rule = "(list read)", description = "Root"}
-            "/test2/test3" = {access_rule = "(list read)", description = "Root"}
-            "/all" = {access_rule = "(list all)", description = "Root"}
-            "/private/:user_id" = {access_rule = "(list all)", description = "Root"}
+            "/docs" = {access_rule = "(list read)", combining = "child-overrides"}
+            "/docs/:id" = {access_rule = "(deny read)", combining = "union"}
         "#,
         )
         .unwrap()
         .try_into()
         .unwrap();
+        let context = Context::from_str("id:1").unwrap();
+        assert!(!rh
+            .is_allowed(
+                Operation::Read,
+                &Path::from_str("/docs/1").unwrap(),
+                &context
+            )
+            .unwrap());
+    }
 
-        assert_eq!(
-            rh.is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/private/").unwrap(),
-                &Context::from_str("user_id:1").unwrap()
-            ),
-            Err(rule::Error::CannotCompare(
-                Rule::Integer(1),
-                Rule::String("".to_string())
+    #[test]
+    fn test_resource_hierarchy_algorithm_ok() {
+        let hierarchy = |algorithm: &str| -> Hierarchy {
+            toml::from_str::<Config>(&format!(
+                r#"
+                [settings]
+                algorithm = "{algorithm}"
+
+                [resources]
+                "/docs" = {{access_rule = "(deny update)"}}
+                "/docs/drafts" = {{access_rule = "(list read update)"}}
+                "/docs/drafts/locked" = {{access_rule = "(deny read)"}}
+            "#
             ))
+            .unwrap()
+            .try_into()
+            .unwrap()
+        };
+        let context = Context::from_str("").unwrap();
+        let decide = |rh: &Hierarchy, to: Operation, on: &str| {
+            rh.evaluate(to, &Path::from_str(on).unwrap(), &context)
+                .unwrap()
+        };
+
+        let rh = hierarchy("deny-overrides");
+        let decision = decide(&rh, Operation::Update, "/docs/drafts");
+        assert!(!decision.allowed);
+        assert_eq!(decision.reason, Reason::Denied);
+        assert_eq!(decision.matched_path, Some(String::from("/docs")));
+        assert!(!decide(&rh, Operation::Read, "/docs/drafts/locked").allowed);
+        assert_eq!(
+            rh.list_permissions(&Path::from_str("/docs/drafts").unwrap(), &context),
+            Ok(Permission::from(Operation::Read))
         );
+
+        let rh = hierarchy("permit-overrides");
+        let decision = decide(&rh, Operation::Update, "/docs/drafts");
+        assert!(decision.allowed);
+        assert_eq!(decision.matched_path, Some(String::from("/docs/drafts")));
+        assert!(decide(&rh, Operation::Read, "/docs/drafts/locked").allowed);
         assert_eq!(
-            rh.is_allowed(
-                Operation::Delete,
-                &mut Path::from_str("/private/").unwrap(),
-                &Context::from_str("").unwrap()
-            ),
-            Err(rule::Error::KeyNotInContext("user_id".to_string()))
+            decide(&rh, Operation::Update, "/docs").reason,
+            Reason::Denied
+        );
+        assert_eq!(
+            rh.list_permissions(&Path::from_str("/docs/drafts").unwrap(), &context),
+            Ok(Permission::from(Operation::Read) | Permission::from(Operation::Update))
+        );
+        assert_eq!(
+            Config::from(&rh).settings.algorithm,
+            Algorithm::PermitOverrides
         );
     }
+
+    #[test]
+    fn test_resource_hierarchy_algorithm_err() {
+        assert!(toml::from_str::<Config>(
+            r#"
+            [settings]
+            algorithm = "first-applicable"
+
+            [resources]
+            "/docs" = {access_rule = "(list read)"}
+        "#,
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,190 @@
+use crate::permission::Operation;
+use crate::resource::Hierarchy;
+use crate::rule::Context;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entitlement {
+    pub subject: String,
+    pub resource: String,
+    pub operation: String,
+    pub granted_by: String,
+    pub rule: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct AccessReview(pub Vec<Entitlement>);
+
+impl AccessReview {
+    #[must_use]
+    pub fn generate(hierarchy: &Hierarchy, subjects: &[(String, Context)]) -> Self {
+        let resources = hierarchy.resources();
+        let mut entitlements = Vec::new();
+        for (subject, context) in subjects {
+            for (resource, _) in &resources {
+                let Some(concrete) = concrete_path(resource, context) else {
+                    continue;
+                };
+                let Ok(path) = hierarchy.path(&concrete) else {
+                    continue;
+                };
+                for operation in Operation::ALL {
+                    if hierarchy.is_allowed(operation.clone(), &path, context) != Ok(true) {
+                        continue;
+                    }
+                    let Ok(verdicts) = hierarchy.verdicts(operation.clone(), &path, context) else {
+                        continue;
+                    };
+                    let Some(granted_by) = verdicts
+                        .into_iter()
+                        .find(|verdict| verdict.allowed == Ok(true))
+                        .map(|verdict| verdict.resource)
+                    else {
+                        continue;
+                    };
+                    let rule = resources
+                        .iter()
+                        .find(|(path, _)| *path == granted_by)
+                        .map(|(_, attributes)| match &attributes.access_rule {
+                            Some(rule) if !attributes.public => rule.to_string(),
+                            _ => String::from("public"),
+                        })
+                        .unwrap_or_default();
+                    entitlements.push(Entitlement {
+                        subject: subject.clone(),
+                        resource: resource.clone(),
+                        operation: operation.to_string(),
+                        granted_by,
+                        rule,
+                    });
+                }
+            }
+        }
+        AccessReview(entitlements)
+    }
+
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("subject,resource,operation,granted_by,rule\n");
+        for entitlement in &self.0 {
+            let fields = [
+                &entitlement.subject,
+                &entitlement.resource,
+                &entitlement.operation,
+                &entitlement.granted_by,
+                &entitlement.rule,
+            ];
+            csv.push_str(
+                &fields
+                    .iter()
+                    .map(|field| csv_field(field))
+                    .collect::<Vec<String>>()
+                    .join(","),
+            );
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn concrete_path(resource: &str, context: &Context) -> Option<String> {
+    resource
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => context.get(param).ok().map(ToString::to_string),
+            None => Some(segment.to_string()),
+        })
+        .collect::<Option<Vec<String>>>()
+        .map(|segments| segments.join("/"))
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_access_review_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/" = {access_rule = "(if (eq $role admin) (list all) (list))"}
+            "/reports" = {access_rule = "(list list read)"}
+            "/private/:user_id" = {access_rule = "(list read update)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let review = AccessReview::generate(
+            &rh,
+            &[
+                (
+                    String::from("alice"),
+                    Context::from_str("role:user,user_id:1").unwrap(),
+                ),
+                (
+                    String::from("bob"),
+                    Context::from_str("role:admin").unwrap(),
+                ),
+            ],
+        );
+        let rows: Vec<(&str, &str, &str, &str)> = review
+            .0
+            .iter()
+            .map(|e| {
+                (
+                    e.subject.as_str(),
+                    e.resource.as_str(),
+                    e.operation.as_str(),
+                    e.granted_by.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("alice", "/private/:user_id", "read", "/private/:user_id"),
+                ("alice", "/private/:user_id", "update", "/private/:user_id"),
+                ("alice", "/reports", "read", "/reports"),
+                ("alice", "/reports", "list", "/reports"),
+                ("bob", "/", "create", "/"),
+                ("bob", "/", "read", "/"),
+                ("bob", "/", "update", "/"),
+                ("bob", "/", "delete", "/"),
+                ("bob", "/", "list", "/"),
+                ("bob", "/reports", "create", "/"),
+                ("bob", "/reports", "read", "/"),
+                ("bob", "/reports", "update", "/"),
+                ("bob", "/reports", "delete", "/"),
+                ("bob", "/reports", "list", "/"),
+            ]
+        );
+        assert_eq!(
+            review.to_csv().lines().take(2).collect::<Vec<&str>>(),
+            vec![
+                "subject,resource,operation,granted_by,rule",
+                "alice,/private/:user_id,read,/private/:user_id,(list read update)",
+            ]
+        );
+        assert!(review
+            .to_json()
+            .contains(r#""rule": "(if (eq $role admin) (list all) (list))""#));
+        assert_eq!(csv_field(r#"a,"b""#), r#""a,""b""""#);
+    }
+}
@@ -1,33 +1,52 @@
+use crate::clock::{self, Clock};
+use crate::counter::CounterProvider;
+use crate::function::Functions;
+use crate::permission::{self, Obligation};
+use crate::resolver::AttributeResolver;
+use crate::schema::{AttributeType, Schema};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
 
-#[derive(Debug, Serialize, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Rule {
-    #[serde(rename = "String")]
     String(String),
-    #[serde(rename = "Bool")]
     Bool(bool),
-    #[serde(rename = "Integer")]
     Integer(i32),
-    #[serde(rename = "Float")]
     Float(f32),
-    #[serde(rename = "If")]
     If(String),
-    #[serde(rename = "And")]
     And(String),
-    #[serde(rename = "Or")]
     Or(String),
-    #[serde(rename = "Eq")]
     Eq(String),
-    #[serde(rename = "In")]
     In(String),
-    #[serde(rename = "List")]
     List(String),
-    #[serde(rename = "Tuple")]
+    Concat(String),
+    Require(String),
+    Usage(String),
+    ContainsAll(String),
+    ContainsAny(String),
+    Between(String),
+    Mod(String),
+    Min(String),
+    Max(String),
+    HasKey(String),
+    Lambda(String),
+    Map(String),
+    ResourceRule(String),
+    Try(String),
+    Perms(String),
+    Deny(String),
+    Literal(String),
     Tuple(Vec<Rule>),
 }
 
-#[derive(Debug, thiserror::Error, PartialEq)]
+#[derive(Debug, Clone, thiserror::Error, PartialEq)]
 pub enum Error {
     #[error("Cannot parse '{0}'")]
     CannotParse(String),
@@ -47,11 +66,387 @@ pub enum Error {
     InvalidAndStatement(Rule),
     #[error("Invalid in statement {0:?}")]
     InvalidInStatement(Rule),
+    #[error("Invalid concat statement {0:?}")]
+    InvalidConcatStatement(Rule),
+    #[error("Invalid require statement {0:?}")]
+    InvalidRequireStatement(Rule),
+    #[error("Invalid deny statement {0:?}")]
+    InvalidDenyStatement(Rule),
     #[error("Key not in context {0}")]
     KeyNotInContext(String),
+    #[error("Undeclared attribute {0}")]
+    UndeclaredAttribute(String),
+    #[error("Type mismatch in {0:?}")]
+    TypeMismatch(Rule),
+    #[error("Maximum evaluation depth of {0} exceeded")]
+    MaxDepthExceeded(usize),
+    #[error("Evaluation budget exhausted")]
+    BudgetExhausted,
+    #[error("Invalid usage statement {0:?}")]
+    InvalidUsageStatement(Rule),
+    #[error("No counter provider to resolve {0:?}")]
+    NoCounterProvider(Rule),
+    #[error("Invalid contains statement {0:?}")]
+    InvalidContainsStatement(Rule),
+    #[error("Invalid between statement {0:?}")]
+    InvalidBetweenStatement(Rule),
+    #[error("Invalid arithmetic statement {0:?}")]
+    InvalidArithmeticStatement(Rule),
+    #[error("Attribute {0} has no segment '{1}'")]
+    MissingSegment(String, String),
+    #[error("Invalid has-key statement {0:?}")]
+    InvalidHasKeyStatement(Rule),
+    #[error("Invalid lambda statement {0:?}")]
+    InvalidLambdaStatement(Rule),
+    #[error("Invalid structured rule: {0}")]
+    InvalidStructuredRule(String),
+    #[error("Invalid resource-rule statement {0:?}")]
+    InvalidResourceRuleStatement(Rule),
+    #[error("Unresolved rule of resource '{0}'")]
+    UnresolvedResourceRule(String),
+    #[error("Invalid try statement {0:?}")]
+    InvalidTryStatement(Rule),
+    #[error("Invalid context value for '{0}'")]
+    InvalidContextValue(String),
+    #[error("Invalid permission {0:?}")]
+    InvalidPermission(Rule),
+    #[error("Duplicate context key {0}")]
+    DuplicateKey(String),
+    #[error("Context value for '{0}' contains rule syntax")]
+    UntrustedContextValue(String),
+    #[error("Attribute {0} is not of type {1:?}")]
+    InvalidAttributeType(String, AttributeType),
 }
 
-#[derive(Debug, PartialEq)]
+impl Error {
+    pub(crate) fn is_recoverable(&self) -> bool {
+        !matches!(self, Error::BudgetExhausted | Error::MaxDepthExceeded(_))
+    }
+}
+
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    nodes: Option<usize>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    #[must_use]
+    pub fn with_nodes(mut self, nodes: usize) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    fn consume(&mut self) -> Result<(), Error> {
+        if let Some(nodes) = self.nodes.as_mut() {
+            *nodes = nodes.checked_sub(1).ok_or(Error::BudgetExhausted)?;
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::BudgetExhausted);
+        }
+        Ok(())
+    }
+}
+
+/// Value of an expression for the context values it reads.
+struct Memo {
+    expression: Rule,
+    inputs: Vec<Option<Rule>>,
+    value: Rule,
+}
+
+pub struct Environment<'a> {
+    max_depth: usize,
+    budget: Budget,
+    counters: Option<&'a dyn CounterProvider>,
+    functions: Option<&'a Functions>,
+    strict: bool,
+    numeric_coercion: bool,
+    memo: Option<HashMap<u64, Vec<Memo>>>,
+    clock: Option<&'a dyn Clock>,
+    resolver: Option<&'a dyn AttributeResolver>,
+    resolved: HashMap<String, Option<Rule>>,
+    now: Option<SystemTime>,
+    trace: Option<Vec<(Rule, Rule)>>,
+    bindings: Vec<(String, Rule)>,
+}
+
+impl Default for Environment<'_> {
+    fn default() -> Self {
+        Environment {
+            max_depth: DEFAULT_MAX_DEPTH,
+            budget: Budget::default(),
+            counters: None,
+            functions: None,
+            strict: false,
+            numeric_coercion: false,
+            memo: None,
+            clock: None,
+            resolver: None,
+            resolved: HashMap::new(),
+            now: None,
+            trace: None,
+            bindings: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Environment<'a> {
+    #[must_use]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    #[must_use]
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    #[must_use]
+    pub fn with_counters(mut self, counters: &'a dyn CounterProvider) -> Self {
+        self.counters = Some(counters);
+        self
+    }
+
+    #[must_use]
+    pub fn with_functions(mut self, functions: &'a Functions) -> Self {
+        self.functions = Some(functions);
+        self
+    }
+
+    #[must_use]
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    #[must_use]
+    pub fn with_numeric_coercion(mut self, numeric_coercion: bool) -> Self {
+        self.numeric_coercion = numeric_coercion;
+        self
+    }
+
+    #[must_use]
+    pub fn with_clock(mut self, clock: &'a dyn Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    #[must_use]
+    pub fn with_resolver(mut self, resolver: &'a dyn AttributeResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Adds clock and resolver attributes the rule needs to the context. Both
+    /// are looked up once per environment, so every rule evaluated with it
+    /// sees the same instant and the same resolved values.
+    pub(crate) fn provide<'c>(
+        &mut self,
+        rule: &Rule,
+        context: &'c Context,
+    ) -> Result<Cow<'c, Context>, Error> {
+        let mut context = Cow::Borrowed(context);
+        if let Some(clock) = self.clock {
+            if context.get("env.now").is_err() {
+                let now = *self.now.get_or_insert_with(|| clock.now());
+                clock::insert_environment(context.to_mut(), now);
+            }
+        }
+        if self.resolver.is_some() {
+            for key in rule.variables() {
+                if context.get(&key).is_ok() {
+                    continue;
+                }
+                if let Some(value) = self.resolve(&key, &context)? {
+                    context.to_mut().insert(&key, value);
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    fn resolve(&mut self, key: &str, context: &Context) -> Result<Option<Rule>, Error> {
+        let Some(resolver) = self.resolver else {
+            return Ok(None);
+        };
+        if let Some(value) = self.resolved.get(key) {
+            return Ok(value.clone());
+        }
+        let value = resolver.resolve(key, context)?;
+        self.resolved.insert(key.to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Records every applied expression along with its value, see
+    /// [`Environment::take_trace`].
+    #[must_use]
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(Vec::new());
+        self
+    }
+
+    /// Expressions applied since the last call, innermost first.
+    pub fn take_trace(&mut self) -> Vec<(Rule, Rule)> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn with_memoization(mut self) -> Self {
+        self.memo = Some(HashMap::new());
+        self
+    }
+
+    /// Value bound to a lambda parameter, innermost binding first.
+    fn bound(&self, rule: &Rule) -> Option<&Rule> {
+        let Rule::String(name) = rule else {
+            return None;
+        };
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Expressions inside a lambda body depend on the bound parameters and
+    /// `has-key` on the whole context, so neither is memoized. Anything else
+    /// is keyed by the context values it reads.
+    fn memo_inputs(&self, rule: &Rule, context: &Context) -> Option<Vec<Option<Rule>>> {
+        if self.memo.is_none()
+            || !self.bindings.is_empty()
+            || rule
+                .operators()
+                .iter()
+                .any(|operator| operator == "has-key")
+        {
+            return None;
+        }
+        Some(
+            rule.variables()
+                .iter()
+                .map(|key| context.get(key).ok().cloned())
+                .collect(),
+        )
+    }
+
+    fn memoized(&self, rule: &Rule, context: &Context) -> Option<Rule> {
+        let inputs = self.memo_inputs(rule, context)?;
+        self.memo
+            .as_ref()?
+            .get(&rule.fingerprint())?
+            .iter()
+            .find(|memo| memo.expression == *rule && memo.inputs == inputs)
+            .map(|memo| memo.value.clone())
+    }
+
+    fn memoize(&mut self, rule: &Rule, context: &Context, value: &Rule) {
+        let Some(inputs) = self.memo_inputs(rule, context) else {
+            return;
+        };
+        if let Some(memo) = self.memo.as_mut() {
+            memo.entry(rule.fingerprint()).or_default().push(Memo {
+                expression: rule.clone(),
+                inputs,
+                value: value.clone(),
+            });
+        }
+    }
+
+    pub fn budget(&mut self) -> &mut Budget {
+        &mut self.budget
+    }
+
+    pub(crate) fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub(crate) fn consume(&mut self) -> Result<(), Error> {
+        self.budget.consume()
+    }
+
+    /// Whether `name` is a registered host function.
+    pub(crate) fn calls(&self, name: &str) -> bool {
+        self.functions
+            .is_some_and(|functions| functions.contains(name))
+    }
+
+    /// Whether memoization or tracing needs each expression evaluated as a
+    /// node of the rule tree.
+    pub(crate) fn records(&self) -> bool {
+        self.memo.is_some() || self.trace.is_some()
+    }
+}
+
+fn placeholders(template: &str) -> (Vec<(&str, &str)>, &str) {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{$") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        segments.push((&rest[..start], &rest[start + 2..start + end]));
+        rest = &rest[start + end + 1..];
+    }
+    (segments, rest)
+}
+
+fn widen_numbers(operands: Vec<Rule>) -> Vec<Rule> {
+    let scalars = || {
+        operands.iter().flat_map(|operand| match operand {
+            Rule::Tuple(items) => items.as_slice(),
+            scalar => std::slice::from_ref(scalar),
+        })
+    };
+    if !scalars().any(|r| matches!(r, Rule::Integer(_)))
+        || !scalars().any(|r| matches!(r, Rule::Float(_)))
+    {
+        return operands;
+    }
+    let widen = |rule: Rule| match rule {
+        Rule::Integer(i) => Rule::Float(i as f32),
+        other => other,
+    };
+    operands
+        .into_iter()
+        .map(|operand| match operand {
+            Rule::Tuple(items) => Rule::Tuple(items.into_iter().map(widen).collect()),
+            scalar => widen(scalar),
+        })
+        .collect()
+}
+
+struct NoCounters;
+
+impl CounterProvider for NoCounters {
+    fn usage(&self, _: &str, _: &Rule, _: &str) -> Result<i32, Error> {
+        Ok(0)
+    }
+}
+
+enum Task<'a> {
+    Eval(&'a Rule, usize),
+    Apply(&'a Rule),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Context(Vec<(String, Rule)>);
 
 impl Context {
@@ -62,668 +457,4109 @@ impl Context {
             .map(|(_, v)| v)
             .ok_or(Error::KeyNotInContext(key.to_string()))
     }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(k, _)| k.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Rule)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[must_use]
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Rule> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    fn missing_segment(&self, path: &str) -> Option<String> {
+        let segments: Vec<&str> = path.split('.').collect();
+        (1..segments.len()).rev().find_map(|i| {
+            let prefix = format!("{}.", segments[..i].join("."));
+            self.0
+                .iter()
+                .any(|(k, _)| k.starts_with(&prefix))
+                .then(|| segments[i].to_string())
+        })
+    }
+
+    pub fn insert_nested(&mut self, prefix: &str, context: &Context) {
+        for (key, value) in &context.0 {
+            self.insert(&format!("{prefix}.{key}"), value.clone());
+        }
+    }
+
+    pub fn insert(&mut self, key: &str, value: Rule) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn validate(&self, schema: &Schema) -> Result<(), Error> {
+        if let Some(missing) = schema.required().find(|name| self.get(name).is_err()) {
+            return Err(Error::KeyNotInContext(missing.to_string()));
+        }
+        for (key, value) in &self.0 {
+            let Some(expected) = schema.get(key) else {
+                continue;
+            };
+            let matches = matches!(
+                (expected, value),
+                (AttributeType::String, Rule::String(_))
+                    | (AttributeType::Bool, Rule::Bool(_))
+                    | (AttributeType::Integer, Rule::Integer(_))
+                    | (AttributeType::Float, Rule::Float(_) | Rule::Integer(_))
+                    | (AttributeType::List, Rule::Tuple(_))
+            );
+            if !matches {
+                return Err(Error::InvalidAttributeType(key.clone(), expected));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: &Context) {
+        for (key, value) in &other.0 {
+            self.insert(key, value.clone());
+        }
+    }
+
+    #[must_use]
+    pub fn layered(layers: &[&Context]) -> Self {
+        let mut context = Context::default();
+        for layer in layers.iter().rev() {
+            context.merge(layer);
+        }
+        context
+    }
+
+    #[must_use]
+    pub fn impersonation(subject: &Context, on_behalf_of: &Context) -> Self {
+        let mut context = Context(Vec::new());
+        context.insert_nested("subject", subject);
+        context.insert_nested("on_behalf_of", on_behalf_of);
+        for (key, value) in &on_behalf_of.0 {
+            context.insert(key, value.clone());
+        }
+        context
+    }
+
+    #[must_use]
+    pub fn anonymous() -> Self {
+        Context(vec![
+            (
+                String::from("principal"),
+                Rule::String(String::from("anonymous")),
+            ),
+            (String::from("authenticated"), Rule::Bool(false)),
+        ])
+    }
+
+    #[must_use]
+    pub fn service(name: &str) -> Self {
+        Context(vec![
+            (
+                String::from("principal"),
+                Rule::String(String::from("service")),
+            ),
+            (String::from("service"), Rule::String(name.to_string())),
+            (String::from("authenticated"), Rule::Bool(true)),
+        ])
+    }
+
+    #[must_use]
+    pub fn categorized(
+        subject: &Context,
+        resource: &Context,
+        action: &Context,
+        environment: &Context,
+    ) -> Self {
+        let mut context = Context::default();
+        for (category, values) in [
+            (Category::Subject, subject),
+            (Category::Resource, resource),
+            (Category::Action, action),
+            (Category::Environment, environment),
+        ] {
+            context.insert_nested(category.prefix(), values);
+        }
+        context
+    }
+
+    pub fn insert_in(&mut self, category: Category, key: &str, value: Rule) {
+        self.insert(&format!("{}.{key}", category.prefix()), value);
+    }
+
+    #[must_use]
+    pub fn category(&self, category: Category) -> Context {
+        let prefix = format!("{}.", category.prefix());
+        self.0
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(&prefix)
+                    .map(|key| (key.to_string(), value.clone()))
+            })
+            .collect()
+    }
+
+    pub fn sanitize(self) -> Result<Context, Error> {
+        for (key, value) in &self.0 {
+            if key.is_empty()
+                || key.starts_with('$')
+                || key
+                    .chars()
+                    .any(|c| c.is_whitespace() || "()[]\";\\{}:,".contains(c))
+                || !is_data(value, false)
+            {
+                return Err(Error::UntrustedContextValue(key.clone()));
+            }
+        }
+        Ok(self)
+    }
+
+    #[must_use]
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, Error> {
+        serde_json::from_str::<serde_json::Value>(s)
+            .map_err(|_| Error::CannotParse(s.to_string()))
+            .and_then(Context::try_from)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Subject,
+    Resource,
+    Action,
+    Environment,
+}
+
+impl Category {
+    #[must_use]
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Category::Subject => "subject",
+            Category::Resource => "resource",
+            Category::Action => "action",
+            Category::Environment => "env",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ContextBuilder(Context);
+
+impl ContextBuilder {
+    #[must_use]
+    pub fn str(mut self, key: &str, value: &str) -> Self {
+        self.0.insert(key, Rule::String(value.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub fn int(mut self, key: &str, value: i32) -> Self {
+        self.0.insert(key, Rule::Integer(value));
+        self
+    }
+
+    #[must_use]
+    pub fn float(mut self, key: &str, value: f32) -> Self {
+        self.0.insert(key, Rule::Float(value));
+        self
+    }
+
+    #[must_use]
+    pub fn bool(mut self, key: &str, value: bool) -> Self {
+        self.0.insert(key, Rule::Bool(value));
+        self
+    }
+
+    #[must_use]
+    pub fn list<'a>(mut self, key: &str, values: impl IntoIterator<Item = &'a str>) -> Self {
+        self.0.insert(
+            key,
+            Rule::Tuple(
+                values
+                    .into_iter()
+                    .map(|value| Rule::String(value.to_string()))
+                    .collect(),
+            ),
+        );
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> Context {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DuplicateKeys {
+    #[default]
+    Reject,
+    LastWins,
+    MultiValue,
 }
 
 impl FromStr for Context {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Context::parse(s, DuplicateKeys::default())
+    }
+}
+
+impl Context {
+    pub fn parse(s: &str, duplicates: DuplicateKeys) -> Result<Self, Error> {
+        let error = || Error::CannotParse(String::from(s));
         let mut context = Context(Vec::new());
-        for pair in s.split(',') {
-            if pair.is_empty() {
+        let mut pairs = vec![(String::new(), None::<(String, bool)>)];
+        let mut lists = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let index = pairs.len() - 1;
+            let (key, value) = pairs.last_mut().ok_or_else(error)?;
+            match (c, value) {
+                (',', _) => pairs.push((String::new(), None)),
+                (_, Some(_)) if lists.len() > index => return Err(error()),
+                (':', value @ None) => *value = Some((String::new(), false)),
+                ('[', Some((value, literal))) if value.is_empty() && !*literal => {
+                    lists.resize(index, None);
+                    lists.push(Some(read_list(&mut chars).ok_or_else(error)??));
+                }
+                ('"', Some((value, literal))) if value.is_empty() && !*literal => {
+                    value.push_str(&read_quoted(&mut chars, &mut false).ok_or_else(error)?);
+                    *literal = true;
+                }
+                ('\\', value) => {
+                    let escaped = chars.next().ok_or_else(error)?;
+                    match value {
+                        Some((value, literal)) => {
+                            value.push(escaped);
+                            *literal = true;
+                        }
+                        None => key.push(escaped),
+                    }
+                }
+                (c, Some((value, _))) => value.push(c),
+                (c, None) => key.push(c),
+            }
+        }
+        lists.resize(pairs.len(), None);
+        for ((key, value), list) in pairs.into_iter().zip(lists) {
+            let value = match (value, list) {
+                (_, Some(list)) => Rule::Tuple(list),
+                (None, None) if key.is_empty() => continue,
+                (None, None) => return Err(error()),
+                (Some((value, true)), None) => Rule::String(value),
+                (Some((value, false)), None) => Rule::from_literal(&value)?,
+            };
+            let Some((_, existing)) = context.0.iter_mut().find(|(k, _)| *k == key) else {
+                context.0.push((key, value));
                 continue;
+            };
+            match (duplicates, existing) {
+                (DuplicateKeys::Reject, _) => return Err(Error::DuplicateKey(key)),
+                (DuplicateKeys::LastWins, existing) => *existing = value,
+                (DuplicateKeys::MultiValue, Rule::Tuple(values)) => values.push(value),
+                (DuplicateKeys::MultiValue, existing) => {
+                    *existing = Rule::Tuple(vec![existing.clone(), value]);
+                }
+            }
+        }
+        Ok(context)
+    }
+}
+
+struct ContextValue<'a>(&'a Rule);
+
+impl Serialize for ContextValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.0 {
+            Rule::Bool(b) => serializer.serialize_bool(*b),
+            Rule::Integer(i) => serializer.serialize_i32(*i),
+            Rule::Float(f) => serializer.serialize_f32(*f),
+            Rule::String(s) | Rule::Literal(s) => serializer.serialize_str(s),
+            Rule::Tuple(items) => serializer.collect_seq(items.iter().map(ContextValue)),
+            rule => rule.serialize(serializer),
+        }
+    }
+}
+
+impl Serialize for Context {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, ContextValue(v))))
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let escape = |s: &str, special: &str| {
+            s.chars().fold(String::new(), |mut escaped, c| {
+                if c == '\\' || special.contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+                escaped
+            })
+        };
+        let is_plain = |s: &str| matches!(Rule::from_literal(s), Ok(Rule::String(_)));
+        for (index, (key, value)) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:", escape(key, ":,"))?;
+            match value {
+                Rule::String(s) if s.is_empty() || !is_plain(s) || s.starts_with(['[', '"']) => {
+                    write!(f, "\"{}\"", escape(s, "\""))?;
+                }
+                Rule::String(s) => write!(f, "{}", escape(s, ","))?,
+                Rule::Tuple(items) => {
+                    let items: Vec<String> = items
+                        .iter()
+                        .map(|item| match item {
+                            Rule::String(s) if !is_plain(s) => format!("\\{}", escape(s, "|]")),
+                            Rule::String(s) => escape(s, "|]"),
+                            item => item.to_string(),
+                        })
+                        .collect();
+                    write!(f, "[{}]", items.join("|"))?;
+                }
+                value => write!(f, "{value}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_data(value: &Rule, nested: bool) -> bool {
+    match value {
+        Rule::String(s) => !s.starts_with('$') && !s.contains("{$"),
+        Rule::Bool(_) | Rule::Integer(_) => true,
+        Rule::Float(f) => f.is_finite(),
+        Rule::Tuple(items) => !nested && items.iter().all(|item| is_data(item, true)),
+        _ => false,
+    }
+}
+
+impl From<HashMap<String, String>> for Context {
+    fn from(map: HashMap<String, String>) -> Self {
+        map.into_iter()
+            .map(|(key, value)| {
+                let value = Rule::from_literal(&value).unwrap_or(Rule::String(value));
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+impl FromIterator<(String, Rule)> for Context {
+    fn from_iter<I: IntoIterator<Item = (String, Rule)>>(iter: I) -> Self {
+        let mut context = Context::default();
+        for (key, value) in iter {
+            context.insert(&key, value);
+        }
+        context
+    }
+}
+
+impl TryFrom<serde_json::Value> for Context {
+    type Error = Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        let serde_json::Value::Object(object) = value else {
+            return Err(Error::InvalidContextValue(String::new()));
+        };
+        let mut context = Context::default();
+        for (key, value) in object {
+            match value {
+                serde_json::Value::Object(_) => {
+                    let nested = Context::try_from(value).map_err(|e| match e {
+                        Error::InvalidContextValue(nested) => {
+                            Error::InvalidContextValue(format!("{key}.{nested}"))
+                        }
+                        e => e,
+                    })?;
+                    context.insert_nested(&key, &nested);
+                }
+                value => {
+                    let value = json_value(&key, value)?;
+                    context.insert(&key, value);
+                }
             }
-            let mut iter = pair.split(':');
-            let key = iter.next().ok_or(Error::CannotParse(String::from(s)))?;
-            let value = iter.next().ok_or(Error::CannotParse(String::from(s)))?;
-            context
-                .0
-                .push((String::from(key), Rule::from_literal(value)?));
         }
         Ok(context)
     }
-}
+}
+
+fn json_value(key: &str, value: serde_json::Value) -> Result<Rule, Error> {
+    let invalid = || Error::InvalidContextValue(key.to_string());
+    match value {
+        serde_json::Value::Bool(value) => Ok(Rule::Bool(value)),
+        serde_json::Value::String(value) => Ok(Rule::String(value)),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) => i32::try_from(value)
+                .map(Rule::Integer)
+                .map_err(|_| invalid()),
+            #[allow(clippy::cast_possible_truncation)]
+            None => number
+                .as_f64()
+                .map(|value| Rule::Float(value as f32))
+                .ok_or_else(invalid),
+        },
+        serde_json::Value::Array(values) => values
+            .into_iter()
+            .map(|value| json_value(key, value))
+            .collect::<Result<_, _>>()
+            .map(Rule::Tuple),
+        serde_json::Value::Null | serde_json::Value::Object(_) => Err(invalid()),
+    }
+}
+
+/// (De)serializes a map of attribute values as plain literals, the way
+/// context values are, so that strings are never parsed as expressions.
+pub(crate) mod literals {
+    use super::{json_value, ContextValue, Rule};
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S>(values: &BTreeMap<String, Rule>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(values.iter().map(|(k, v)| (k, ContextValue(v))))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BTreeMap<String, Rule>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| {
+                json_value(&key, value)
+                    .map_err(|_| D::Error::custom(format!("invalid value for `{key}`")))
+                    .map(|value| (key, value))
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_rule(s)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::String(s)
+                if s.is_empty()
+                    || s.chars()
+                        .any(|c| c.is_whitespace() || "()[]\";\\".contains(c))
+                    || !matches!(Rule::from_literal(s), Ok(Rule::String(_))) =>
+            {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Rule::Literal(s) => write!(
+                f,
+                "\"{}\"",
+                s.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('$', "\\$")
+            ),
+            Rule::String(s)
+            | Rule::If(s)
+            | Rule::And(s)
+            | Rule::Or(s)
+            | Rule::Eq(s)
+            | Rule::In(s)
+            | Rule::List(s)
+            | Rule::Concat(s)
+            | Rule::Require(s)
+            | Rule::Usage(s)
+            | Rule::ContainsAll(s)
+            | Rule::ContainsAny(s)
+            | Rule::Between(s)
+            | Rule::Mod(s)
+            | Rule::Min(s)
+            | Rule::Max(s)
+            | Rule::HasKey(s)
+            | Rule::Lambda(s)
+            | Rule::Map(s)
+            | Rule::ResourceRule(s)
+            | Rule::Try(s)
+            | Rule::Perms(s)
+            | Rule::Deny(s) => write!(f, "{s}"),
+            Rule::Bool(b) => write!(f, "{b}"),
+            Rule::Integer(i) => write!(f, "{i}"),
+            Rule::Float(v) => write!(f, "{v:?}"),
+            Rule::Tuple(children) => write!(
+                f,
+                "({})",
+                children
+                    .iter()
+                    .map(Rule::to_string)
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+        }
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RuleForm {
+    Expression(String),
+    Structured(StructuredRule),
+}
+
+#[derive(Deserialize)]
+struct StructuredRule {
+    op: String,
+    #[serde(default)]
+    args: Vec<Operand>,
+    #[serde(flatten)]
+    named: BTreeMap<String, Operand>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Operand {
+    Bool(bool),
+    Integer(i32),
+    Float(f32),
+    String(String),
+    List(Vec<Operand>),
+    Rule(StructuredRule),
+}
+
+impl TryFrom<StructuredRule> for Rule {
+    type Error = Error;
+
+    fn try_from(structured: StructuredRule) -> Result<Self, Self::Error> {
+        let StructuredRule {
+            op,
+            mut args,
+            mut named,
+        } = structured;
+        let head = operator(&op);
+        if matches!(head, Rule::String(_)) {
+            return Err(Error::InvalidStructuredRule(format!(
+                "unknown operator '{op}'"
+            )));
+        }
+        let slots: &[&str] = match op.as_str() {
+            "if" => &["cond", "then", "else"],
+            "eq" | "ne" | "and" | "or" => &["left", "right"],
+            "in" => &["value", "list"],
+            "between" | "between-exclusive" => &["value", "low", "high"],
+            "contains-all" | "contains-any" | "intersects" => &["list", "items"],
+            _ => &[],
+        };
+        args.extend(slots.iter().filter_map(|slot| named.remove(*slot)));
+        if let Some(field) = named.keys().next() {
+            return Err(Error::InvalidStructuredRule(format!(
+                "unknown field '{field}' for '{op}'"
+            )));
+        }
+        Ok(Rule::Tuple(
+            std::iter::once(Ok(head))
+                .chain(args.into_iter().map(Rule::try_from))
+                .collect::<Result<Vec<Rule>, Error>>()?,
+        ))
+    }
+}
+
+impl TryFrom<Operand> for Rule {
+    type Error = Error;
+
+    fn try_from(operand: Operand) -> Result<Self, Self::Error> {
+        Ok(match operand {
+            Operand::Bool(b) => Rule::Bool(b),
+            Operand::Integer(i) => Rule::Integer(i),
+            Operand::Float(f) => Rule::Float(f),
+            Operand::String(s) => Rule::String(s),
+            Operand::List(items) => Rule::Tuple(
+                std::iter::once(Ok(Rule::List(String::from("list"))))
+                    .chain(items.into_iter().map(Rule::try_from))
+                    .collect::<Result<Vec<Rule>, Error>>()?,
+            ),
+            Operand::Rule(structured) => Rule::try_from(structured)?,
+        })
+    }
+}
+
+impl<'a> Deserialize<'a> for Rule {
+    fn deserialize<D>(deserializer: D) -> Result<Rule, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let rule = match RuleForm::deserialize(deserializer)? {
+            RuleForm::Expression(s) => Rule::from_str(s.as_str()),
+            RuleForm::Structured(structured) => Rule::try_from(structured),
+        }
+        .map_err(serde::de::Error::custom)?;
+        // Operation aliases are only known once the whole config is read.
+        rule.mask_operation_names()
+            .validate()
+            .map_err(serde::de::Error::custom)?;
+        Ok(rule)
+    }
+}
+
+fn operator(name: &str) -> Rule {
+    let name = name.to_string();
+    match name.as_str() {
+        "if" => Rule::If(name),
+        "eq" | "ne" => Rule::Eq(name),
+        "list" => Rule::List(name),
+        "and" => Rule::And(name),
+        "or" => Rule::Or(name),
+        "in" => Rule::In(name),
+        "concat" => Rule::Concat(name),
+        "require" | "obligation" => Rule::Require(name),
+        "usage" => Rule::Usage(name),
+        "contains-all" => Rule::ContainsAll(name),
+        "contains-any" | "intersects" => Rule::ContainsAny(name),
+        "between" | "between-exclusive" => Rule::Between(name),
+        "mod" => Rule::Mod(name),
+        "min" => Rule::Min(name),
+        "max" => Rule::Max(name),
+        "has-key" => Rule::HasKey(name),
+        "fn" => Rule::Lambda(name),
+        "map" | "filter" | "any" => Rule::Map(name),
+        "resource-rule" => Rule::ResourceRule(name),
+        "try" => Rule::Try(name),
+        "perms" => Rule::Perms(name),
+        "deny" => Rule::Deny(name),
+        _ => Rule::String(name),
+    }
+}
+
+enum Token {
+    Open(bool),
+    Close(bool),
+    Atom(String),
+    Literal(String, bool),
+}
+
+fn read_quoted(chars: &mut std::str::Chars, verbatim: &mut bool) -> Option<String> {
+    let mut literal = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(literal),
+            '\\' => {
+                let c = chars.next()?;
+                *verbatim |= c == '$';
+                literal.push(c);
+            }
+            c => literal.push(c),
+        }
+    }
+}
+
+fn read_list(chars: &mut std::str::Chars) -> Option<Result<Vec<Rule>, Error>> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut literal = false;
+    let push = |items: &mut Vec<Result<Rule, Error>>, item: &mut String, literal: &mut bool| {
+        let item = std::mem::take(item);
+        items.push(if std::mem::take(literal) {
+            Ok(Rule::String(item))
+        } else {
+            Rule::from_literal(&item)
+        });
+    };
+    loop {
+        match chars.next()? {
+            ']' if items.is_empty() && item.is_empty() && !literal => return Some(Ok(Vec::new())),
+            ']' => {
+                push(&mut items, &mut item, &mut literal);
+                return Some(items.into_iter().collect());
+            }
+            '|' => push(&mut items, &mut item, &mut literal),
+            '\\' => {
+                item.push(chars.next()?);
+                literal = true;
+            }
+            c => item.push(c),
+        }
+    }
+}
+
+fn tokenize(rule: &str) -> Result<Vec<Token>, Error> {
+    let error = || Error::CannotParse(String::from(rule));
+    let mut tokens = Vec::new();
+    let mut buffer = String::new();
+    let mut escaped = false;
+    let mut verbatim = false;
+    let flush =
+        |buffer: &mut String, escaped: &mut bool, verbatim: &mut bool, tokens: &mut Vec<Token>| {
+            if *escaped {
+                tokens.push(Token::Literal(std::mem::take(buffer), *verbatim));
+            } else if !buffer.is_empty() {
+                tokens.push(Token::Atom(std::mem::take(buffer)));
+            }
+            *escaped = false;
+            *verbatim = false;
+        };
+    let mut chars = rule.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                flush(&mut buffer, &mut escaped, &mut verbatim, &mut tokens);
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '(' | '[' => {
+                flush(&mut buffer, &mut escaped, &mut verbatim, &mut tokens);
+                tokens.push(Token::Open(c == '['));
+            }
+            ')' | ']' => {
+                flush(&mut buffer, &mut escaped, &mut verbatim, &mut tokens);
+                tokens.push(Token::Close(c == ']'));
+            }
+            '\\' => {
+                let c = chars.next().ok_or_else(error)?;
+                verbatim |= c == '$';
+                buffer.push(c);
+                escaped = true;
+            }
+            '"' if buffer.is_empty() && !escaped => {
+                buffer = read_quoted(&mut chars, &mut verbatim).ok_or_else(error)?;
+                escaped = true;
+            }
+            c if c.is_whitespace() => flush(&mut buffer, &mut escaped, &mut verbatim, &mut tokens),
+            c => buffer.push(c),
+        }
+    }
+    flush(&mut buffer, &mut escaped, &mut verbatim, &mut tokens);
+    Ok(tokens)
+}
+
+/// Whether the rule text holds a `;` comment outside quoted and escaped text.
+pub(crate) fn has_comment(rule: &str) -> bool {
+    let mut chars = rule.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => return true,
+            '\\' => {
+                chars.next();
+            }
+            '"' if read_quoted(&mut chars, &mut false).is_none() => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn parse_rule(rule: &str) -> Result<Rule, Error> {
+    let error = || Error::CannotParse(String::from(rule));
+    let mut stack: Vec<(Vec<Rule>, bool)> = vec![(Vec::new(), false)];
+    for token in tokenize(rule)? {
+        match token {
+            Token::Open(false) => stack.push((Vec::new(), false)),
+            Token::Open(true) => stack.push((vec![Rule::List(String::from("list"))], true)),
+            Token::Close(bracket) => {
+                if stack.len() < 2 {
+                    return Err(error());
+                }
+                let (children, opened_with_bracket) = stack.pop().ok_or_else(error)?;
+                if opened_with_bracket != bracket {
+                    return Err(error());
+                }
+                stack
+                    .last_mut()
+                    .ok_or_else(error)?
+                    .0
+                    .push(Rule::Tuple(children));
+            }
+            Token::Atom(atom) => {
+                let (children, _) = stack.last_mut().ok_or_else(error)?;
+                let atom = match atom.strip_prefix("${").and_then(|a| a.strip_suffix('}')) {
+                    Some(name) if !name.is_empty() => format!("${name}"),
+                    _ => atom,
+                };
+                let node = match Rule::from_literal(&atom)? {
+                    Rule::String(atom) if children.is_empty() => operator(&atom),
+                    node => node,
+                };
+                children.push(node);
+            }
+            Token::Literal(literal, verbatim) => {
+                stack.last_mut().ok_or_else(error)?.0.push(if verbatim {
+                    Rule::Literal(literal)
+                } else {
+                    Rule::String(literal)
+                })
+            }
+        }
+    }
+    if stack.len() != 1 {
+        return Err(error());
+    }
+    stack
+        .pop()
+        .and_then(|(mut children, _)| children.pop())
+        .ok_or_else(error)
+}
+
+impl Rule {
+    pub fn from_literal(s: &str) -> Result<Rule, Error> {
+        if s.parse::<i32>().is_ok() {
+            Ok(Rule::Integer(s.parse::<i32>().ok().ok_or(
+                Error::CannotParseAs(Rule::Integer(0), s.to_string()),
+            )?))
+        } else if s.parse::<f32>().is_ok() {
+            Ok(Rule::Float(s.parse::<f32>().ok().ok_or(
+                Error::CannotParseAs(Rule::Float(0.0), s.to_string()),
+            )?))
+        } else if s.parse::<bool>().is_ok() {
+            Ok(Rule::Bool(s.parse::<bool>().ok().ok_or(
+                Error::CannotParseAs(Rule::Bool(false), s.to_string()),
+            )?))
+        } else {
+            Ok(Rule::String(s.to_string()))
+        }
+    }
+
+    #[must_use]
+    pub fn to_pretty_string(&self, indent: usize) -> String {
+        let mut output = String::new();
+        self.write_pretty(&mut output, indent, 0);
+        output
+    }
+
+    fn write_pretty(&self, output: &mut String, indent: usize, depth: usize) {
+        let Rule::Tuple(children) = self else {
+            output.push_str(&self.to_string());
+            return;
+        };
+        output.push('(');
+        if children
+            .iter()
+            .all(|child| !matches!(child, Rule::Tuple(_)))
+        {
+            output.push_str(
+                &children
+                    .iter()
+                    .map(Rule::to_string)
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            );
+        } else {
+            for (i, child) in children.iter().enumerate() {
+                if i > 0 {
+                    output.push('\n');
+                    output.push_str(&" ".repeat(indent * (depth + 1)));
+                }
+                child.write_pretty(output, indent, depth + 1);
+            }
+        }
+        output.push(')');
+    }
+
+    pub fn typecheck(&self, schema: &Schema) -> Result<Option<AttributeType>, Error> {
+        let children = match self {
+            Rule::Literal(_) => return Ok(Some(AttributeType::String)),
+            Rule::String(val) => {
+                return match val.strip_prefix('$') {
+                    Some(key) => schema
+                        .get(key)
+                        .map(Some)
+                        .ok_or(Error::UndeclaredAttribute(key.to_string())),
+                    None => Ok(Some(AttributeType::String)),
+                }
+            }
+            Rule::Bool(_) => return Ok(Some(AttributeType::Bool)),
+            Rule::Integer(_) => return Ok(Some(AttributeType::Integer)),
+            Rule::Float(_) => return Ok(Some(AttributeType::Float)),
+            Rule::Tuple(children) if matches!(children.first(), Some(Rule::Lambda(_))) => {
+                return Ok(None)
+            }
+            Rule::Tuple(children) => children,
+            _ => return Ok(None),
+        };
+        let types = children
+            .iter()
+            .skip(1)
+            .map(|child| child.typecheck(schema))
+            .collect::<Result<Vec<Option<AttributeType>>, Error>>()?;
+        let compatible = |l: Option<AttributeType>, r: Option<AttributeType>| {
+            l.is_none() || r.is_none() || l == r
+        };
+        let mismatch = || Error::TypeMismatch(self.clone());
+        match (children.first(), types.as_slice()) {
+            (Some(Rule::If(_)), [condition, then, otherwise]) => {
+                if !compatible(*condition, Some(AttributeType::Bool)) {
+                    return Err(mismatch());
+                }
+                Ok(if then == otherwise { *then } else { None })
+            }
+            (Some(Rule::Eq(_)), [l, r]) => {
+                if !compatible(*l, *r) || *l == Some(AttributeType::List) {
+                    return Err(mismatch());
+                }
+                Ok(Some(AttributeType::Bool))
+            }
+            (Some(Rule::And(_) | Rule::Or(_)), [l, r]) => {
+                if !compatible(*l, Some(AttributeType::Bool))
+                    || !compatible(*r, Some(AttributeType::Bool))
+                {
+                    return Err(mismatch());
+                }
+                Ok(Some(AttributeType::Bool))
+            }
+            (Some(Rule::ContainsAll(_) | Rule::ContainsAny(_)), [_, _]) => {
+                Ok(Some(AttributeType::Bool))
+            }
+            (Some(Rule::Between(_)), [value, low, high]) => {
+                if !compatible(*value, *low)
+                    || !compatible(*value, *high)
+                    || !compatible(*low, *high)
+                    || [value, low, high]
+                        .iter()
+                        .any(|t| matches!(t, Some(AttributeType::Bool | AttributeType::List)))
+                {
+                    return Err(mismatch());
+                }
+                Ok(Some(AttributeType::Bool))
+            }
+            (Some(Rule::Mod(_) | Rule::Min(_) | Rule::Max(_)), types) => {
+                let numeric = types
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<AttributeType>>();
+                match numeric.first() {
+                    Some(t @ (AttributeType::Integer | AttributeType::Float))
+                        if numeric.iter().all(|n| n == t) =>
+                    {
+                        Ok(Some(*t))
+                    }
+                    None => Ok(None),
+                    _ => Err(mismatch()),
+                }
+            }
+            (Some(Rule::In(_)), [l, r]) => {
+                if *l == Some(AttributeType::List) || !compatible(*r, Some(AttributeType::List)) {
+                    return Err(mismatch());
+                }
+                Ok(Some(AttributeType::Bool))
+            }
+            (Some(Rule::Require(_) | Rule::Deny(_)), _) => Ok(None),
+            (Some(Rule::Usage(_)), _) => Ok(Some(AttributeType::Integer)),
+            (Some(Rule::HasKey(_)), _) => Ok(Some(AttributeType::Bool)),
+            (Some(Rule::ResourceRule(_)), _) => Ok(Some(AttributeType::List)),
+            (Some(Rule::Try(_)), [expression, fallback]) => Ok(if expression == fallback {
+                *expression
+            } else {
+                None
+            }),
+            (Some(Rule::Map(keyword)), [list, _]) => {
+                if !compatible(*list, Some(AttributeType::List)) {
+                    return Err(mismatch());
+                }
+                Ok(Some(if keyword == "any" {
+                    AttributeType::Bool
+                } else {
+                    AttributeType::List
+                }))
+            }
+            (Some(Rule::Concat(_)), types) => {
+                if types.iter().all(|t| *t == Some(AttributeType::List)) {
+                    Ok(Some(AttributeType::List))
+                } else if types.contains(&Some(AttributeType::List)) {
+                    Err(mismatch())
+                } else {
+                    Ok(Some(AttributeType::String))
+                }
+            }
+            _ => Ok(Some(AttributeType::List)),
+        }
+    }
+
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(rule) = stack.pop() {
+            count += 1;
+            if let Rule::Tuple(children) = rule {
+                stack.extend(children);
+            }
+        }
+        count
+    }
+
+    /// Keywords and host function names called by the rule, in order of
+    /// first appearance.
+    #[must_use]
+    pub fn operators(&self) -> Vec<String> {
+        let mut operators = Vec::new();
+        let mut stack = vec![self];
+        while let Some(rule) = stack.pop() {
+            let Rule::Tuple(children) = rule else {
+                continue;
+            };
+            let operator = match (rule.is_valid_operation(), children.first()) {
+                (Ok(true) | Err(_), Some(head)) => Some(head.to_string()),
+                (Ok(false), Some(Rule::String(name))) if !name.starts_with('$') => {
+                    Some(name.clone())
+                }
+                _ => None,
+            };
+            if let Some(operator) = operator.filter(|operator| !operators.contains(operator)) {
+                operators.push(operator);
+            }
+            match rule.lambda() {
+                Some((_, body)) => stack.push(body),
+                None => stack.extend(children.iter().rev()),
+            }
+        }
+        operators
+    }
+
+    /// Whether everything the rule grants is behind `condition`: each branch
+    /// that can grant sits under an `if` whose condition requires it. Forms
+    /// other than `if`, `concat`, `deny` and empty lists count as granting.
+    #[must_use]
+    pub fn grants_only_if(&self, condition: &Rule) -> bool {
+        let Rule::Tuple(items) = self else {
+            return false;
+        };
+        match items.as_slice() {
+            [] | [Rule::List(_)] | [Rule::Deny(_), ..] => true,
+            [Rule::If(_), test, then, otherwise] => {
+                (test.requires(condition) || then.grants_only_if(condition))
+                    && otherwise.grants_only_if(condition)
+            }
+            [Rule::Concat(_), operands @ ..] => operands
+                .iter()
+                .all(|operand| operand.grants_only_if(condition)),
+            _ => false,
+        }
+    }
+
+    /// Whether the rule can only be true when `condition` is, with `eq`
+    /// operands in either order.
+    fn requires(&self, condition: &Rule) -> bool {
+        let (Rule::Tuple(items), Rule::Tuple(expected)) = (self, condition) else {
+            return false;
+        };
+        match (items.as_slice(), expected.as_slice()) {
+            ([Rule::Eq(_), left, right], [Rule::Eq(_), l, r]) => {
+                (left == l && right == r) || (left == r && right == l)
+            }
+            ([Rule::And(_), operands @ ..], _) => {
+                operands.iter().any(|operand| operand.requires(condition))
+            }
+            ([Rule::Or(_), operands @ ..], _) => {
+                operands.iter().all(|operand| operand.requires(condition))
+            }
+            _ => false,
+        }
+    }
+
+    #[must_use]
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables = Vec::new();
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables(&self, variables: &mut Vec<String>) {
+        match self {
+            Rule::String(val) if val.starts_with('$') => {
+                let key = val.trim_start_matches('$').to_string();
+                if !variables.contains(&key) {
+                    variables.push(key);
+                }
+            }
+            Rule::String(val) => {
+                for (_, key) in placeholders(val).0 {
+                    if !variables.iter().any(|v| v == key) {
+                        variables.push(key.to_string());
+                    }
+                }
+            }
+            Rule::Tuple(children) => {
+                if let [Rule::HasKey(_), Rule::String(key)] = children.as_slice() {
+                    if !variables.contains(key) {
+                        variables.push(key.clone());
+                    }
+                    return;
+                }
+                for child in children {
+                    child.collect_variables(variables);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[must_use]
+    pub fn optimize(&self) -> Rule {
+        self.fold_constants().simplify()
+    }
+
+    #[must_use]
+    pub fn fold_constants(&self) -> Rule {
+        let Rule::Tuple(children) = self else {
+            return self.clone();
+        };
+        if let Some(Rule::Lambda(_)) = children.first() {
+            return self.clone();
+        }
+        let folded = Rule::Tuple(children.iter().map(Rule::fold_constants).collect());
+        if !matches!(folded.is_valid_operation(), Ok(true)) || !folded.variables().is_empty() {
+            return folded;
+        }
+        match folded.eval(&Context(Vec::new())) {
+            Ok(value @ (Rule::Bool(_) | Rule::Integer(_) | Rule::Float(_))) => value,
+            Ok(Rule::String(value))
+                if !value.is_empty()
+                    && !value.starts_with('$')
+                    && matches!(Rule::from_literal(&value), Ok(Rule::String(_))) =>
+            {
+                Rule::String(value)
+            }
+            _ => folded,
+        }
+    }
+
+    /// Renames operations returned by the rule according to `aliases`. Only
+    /// lists in result position are rewritten, so data lists used in conditions
+    /// are left untouched.
+    #[must_use]
+    pub fn alias_operations(&self, aliases: &BTreeMap<String, String>) -> Rule {
+        self.map_operations(&|_, operation| {
+            vec![aliases.get(operation).map_or_else(
+                || Rule::String(operation.to_string()),
+                |canonical| Rule::String(canonical.clone()),
+            )]
+        })
+    }
+
+    /// Replaces `all` granted by the rule with the `operations` a resource
+    /// declares, in the same result positions as [`Rule::alias_operations`].
+    /// `all` in `deny` is kept.
+    #[must_use]
+    pub fn expand_all(&self, operations: &[String]) -> Rule {
+        self.map_operations(&|head, operation| match (head, operation) {
+            (Rule::List(_) | Rule::Perms(_), "all") => {
+                operations.iter().cloned().map(Rule::String).collect()
+            }
+            _ => vec![Rule::String(operation.to_string())],
+        })
+    }
+
+    fn map_operations(&self, f: &impl Fn(&Rule, &str) -> Vec<Rule>) -> Rule {
+        let Rule::Tuple(children) = self else {
+            return self.clone();
+        };
+        match children.as_slice() {
+            [head @ Rule::If(_), condition, then, otherwise] => Rule::Tuple(vec![
+                head.clone(),
+                condition.clone(),
+                then.map_operations(f),
+                otherwise.map_operations(f),
+            ]),
+            [head @ (Rule::Concat(_) | Rule::Try(_)), operands @ ..] => Rule::Tuple(
+                std::iter::once(head.clone())
+                    .chain(operands.iter().map(|operand| operand.map_operations(f)))
+                    .collect(),
+            ),
+            [head @ (Rule::List(_) | Rule::Perms(_) | Rule::Deny(_)), operands @ ..] => {
+                Rule::Tuple(
+                    std::iter::once(head.clone())
+                        .chain(operands.iter().flat_map(|operand| match operand {
+                            Rule::String(operation) => f(head, operation),
+                            operand => vec![operand.map_operations(f)],
+                        }))
+                        .collect(),
+                )
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// Literal operation names the rule can return, in the same result
+    /// positions as [`Rule::alias_operations`]. Variables, `all` and `none` are
+    /// skipped.
+    #[must_use]
+    pub fn granted_operations(&self) -> Vec<String> {
+        let mut operations = Vec::new();
+        self.collect_granted_operations(&mut operations);
+        operations
+    }
+
+    fn collect_granted_operations(&self, operations: &mut Vec<String>) {
+        let Rule::Tuple(children) = self else {
+            return;
+        };
+        match children.as_slice() {
+            [Rule::If(_), _, then, otherwise] => {
+                then.collect_granted_operations(operations);
+                otherwise.collect_granted_operations(operations);
+            }
+            [Rule::Concat(_) | Rule::Try(_), operands @ ..] => {
+                for operand in operands {
+                    operand.collect_granted_operations(operations);
+                }
+            }
+            [Rule::List(_) | Rule::Perms(_) | Rule::Deny(_), operands @ ..] => {
+                for operand in operands {
+                    match operand {
+                        Rule::String(operation)
+                            if operation == "all"
+                                || operation == "none"
+                                || operation.starts_with('$') => {}
+                        Rule::String(operation) => {
+                            if !operations.contains(operation) {
+                                operations.push(operation.clone());
+                            }
+                        }
+                        operand => operand.collect_granted_operations(operations),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[must_use]
+    pub fn bind(&self, context: &Context) -> Rule {
+        match self {
+            Rule::String(s) if s.starts_with('$') => match context.get(s.trim_start_matches('$')) {
+                Ok(value) if !is_data(value, false) => self.clone(),
+                Ok(Rule::Tuple(values)) => Rule::Tuple(
+                    std::iter::once(Rule::List(String::from("list")))
+                        .chain(values.iter().cloned())
+                        .collect(),
+                ),
+                Ok(value) => value.clone(),
+                Err(_) => self.clone(),
+            },
+            Rule::Tuple(_) if self.lambda().is_some() => self.clone(),
+            Rule::Tuple(children) => {
+                Rule::Tuple(children.iter().map(|child| child.bind(context)).collect())
+            }
+            other => other.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn simplify(&self) -> Rule {
+        let Rule::Tuple(children) = self else {
+            return self.clone();
+        };
+        let children: Vec<Rule> = children.iter().map(Rule::simplify).collect();
+        match (children.first(), children.len()) {
+            (Some(Rule::If(_)), 4) => match &children[1] {
+                Rule::Bool(true) => children[2].clone(),
+                Rule::Bool(false) => children[3].clone(),
+                _ if children[2] == children[3] => children[2].clone(),
+                _ => Rule::Tuple(children),
+            },
+            (Some(Rule::And(_)), 3) => {
+                let (left, right) = (&children[1], &children[2]);
+                if !left.is_boolean() || !right.is_boolean() {
+                    return Rule::Tuple(children);
+                }
+                match (left, right) {
+                    (Rule::Bool(false), _) | (_, Rule::Bool(false)) => Rule::Bool(false),
+                    (Rule::Bool(true), other) | (other, Rule::Bool(true)) => other.clone(),
+                    (l, r) if l == r || r.is_operation_with(&Rule::Or, l) => l.clone(),
+                    (l, r) if l.is_operation_with(&Rule::Or, r) => r.clone(),
+                    _ => Rule::Tuple(children),
+                }
+            }
+            (Some(Rule::Or(_)), 3) => {
+                let (left, right) = (&children[1], &children[2]);
+                if !left.is_boolean() || !right.is_boolean() {
+                    return Rule::Tuple(children);
+                }
+                match (left, right) {
+                    (Rule::Bool(true), _) | (_, Rule::Bool(true)) => Rule::Bool(true),
+                    (Rule::Bool(false), other) | (other, Rule::Bool(false)) => other.clone(),
+                    (l, r) if l == r || r.is_operation_with(&Rule::And, l) => l.clone(),
+                    (l, r) if l.is_operation_with(&Rule::And, r) => r.clone(),
+                    _ => Rule::Tuple(children),
+                }
+            }
+            _ => Rule::Tuple(children),
+        }
+    }
+
+    fn is_boolean(&self) -> bool {
+        match self {
+            Rule::Bool(_) => true,
+            Rule::Tuple(children) => {
+                matches!(
+                    children.first(),
+                    Some(
+                        Rule::Eq(_)
+                            | Rule::And(_)
+                            | Rule::Or(_)
+                            | Rule::In(_)
+                            | Rule::ContainsAll(_)
+                            | Rule::ContainsAny(_)
+                            | Rule::Between(_)
+                            | Rule::HasKey(_)
+                    )
+                ) || matches!(children.first(), Some(Rule::Map(keyword)) if keyword == "any")
+            }
+            _ => false,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.to_string().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn is_operation_with(&self, operator: &dyn Fn(String) -> Rule, operand: &Rule) -> bool {
+        let Rule::Tuple(children) = self else {
+            return false;
+        };
+        children.len() == 3
+            && std::mem::discriminant(&children[0])
+                == std::mem::discriminant(&operator(String::new()))
+            && (children[1] == *operand || children[2] == *operand)
+    }
+
+    pub fn resolve(
+        &self,
+        lookup: &mut impl FnMut(&str) -> Result<Rule, Error>,
+    ) -> Result<Rule, Error> {
+        let Rule::Tuple(children) = self else {
+            return Ok(self.clone());
+        };
+        if let Some(Rule::ResourceRule(_)) = children.first() {
+            return match children.as_slice() {
+                [_, Rule::String(path)] => lookup(path),
+                _ => Err(Error::InvalidResourceRuleStatement(self.clone())),
+            };
+        }
+        Ok(Rule::Tuple(
+            children
+                .iter()
+                .map(|child| child.resolve(lookup))
+                .collect::<Result<Vec<Rule>, Error>>()?,
+        ))
+    }
+
+    /// Dry-runs the parts of the rule that do not depend on the request.
+    /// Operands read from the context or returned by host functions, which
+    /// are not registered yet, are only checked at evaluation time.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.resolve(&mut |_| Ok(Rule::Tuple(vec![Rule::List(String::from("list"))])))?
+            .dry_run()
+    }
+
+    fn dry_run(&self) -> Result<(), Error> {
+        if !self.reads_runtime_values() {
+            return self
+                .eval_in(
+                    &Context(Vec::new()),
+                    &mut Environment::default().with_counters(&NoCounters),
+                )
+                .map(|_| ());
+        }
+        let Rule::Tuple(children) = self else {
+            return Ok(());
+        };
+        if let Some((_, body)) = self.lambda() {
+            return body.check_arity();
+        }
+        self.is_valid_operation()?;
+        let Some((head @ (Rule::Perms(_) | Rule::Deny(_)), operands)) = children.split_first()
+        else {
+            return children.iter().skip(1).try_for_each(Rule::dry_run);
+        };
+        // Literal permission names are still checked, only `$` operands wait.
+        let (runtime, literals): (Vec<&Rule>, Vec<&Rule>) = operands
+            .iter()
+            .partition(|operand| operand.reads_runtime_values());
+        if !literals.is_empty() {
+            Rule::Tuple(std::iter::once(head).chain(literals).cloned().collect()).dry_run()?;
+        }
+        runtime.into_iter().try_for_each(Rule::dry_run)
+    }
+
+    /// Replaces the literal `perms` and `deny` operands that are not operation
+    /// names with `none`.
+    fn mask_operation_names(&self) -> Rule {
+        let Rule::Tuple(children) = self else {
+            return self.clone();
+        };
+        let names = matches!(children.first(), Some(Rule::Perms(_) | Rule::Deny(_)));
+        Rule::Tuple(
+            children
+                .iter()
+                .map(|child| match child {
+                    Rule::String(name)
+                        if names
+                            && !name.starts_with('$')
+                            && permission::Permission::from_name(name).is_none() =>
+                    {
+                        Rule::String(String::from("none"))
+                    }
+                    child => child.mask_operation_names(),
+                })
+                .collect(),
+        )
+    }
+
+    fn check_arity(&self) -> Result<(), Error> {
+        let Rule::Tuple(children) = self else {
+            return Ok(());
+        };
+        if let Some((_, body)) = self.lambda() {
+            return body.check_arity();
+        }
+        self.is_valid_operation()?;
+        children.iter().try_for_each(Rule::check_arity)
+    }
+
+    fn reads_runtime_values(&self) -> bool {
+        match self {
+            Rule::String(s) => s.starts_with('$'),
+            Rule::Tuple(children) => match self.lambda() {
+                Some((_, body)) => body.reads_runtime_values(),
+                None => {
+                    matches!(children.first(), Some(Rule::String(name)) if !name.starts_with('$'))
+                        || children.iter().any(Rule::reads_runtime_values)
+                }
+            },
+            _ => false,
+        }
+    }
+
+    pub fn eval(&self, context: &Context) -> Result<Rule, Error> {
+        self.eval_with_max_depth(context, DEFAULT_MAX_DEPTH)
+    }
+
+    pub fn eval_with_max_depth(&self, context: &Context, max_depth: usize) -> Result<Rule, Error> {
+        self.eval_in(
+            context,
+            &mut Environment::default().with_max_depth(max_depth),
+        )
+    }
+
+    pub fn eval_with_budget(&self, context: &Context, budget: &mut Budget) -> Result<Rule, Error> {
+        let mut environment = Environment::default().with_budget(std::mem::take(budget));
+        let result = self.eval_in(context, &mut environment);
+        *budget = environment.budget;
+        result
+    }
+
+    pub fn eval_in(&self, context: &Context, environment: &mut Environment) -> Result<Rule, Error> {
+        let context = environment.provide(self, context)?;
+        let context = context.as_ref();
+        let max_depth = environment.max_depth;
+        let mut tasks = vec![Task::Eval(self, 0)];
+        let mut values: Vec<Rule> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Eval(rule, depth) => {
+                    if depth > max_depth {
+                        return Err(Error::MaxDepthExceeded(max_depth));
+                    }
+                    environment.budget.consume()?;
+                    if let Some(value) = environment.bound(rule) {
+                        values.push(value.clone());
+                        continue;
+                    }
+                    let Rule::Tuple(children) = rule else {
+                        values.push(rule.eval_atom(context, environment.strict)?);
+                        continue;
+                    };
+                    let is_host_call = matches!(
+                        (children.first(), environment.functions),
+                        (Some(Rule::String(name)), Some(functions)) if functions.contains(name)
+                    );
+                    if !is_host_call && !rule.is_valid_operation()? {
+                        values.push(Rule::Tuple(vec![]));
+                        continue;
+                    }
+                    if let Some(Rule::Lambda(_)) = children.first() {
+                        values.push(rule.clone());
+                        continue;
+                    }
+                    if let Some(value) = environment.memoized(rule, context) {
+                        values.push(value);
+                        continue;
+                    }
+                    if let [Rule::Try(_), expression, fallback] = children.as_slice() {
+                        environment.max_depth = max_depth.saturating_sub(depth + 1);
+                        let result = match expression.eval_in(context, environment) {
+                            Err(e) if e.is_recoverable() => fallback.eval_in(context, environment),
+                            result => result,
+                        };
+                        environment.max_depth = max_depth;
+                        values.push(result.map_err(|e| match e {
+                            Error::MaxDepthExceeded(_) => Error::MaxDepthExceeded(max_depth),
+                            e => e,
+                        })?);
+                        continue;
+                    }
+                    tasks.push(Task::Apply(rule));
+                    for child in children.iter().skip(1).rev() {
+                        tasks.push(Task::Eval(child, depth + 1));
+                    }
+                }
+                Task::Apply(rule) => {
+                    let Rule::Tuple(children) = rule else {
+                        unreachable!("only tuples are applied");
+                    };
+                    let operands = values.split_off(values.len() - (children.len() - 1));
+                    let value =
+                        Rule::apply_head(&children[0], operands, context, environment, &|| {
+                            rule.clone()
+                        })?;
+                    environment.memoize(rule, context, &value);
+                    if let Some(trace) = environment.trace.as_mut() {
+                        trace.push((rule.clone(), value.clone()));
+                    }
+                    values.push(value);
+                }
+            }
+        }
+        values
+            .pop()
+            .ok_or_else(|| Error::CannotParse(self.to_string()))
+    }
+
+    pub(crate) fn eval_atom(&self, context: &Context, strict: bool) -> Result<Rule, Error> {
+        match self {
+            Rule::String(val) if val.starts_with('$') => {
+                Rule::lookup(val.trim_start_matches('$'), context, strict)
+            }
+            Rule::Literal(val) => Ok(Rule::String(val.clone())),
+            Rule::String(val) if val.contains("{$") => {
+                let (segments, tail) = placeholders(val);
+                let mut string = String::new();
+                for (literal, key) in segments {
+                    string.push_str(literal);
+                    match Rule::lookup(key, context, strict)? {
+                        Rule::String(s) => string.push_str(&s),
+                        value => string.push_str(&value.to_string()),
+                    }
+                }
+                string.push_str(tail);
+                Ok(Rule::String(string))
+            }
+            val => Ok(val.clone()),
+        }
+    }
+
+    fn lookup(key: &str, context: &Context, strict: bool) -> Result<Rule, Error> {
+        match context.0.iter().find(|(k, _)| k == key) {
+            Some((_, val)) => Ok(val.clone()),
+            None => match context.missing_segment(key) {
+                Some(segment) => Err(Error::MissingSegment(key.to_string(), segment)),
+                None if strict => Err(Error::KeyNotInContext(key.to_string())),
+                None => Ok(Rule::String(String::new())),
+            },
+        }
+    }
+
+    pub(crate) fn is_valid_operation(&self) -> Result<bool, Error> {
+        let Rule::Tuple(children) = self else {
+            return Ok(false);
+        };
+        match children.first() {
+            Some(Rule::If(_)) if children.len() != 4 => {
+                Err(Error::InvalidIfStatement(self.clone()))
+            }
+            Some(Rule::Eq(_)) if children.len() != 3 => {
+                Err(Error::InvalidEqStatement(self.clone()))
+            }
+            Some(Rule::And(_)) if children.len() != 3 => {
+                Err(Error::InvalidAndStatement(self.clone()))
+            }
+            Some(Rule::Or(_)) if children.len() != 3 => {
+                Err(Error::InvalidOrStatement(self.clone()))
+            }
+            Some(Rule::In(_)) if children.len() != 3 => {
+                Err(Error::InvalidInStatement(self.clone()))
+            }
+            Some(Rule::Concat(_)) if children.len() < 2 => {
+                Err(Error::InvalidConcatStatement(self.clone()))
+            }
+            Some(Rule::Require(_)) if children.len() < 2 => {
+                Err(Error::InvalidRequireStatement(self.clone()))
+            }
+            Some(Rule::Deny(_)) if children.len() < 2 => {
+                Err(Error::InvalidDenyStatement(self.clone()))
+            }
+            Some(Rule::Usage(_)) if children.len() != 4 => {
+                Err(Error::InvalidUsageStatement(self.clone()))
+            }
+            Some(Rule::ContainsAll(_) | Rule::ContainsAny(_)) if children.len() != 3 => {
+                Err(Error::InvalidContainsStatement(self.clone()))
+            }
+            Some(Rule::Between(_)) if children.len() != 4 => {
+                Err(Error::InvalidBetweenStatement(self.clone()))
+            }
+            Some(Rule::Mod(_)) if children.len() != 3 => {
+                Err(Error::InvalidArithmeticStatement(self.clone()))
+            }
+            Some(Rule::Min(_) | Rule::Max(_)) if children.len() < 2 => {
+                Err(Error::InvalidArithmeticStatement(self.clone()))
+            }
+            Some(Rule::HasKey(_)) if !matches!(children.as_slice(), [_, Rule::String(_)]) => {
+                Err(Error::InvalidHasKeyStatement(self.clone()))
+            }
+            Some(Rule::Lambda(_)) if self.lambda().is_none() => {
+                Err(Error::InvalidLambdaStatement(self.clone()))
+            }
+            Some(Rule::Map(_)) if children.len() != 3 => {
+                Err(Error::InvalidLambdaStatement(self.clone()))
+            }
+            Some(Rule::ResourceRule(_)) if !matches!(children.as_slice(), [_, Rule::String(_)]) => {
+                Err(Error::InvalidResourceRuleStatement(self.clone()))
+            }
+            Some(Rule::Try(_)) if children.len() != 3 => {
+                Err(Error::InvalidTryStatement(self.clone()))
+            }
+            Some(
+                Rule::If(_)
+                | Rule::Eq(_)
+                | Rule::And(_)
+                | Rule::Or(_)
+                | Rule::In(_)
+                | Rule::List(_)
+                | Rule::Concat(_)
+                | Rule::Require(_)
+                | Rule::Usage(_)
+                | Rule::ContainsAll(_)
+                | Rule::ContainsAny(_)
+                | Rule::Between(_)
+                | Rule::Mod(_)
+                | Rule::Min(_)
+                | Rule::Max(_)
+                | Rule::HasKey(_)
+                | Rule::Lambda(_)
+                | Rule::Map(_)
+                | Rule::ResourceRule(_)
+                | Rule::Try(_)
+                | Rule::Perms(_)
+                | Rule::Deny(_),
+            ) => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    fn lambda(&self) -> Option<(&str, &Rule)> {
+        let Rule::Tuple(children) = self else {
+            return None;
+        };
+        let [Rule::Lambda(_), Rule::Tuple(params), body] = children.as_slice() else {
+            return None;
+        };
+        match params.as_slice() {
+            [Rule::String(param)] if !param.starts_with('$') => Some((param, body)),
+            _ => None,
+        }
+    }
+
+    /// Applies an expression's head to its evaluated operands, calling host
+    /// functions, counters and lambdas from the environment. `node` rebuilds
+    /// the whole expression for error reports.
+    pub(crate) fn apply_head(
+        head: &Rule,
+        mut operands: Vec<Rule>,
+        context: &Context,
+        environment: &mut Environment,
+        node: &dyn Fn() -> Rule,
+    ) -> Result<Rule, Error> {
+        if environment.numeric_coercion
+            && matches!(head, Rule::Eq(_) | Rule::In(_) | Rule::Between(_))
+        {
+            operands = widen_numbers(operands);
+        }
+        let host_call = match (head, environment.functions) {
+            (Rule::String(name), Some(functions)) => functions.call(name, &operands),
+            _ => None,
+        };
+        match (host_call, head, environment.counters) {
+            (Some(result), _, _) => result,
+            (None, Rule::Map(keyword), _) => {
+                Rule::apply_lambda(keyword, operands, context, environment, node)
+            }
+            (None, Rule::Usage(_), Some(counters)) => {
+                let [Rule::String(counter), key, Rule::String(window)] =
+                    <[Rule; 3]>::try_from(operands)
+                        .map_err(|_| Error::InvalidUsageStatement(node()))?
+                else {
+                    return Err(Error::InvalidUsageStatement(node()));
+                };
+                Ok(Rule::Integer(counters.usage(&counter, &key, &window)?))
+            }
+            (None, Rule::HasKey(_), _) => {
+                let [Rule::String(key)] = operands.as_slice() else {
+                    return Err(Error::InvalidHasKeyStatement(node()));
+                };
+                let prefix = format!("{key}.");
+                Ok(Rule::Bool(
+                    context.keys().any(|k| k == key || k.starts_with(&prefix)),
+                ))
+            }
+            _ => Rule::apply_keyword(head, operands, node),
+        }
+    }
+
+    fn apply_lambda(
+        keyword: &str,
+        operands: Vec<Rule>,
+        context: &Context,
+        environment: &mut Environment,
+        node: &dyn Fn() -> Rule,
+    ) -> Result<Rule, Error> {
+        let invalid = || Error::InvalidLambdaStatement(node());
+        let [list, lambda] = <[Rule; 2]>::try_from(operands).map_err(|_| invalid())?;
+        let (param, body) = lambda.lambda().ok_or_else(invalid)?;
+        let items = match list {
+            Rule::Tuple(items) => items,
+            scalar => vec![scalar],
+        };
+        let mut results = Vec::new();
+        for item in items {
+            environment.bindings.push((param.to_string(), item.clone()));
+            let result = body.eval_in(context, environment);
+            environment.bindings.pop();
+            match (keyword, result?) {
+                ("map", result) => results.push(result),
+                ("filter", Rule::Bool(true)) => results.push(item),
+                ("any", Rule::Bool(true)) => return Ok(Rule::Bool(true)),
+                (_, Rule::Bool(false)) => {}
+                (_, result) => return Err(Error::InvalidIfCondition(result)),
+            }
+        }
+        Ok(if keyword == "any" {
+            Rule::Bool(false)
+        } else {
+            Rule::Tuple(results)
+        })
+    }
+
+    /// Applies a keyword to its evaluated operands. `node` rebuilds the whole
+    /// expression for error reports.
+    fn apply_keyword(
+        head: &Rule,
+        operands: Vec<Rule>,
+        node: &dyn Fn() -> Rule,
+    ) -> Result<Rule, Error> {
+        match head {
+            Rule::If(_) => {
+                let [condition, then, otherwise] = <[Rule; 3]>::try_from(operands)
+                    .map_err(|_| Error::InvalidIfStatement(node()))?;
+                match condition {
+                    Rule::Bool(false) => Ok(otherwise),
+                    Rule::Bool(true) => Ok(then),
+                    _ => Err(Error::InvalidIfCondition(condition)),
+                }
+            }
+            Rule::Eq(keyword) => {
+                let [left, right] = <[Rule; 2]>::try_from(operands)
+                    .map_err(|_| Error::InvalidEqStatement(node()))?;
+                let equal = match (left, right) {
+                    (Rule::String(l), Rule::String(r)) => l == r,
+                    (Rule::Integer(l), Rule::Integer(r)) => l == r,
+                    (Rule::Float(l), Rule::Float(r)) => (l - r).abs() < 0.1, // Adjust tolerance
+                    (Rule::Bool(l), Rule::Bool(r)) => l == r,
+                    (l, r) => return Err(Error::CannotCompare(l, r)),
+                };
+                Ok(Rule::Bool(equal != (keyword == "ne")))
+            }
+            Rule::List(_) => Ok(Rule::Tuple(operands)),
+            Rule::Perms(_) => {
+                if let Some(invalid) = operands.iter().find(|operand| match operand {
+                    Rule::String(s) => permission::Permission::from_name(s).is_none(),
+                    operand => {
+                        Obligation::try_from(*operand).is_err() && !permission::is_deny(operand)
+                    }
+                }) {
+                    return Err(Error::InvalidPermission(invalid.clone()));
+                }
+                Ok(Rule::Tuple(operands))
+            }
+            Rule::Deny(name) => {
+                if let Some(invalid) = operands.iter().find(|operand| match operand {
+                    Rule::String(s) => permission::Permission::from_name(s).is_none(),
+                    _ => true,
+                }) {
+                    return Err(Error::InvalidPermission(invalid.clone()));
+                }
+                Ok(Rule::Tuple(
+                    std::iter::once(Rule::Deny(name.clone()))
+                        .chain(operands)
+                        .collect(),
+                ))
+            }
+            Rule::And(_) => {
+                let [left, right] = <[Rule; 2]>::try_from(operands)
+                    .map_err(|_| Error::InvalidAndStatement(node()))?;
+                match (left, right) {
+                    (Rule::Bool(l), Rule::Bool(r)) => Ok(Rule::Bool(l && r)),
+                    (l, r) => Err(Error::CannotCompare(l, r)),
+                }
+            }
+            Rule::Or(_) => {
+                let [left, right] = <[Rule; 2]>::try_from(operands)
+                    .map_err(|_| Error::InvalidOrStatement(node()))?;
+                match (left, right) {
+                    (Rule::Bool(l), Rule::Bool(r)) => Ok(Rule::Bool(l || r)),
+                    (l, r) => Err(Error::CannotCompare(l, r)),
+                }
+            }
+            Rule::In(_) => {
+                let [left, right] = <[Rule; 2]>::try_from(operands)
+                    .map_err(|_| Error::InvalidInStatement(node()))?;
+                match (left, right) {
+                    (
+                        l @ (Rule::String(_) | Rule::Integer(_) | Rule::Float(_) | Rule::Bool(_)),
+                        Rule::Tuple(ref r),
+                    ) => Ok(Rule::Bool(r.contains(&l))),
+                    (_, _) => Err(Error::InvalidInStatement(node())),
+                }
+            }
+            Rule::Concat(_) => {
+                if operands
+                    .iter()
+                    .all(|operand| matches!(operand, Rule::Tuple(_)))
+                {
+                    let mut list = Vec::new();
+                    for operand in operands {
+                        match operand {
+                            // Deny and obligation forms stay nested so their
+                            // operands are not read as granted operations.
+                            Rule::Tuple(items)
+                                if matches!(
+                                    items.first(),
+                                    Some(Rule::Deny(_) | Rule::Require(_))
+                                ) =>
+                            {
+                                list.push(Rule::Tuple(items));
+                            }
+                            Rule::Tuple(items) => list.extend(items),
+                            _ => {}
+                        }
+                    }
+                    return Ok(Rule::Tuple(list));
+                }
+                let mut string = String::new();
+                for operand in operands {
+                    match operand {
+                        Rule::String(s) => string.push_str(&s),
+                        Rule::Integer(i) => string.push_str(&i.to_string()),
+                        Rule::Float(f) => string.push_str(&f.to_string()),
+                        Rule::Bool(b) => string.push_str(&b.to_string()),
+                        _ => return Err(Error::InvalidConcatStatement(node())),
+                    }
+                }
+                Ok(Rule::String(string))
+            }
+            Rule::Require(name) => Ok(Rule::Tuple(
+                std::iter::once(Rule::Require(name.clone()))
+                    .chain(operands)
+                    .collect(),
+            )),
+            Rule::Usage(_) => Err(Error::NoCounterProvider(node())),
+            Rule::ResourceRule(_) => match operands.as_slice() {
+                [Rule::String(path)] => Err(Error::UnresolvedResourceRule(path.clone())),
+                _ => Err(Error::InvalidResourceRuleStatement(node())),
+            },
+            head @ (Rule::ContainsAll(_) | Rule::ContainsAny(_)) => {
+                let [haystack, needles] = <[Rule; 2]>::try_from(operands)
+                    .map_err(|_| Error::InvalidContainsStatement(node()))?
+                    .map(|operand| match operand {
+                        Rule::Tuple(items) => items,
+                        scalar => vec![scalar],
+                    });
+                let mut found = needles.iter().map(|needle| haystack.contains(needle));
+                Ok(Rule::Bool(if matches!(head, Rule::ContainsAll(_)) {
+                    found.all(|f| f)
+                } else {
+                    found.any(|f| f)
+                }))
+            }
+            Rule::Between(keyword) => {
+                let [value, low, high] = <[Rule; 3]>::try_from(operands)
+                    .map_err(|_| Error::InvalidBetweenStatement(node()))?;
+                let (above, below) = match (&value, &low, &high) {
+                    (Rule::Integer(v), Rule::Integer(l), Rule::Integer(h)) => (v.cmp(l), v.cmp(h)),
+                    (Rule::String(v), Rule::String(l), Rule::String(h)) => (v.cmp(l), v.cmp(h)),
+                    (Rule::Float(v), Rule::Float(l), Rule::Float(h)) => (
+                        v.partial_cmp(l)
+                            .ok_or(Error::InvalidBetweenStatement(node()))?,
+                        v.partial_cmp(h)
+                            .ok_or(Error::InvalidBetweenStatement(node()))?,
+                    ),
+                    _ => return Err(Error::InvalidBetweenStatement(node())),
+                };
+                Ok(Rule::Bool(if keyword == "between-exclusive" {
+                    above.is_gt() && below.is_lt()
+                } else {
+                    above.is_ge() && below.is_le()
+                }))
+            }
+            Rule::Mod(_) => match <[Rule; 2]>::try_from(operands) {
+                Ok([Rule::Integer(l), Rule::Integer(r)]) => l
+                    .checked_rem_euclid(r)
+                    .map(Rule::Integer)
+                    .ok_or(Error::InvalidArithmeticStatement(node())),
+                Ok([Rule::Float(l), Rule::Float(r)]) if r != 0.0 => {
+                    Ok(Rule::Float(l.rem_euclid(r)))
+                }
+                _ => Err(Error::InvalidArithmeticStatement(node())),
+            },
+            head @ (Rule::Min(_) | Rule::Max(_)) => {
+                let is_min = matches!(head, Rule::Min(_));
+                let mut operands = operands.into_iter();
+                let first = operands
+                    .next()
+                    .ok_or(Error::InvalidArithmeticStatement(node()))?;
+                operands
+                    .try_fold(first, |best, operand| match (&best, &operand) {
+                        (Rule::Integer(b), Rule::Integer(o)) => {
+                            Ok(if (o < b) == is_min { operand } else { best })
+                        }
+                        (Rule::Float(b), Rule::Float(o)) => {
+                            Ok(if (o < b) == is_min { operand } else { best })
+                        }
+                        _ => Err(Error::InvalidArithmeticStatement(node())),
+                    })
+                    .and_then(|result| match result {
+                        Rule::Integer(_) | Rule::Float(_) => Ok(result),
+                        _ => Err(Error::InvalidArithmeticStatement(node())),
+                    })
+            }
+            _ => Ok(Rule::Tuple(vec![])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Program;
+
+    #[test]
+    fn test_parse_context_ok() {
+        assert_eq!(
+            Context::from_str("name:John,age:20,weight:70.5,active:true"),
+            Ok(Context(vec![
+                (String::from("name"), Rule::String(String::from("John"))),
+                (String::from("age"), Rule::Integer(20)),
+                (String::from("weight"), Rule::Float(70.5)),
+                (String::from("active"), Rule::Bool(true)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_literal_ok() {
+        assert_eq!(
+            Rule::from_literal("John"),
+            Ok(Rule::String(String::from("John")))
+        );
+        assert_eq!(Rule::from_literal("20"), Ok(Rule::Integer(20)));
+        assert_eq!(Rule::from_literal("70.5"), Ok(Rule::Float(70.5)));
+        assert_eq!(Rule::from_literal("true"), Ok(Rule::Bool(true)));
+        assert_eq!(Rule::from_literal(""), Ok(Rule::String(String::new())));
+        assert_eq!(
+            Rule::from_literal("0d!"),
+            Ok(Rule::String(String::from("0d!")))
+        );
+    }
+
+    #[test]
+    fn test_parse_rule_ok() {
+        assert_eq!(Rule::from_str("()"), Ok(Rule::Tuple(vec![])));
+        assert_eq!(
+            Rule::from_str("(if)"),
+            Ok(Rule::Tuple(vec![Rule::If(String::from("if")),]))
+        );
+        assert_eq!(
+            Rule::from_str("(or true true)"),
+            Ok(Rule::Tuple(vec![
+                Rule::Or(String::from("or")),
+                Rule::Bool(true),
+                Rule::Bool(true),
+            ]))
+        );
+        assert_eq!(
+            Rule::from_str("(and true true)"),
+            Ok(Rule::Tuple(vec![
+                Rule::And(String::from("and")),
+                Rule::Bool(true),
+                Rule::Bool(true),
+            ]))
+        );
+        assert_eq!(
+            Rule::from_str("(if (eq $name true) (list create) (list))"),
+            Ok(Rule::Tuple(vec![
+                Rule::If(String::from("if")),
+                Rule::Tuple(vec![
+                    Rule::Eq(String::from("eq")),
+                    Rule::String(String::from("$name")),
+                    Rule::Bool(true),
+                ]),
+                Rule::Tuple(vec![
+                    Rule::List(String::from("list")),
+                    Rule::String(String::from("create")),
+                ]),
+                Rule::Tuple(vec![Rule::List(String::from("list")),]),
+            ]))
+        );
+        assert_eq!(
+            Rule::from_str("(if (eq $name true) (list create) (list))"),
+            Ok(Rule::Tuple(vec![
+                Rule::If(String::from("if")),
+                Rule::Tuple(vec![
+                    Rule::Eq(String::from("eq")),
+                    Rule::String(String::from("$name")),
+                    Rule::Bool(true),
+                ]),
+                Rule::Tuple(vec![
+                    Rule::List(String::from("list")),
+                    Rule::String(String::from("create")),
+                ]),
+                Rule::Tuple(vec![Rule::List(String::from("list")),]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_rule_err() {
+        assert_eq!(Rule::from_str(""), Err(Error::CannotParse(String::new())));
+        assert_eq!(
+            Rule::from_str("(if (eq )) (list create) (list))"),
+            Err(Error::CannotParse(
+                "(if (eq )) (list create) (list))".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_in_ok() {
+        assert_eq!(
+            Rule::from_str("(  in   john   ( list ) )")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str(
+                "(
+                in 10 (
+                    list
+                )
+            )"
+            )
+            .unwrap()
+            .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(in john (list john jane))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(in john (list jane))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(in john (list 10))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(in john (list 10 john))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+    }
+    #[test]
+    fn test_eval_rule_in_err() {
+        assert_eq!(
+            Rule::from_str("(in john jane)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidInStatement(Rule::Tuple(vec![
+                Rule::In(String::from("in")),
+                Rule::String(String::from("john")),
+                Rule::String(String::from("jane")),
+            ])))
+        );
+        assert_eq!(
+            Rule::from_str("(in (list john) jane)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidInStatement(Rule::Tuple(vec![
+                Rule::In(String::from("in")),
+                Rule::Tuple(vec![
+                    Rule::List(String::from("list")),
+                    Rule::String(String::from("john"))
+                ]),
+                Rule::String(String::from("jane")),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_and_ok() {
+        assert_eq!(
+            Rule::from_str("(and true true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(and true false)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(and false true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(and false false)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_and_err() {
+        assert_eq!(
+            Rule::from_str("(and true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidAndStatement(Rule::Tuple(vec![
+                Rule::And(String::from("and")),
+                Rule::Bool(true),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_or_ok() {
+        assert_eq!(
+            Rule::from_str("(or true true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(or true false)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(or false true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(or false false)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_or_err() {
+        assert_eq!(
+            Rule::from_str("(or true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidOrStatement(Rule::Tuple(vec![
+                Rule::Or(String::from("or")),
+                Rule::Bool(true),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_eq_ok() {
+        assert_eq!(
+            Rule::from_str("(eq $a $b)")
+                .unwrap()
+                .eval(&Context::from_str("a:10,b:10").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(eq john john)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(eq john jane)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(eq 10 10)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(eq 10 20)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(eq 10.0 10.0)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(eq 10.0 20.0)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+        assert_eq!(
+            Rule::from_str("(eq true true)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(eq true false)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(false))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_eq_err() {
+        assert_eq!(
+            Rule::from_str("(eq)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidEqStatement(Rule::Tuple(vec![Rule::Eq(
+                String::from("eq")
+            ),])))
+        );
+        assert_eq!(
+            Rule::from_str("(eq john)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidEqStatement(Rule::Tuple(vec![
+                Rule::Eq(String::from("eq")),
+                Rule::String(String::from("john")),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_if_ok() {
+        assert_eq!(
+            Rule::from_str("(if true true false)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(if false true 10)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Integer(10))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_if_err() {
+        assert_eq!(
+            Rule::from_str("(if)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidIfStatement(Rule::Tuple(vec![Rule::If(
+                String::from("if")
+            ),])))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_ok() {
+        assert_eq!(
+            Rule::from_str("(if (eq $name john) (list create) (list))")
+                .unwrap()
+                .eval(&Context::from_str("name:john,role:admin").unwrap()),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("create")),]))
+        );
+        assert_eq!(
+            Rule::from_str("(if (eq $role admin) (list create read update delete list) (if (eq $role user) (list read list) (list)))")
+                .unwrap()
+                .eval(&Context::from_str("name:john,role:admin").unwrap()),
+            Ok(Rule::Tuple(vec![
+                Rule::String(String::from("create")),
+                Rule::String(String::from("read")),
+                Rule::String(String::from("update")),
+                Rule::String(String::from("delete")),
+                Rule::String(String::from("list")),
+            ]))
+        );
+        assert_eq!(
+            Rule::from_str("(if (eq $role admin) (list create read update delete list) (if (eq $role user) (list read list) (list)))")
+                .unwrap()
+                .eval(&Context::from_str("name:john,role:user").unwrap()),
+            Ok(Rule::Tuple(vec![
+                Rule::String(String::from("read")),
+                Rule::String(String::from("list")),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_concat_ok() {
+        assert_eq!(
+            Rule::from_str("(concat $tenant / $project)")
+                .unwrap()
+                .eval(&Context::from_str("tenant:acme,project:42").unwrap()),
+            Ok(Rule::String(String::from("acme/42")))
+        );
+        assert_eq!(
+            Rule::from_str("(eq (concat $tenant / $project) acme/42)")
+                .unwrap()
+                .eval(&Context::from_str("tenant:acme,project:42").unwrap()),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(concat (list create) (list read list))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Ok(Rule::Tuple(vec![
+                Rule::String(String::from("create")),
+                Rule::String(String::from("read")),
+                Rule::String(String::from("list")),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_concat_err() {
+        assert_eq!(
+            Rule::from_str("(concat)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidConcatStatement(Rule::Tuple(vec![
+                Rule::Concat(String::from("concat")),
+            ])))
+        );
+        assert_eq!(
+            Rule::from_str("(concat john (list jane))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidConcatStatement(Rule::Tuple(vec![
+                Rule::Concat(String::from("concat")),
+                Rule::String(String::from("john")),
+                Rule::Tuple(vec![
+                    Rule::List(String::from("list")),
+                    Rule::String(String::from("jane")),
+                ]),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_parse_rule_comment_ok() {
+        assert_eq!(
+            Rule::from_str(
+                "; Admins can do anything
+                (if (eq $role admin) ; checked first
+                    (list all)
+                    (list)) ; nothing otherwise"
+            ),
+            Rule::from_str("(if (eq $role admin) (list all) (list))")
+        );
+        assert_eq!(
+            Rule::from_str("(list read;comment\ncreate)"),
+            Ok(Rule::Tuple(vec![
+                Rule::List(String::from("list")),
+                Rule::String(String::from("read")),
+                Rule::String(String::from("create")),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_rule_to_pretty_string_ok() {
+        assert_eq!(Rule::from_str("()").unwrap().to_pretty_string(2), "()");
+        assert_eq!(
+            Rule::from_str("(  list   read create )")
+                .unwrap()
+                .to_pretty_string(2),
+            "(list read create)"
+        );
+        assert_eq!(
+            Rule::from_str(
+                "(if (eq $role admin) (list all) (if (eq $age 10.0) (list read) (list)))"
+            )
+            .unwrap()
+            .to_pretty_string(2),
+            "(if
+  (eq $role admin)
+  (list all)
+  (if
+    (eq $age 10.0)
+    (list read)
+    (list)))"
+        );
+        let rule = Rule::from_str("(if (eq $age 10.0) (list read) (list))").unwrap();
+        assert_eq!(Rule::from_str(&rule.to_pretty_string(4)), Ok(rule));
+    }
+
+    #[test]
+    fn test_rule_simplify_ok() {
+        let simplify = |s: &str| Rule::from_str(s).unwrap().simplify();
+        assert_eq!(
+            simplify("(if true (list all) (list))"),
+            Rule::from_str("(list all)").unwrap()
+        );
+        assert_eq!(
+            simplify("(if (and false (eq $a b)) (list all) (list))"),
+            Rule::from_str("(list)").unwrap()
+        );
+        assert_eq!(
+            simplify("(if (eq $a b) (list read) (list read))"),
+            Rule::from_str("(list read)").unwrap()
+        );
+        assert_eq!(
+            simplify("(or false (and (eq $a b) true))"),
+            Rule::from_str("(eq $a b)").unwrap()
+        );
+        assert_eq!(
+            simplify("(and (eq $a b) (or (eq $a b) (eq $c d)))"),
+            Rule::from_str("(eq $a b)").unwrap()
+        );
+        assert_eq!(
+            simplify("(or (and (eq $c d) (eq $a b)) (eq $a b))"),
+            Rule::from_str("(eq $a b)").unwrap()
+        );
+        assert_eq!(
+            simplify("(and (eq $a b) (eq $a b))"),
+            Rule::from_str("(eq $a b)").unwrap()
+        );
+        assert_eq!(
+            simplify("(and true $a)"),
+            Rule::from_str("(and true $a)").unwrap()
+        );
+        assert_eq!(
+            simplify("(if $a (list read) (list))"),
+            Rule::from_str("(if $a (list read) (list))").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rule_display_ok() {
+        assert_eq!(Rule::from_str("()").unwrap().to_string(), "()");
+        assert_eq!(
+            Rule::from_str(
+                "(if (eq   $role admin)\n(list all) ; comment\n(in 10.0 (list 1 true)))"
+            )
+            .unwrap()
+            .to_string(),
+            "(if (eq $role admin) (list all) (in 10.0 (list 1 true)))"
+        );
+        let rule =
+            Rule::from_str("(if (eq $age 10.0) (concat (list read) (list)) (list))").unwrap();
+        assert_eq!(Rule::from_str(&rule.to_string()), Ok(rule));
+    }
+
+    #[test]
+    fn test_rule_serialize_ok() {
+        assert_eq!(
+            serde_json::to_string(&Rule::from_str("(eq $a 1.5)").unwrap()).unwrap(),
+            "\"(eq $a 1.5)\""
+        );
+    }
+
+    #[test]
+    fn test_rule_variables_ok() {
+        assert_eq!(
+            Rule::from_str(
+                "(if (and (eq $role admin) (in $user_id (list $owner $role))) (list all) (list))"
+            )
+            .unwrap()
+            .variables(),
+            vec![
+                "role".to_string(),
+                "user_id".to_string(),
+                "owner".to_string()
+            ]
+        );
+        assert!(Rule::from_str("(list read)")
+            .unwrap()
+            .variables()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rule_typecheck_ok() {
+        let schema = Schema::from_iter([
+            ("role", AttributeType::String),
+            ("age", AttributeType::Integer),
+            ("scopes", AttributeType::List),
+        ]);
+        assert_eq!(
+            Rule::from_str("(if (and (eq $role admin) (in read $scopes)) (list all) (list))")
+                .unwrap()
+                .typecheck(&schema),
+            Ok(Some(AttributeType::List))
+        );
+        assert_eq!(
+            Rule::from_str("(eq (concat $role /) admin/)")
+                .unwrap()
+                .typecheck(&schema),
+            Ok(Some(AttributeType::Bool))
+        );
+        assert_eq!(
+            Rule::from_str("(if (eq $age 18) 1 false)")
+                .unwrap()
+                .typecheck(&schema),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_rule_typecheck_err() {
+        let schema = Schema::from_iter([("role", AttributeType::String)]);
+        assert_eq!(
+            Rule::from_str("(eq $team admin)")
+                .unwrap()
+                .typecheck(&schema),
+            Err(Error::UndeclaredAttribute("team".to_string()))
+        );
+        assert_eq!(
+            Rule::from_str("(eq $role 10)").unwrap().typecheck(&schema),
+            Err(Error::TypeMismatch(
+                Rule::from_str("(eq $role 10)").unwrap()
+            ))
+        );
+        assert_eq!(
+            Rule::from_str("(if $role (list) (list))")
+                .unwrap()
+                .typecheck(&schema),
+            Err(Error::TypeMismatch(
+                Rule::from_str("(if $role (list) (list))").unwrap()
+            ))
+        );
+        assert_eq!(
+            Rule::from_str("(in $role admin)")
+                .unwrap()
+                .typecheck(&schema),
+            Err(Error::TypeMismatch(
+                Rule::from_str("(in $role admin)").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_max_depth_ok() {
+        let rule = Rule::from_str("(list (list (list read)))").unwrap();
+        assert_eq!(
+            rule.eval_with_max_depth(&Context::from_str("").unwrap(), 3),
+            Ok(Rule::Tuple(vec![Rule::Tuple(vec![Rule::Tuple(vec![
+                Rule::String(String::from("read"))
+            ])])]))
+        );
+        let depth = 5000;
+        let rule = Rule::from_str(&format!(
+            "{}true{}",
+            "(and true ".repeat(depth),
+            ")".repeat(depth)
+        ))
+        .unwrap();
+        assert_eq!(
+            rule.eval_with_max_depth(&Context::from_str("").unwrap(), depth),
+            Ok(Rule::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_max_depth_err() {
+        let rule = Rule::from_str("(list (list (list read)))").unwrap();
+        assert_eq!(
+            rule.eval_with_max_depth(&Context::from_str("").unwrap(), 2),
+            Err(Error::MaxDepthExceeded(2))
+        );
+        let depth = DEFAULT_MAX_DEPTH + 1;
+        let rule = Rule::from_str(&format!(
+            "{}true{}",
+            "(and true ".repeat(depth),
+            ")".repeat(depth)
+        ))
+        .unwrap();
+        assert_eq!(
+            rule.eval(&Context::from_str("").unwrap()),
+            Err(Error::MaxDepthExceeded(DEFAULT_MAX_DEPTH))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_budget_ok() {
+        assert_eq!(
+            Rule::from_str("(and true (eq 1 1))")
+                .unwrap()
+                .eval_with_budget(
+                    &Context::from_str("").unwrap(),
+                    &mut Budget::default().with_nodes(5)
+                ),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(list read)").unwrap().eval_with_budget(
+                &Context::from_str("").unwrap(),
+                &mut Budget::default().with_timeout(Duration::from_secs(60))
+            ),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("read"))]))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_budget_err() {
+        let mut budget = Budget::default().with_nodes(4);
+        assert_eq!(
+            Rule::from_str("(and true (eq 1 1))")
+                .unwrap()
+                .eval_with_budget(&Context::from_str("").unwrap(), &mut budget),
+            Err(Error::BudgetExhausted)
+        );
+        assert_eq!(
+            Rule::from_str("(list read)").unwrap().eval_with_budget(
+                &Context::from_str("").unwrap(),
+                &mut Budget::default().with_timeout(Duration::ZERO)
+            ),
+            Err(Error::BudgetExhausted)
+        );
+    }
+
+    #[test]
+    fn test_context_presets_ok() {
+        assert_eq!(
+            Context::anonymous(),
+            Context::from_str("principal:anonymous,authenticated:false").unwrap()
+        );
+        assert_eq!(
+            Context::service("billing"),
+            Context::from_str("principal:service,service:billing,authenticated:true").unwrap()
+        );
+        let mut context = Context::anonymous();
+        context.insert("authenticated", Rule::Bool(true));
+        context.insert("role", Rule::String(String::from("guest")));
+        assert_eq!(
+            context,
+            Context::from_str("principal:anonymous,authenticated:true,role:guest").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rule_optimize_ok() {
+        let optimize = |s: &str| Rule::from_str(s).unwrap().optimize();
+        assert_eq!(
+            optimize("(if (or (eq 1 2) (in read (list read))) (list all) (list))"),
+            Rule::from_str("(list all)").unwrap()
+        );
+        assert_eq!(
+            optimize("(eq $path (concat a / b))"),
+            Rule::from_str("(eq $path a/b)").unwrap()
+        );
+        assert_eq!(
+            optimize("(eq $id (concat 1 2))"),
+            Rule::from_str("(eq $id (concat 1 2))").unwrap()
+        );
+        assert_eq!(
+            optimize("(if (eq $role admin) (list all) (list))"),
+            Rule::from_str("(if (eq $role admin) (list all) (list))").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_context_builder_ok() {
+        let context = Context::builder()
+            .str("role", "admin")
+            .int("user_id", 1)
+            .float("score", 0.5)
+            .bool("active", true)
+            .str("role", "editor")
+            .build();
+        assert_eq!(
+            context,
+            Context::from_str("role:editor,user_id:1,score:0.5,active:true").unwrap()
+        );
+        assert_eq!(
+            Context::builder().str("id", "42").build().get("id"),
+            Ok(&Rule::String(String::from("42")))
+        );
+        assert_eq!(
+            Rule::from_str("(in admin $groups)").unwrap().eval(
+                &Context::builder()
+                    .list("groups", ["staff", "admin"])
+                    .build()
+            ),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(Context::builder().build(), Context::default());
+    }
+
+    #[test]
+    fn test_rule_bind_ok() {
+        let context = Context::from_str("user_id:1,role:admin").unwrap();
+        let bind = |s: &str| Rule::from_str(s).unwrap().bind(&context).optimize();
+        assert_eq!(
+            bind("(or (eq $role admin) (eq $item.owner $user_id))"),
+            Rule::Bool(true)
+        );
+        assert_eq!(
+            bind("(and (eq $role admin) (eq $item.owner $user_id))"),
+            Rule::from_str("(eq $item.owner 1)").unwrap()
+        );
+        assert_eq!(
+            Rule::from_str("(in $item.team $teams)")
+                .unwrap()
+                .bind(&Context::builder().list("teams", ["a", "b"]).build()),
+            Rule::from_str("(in $item.team (list a b))").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_context_conversions_ok() {
+        assert_eq!(
+            Context::from(HashMap::from([(
+                String::from("user_id"),
+                String::from("1")
+            )])),
+            Context::from_str("user_id:1").unwrap()
+        );
+        assert_eq!(
+            [
+                (String::from("role"), Rule::String(String::from("user"))),
+                (String::from("role"), Rule::String(String::from("admin"))),
+            ]
+            .into_iter()
+            .collect::<Context>(),
+            Context::from_str("role:admin").unwrap()
+        );
+        assert_eq!(
+            Context::try_from(serde_json::json!({
+                "role": "admin",
+                "score": 0.5,
+                "user": {"id": 7, "active": true},
+                "groups": ["staff", "ops"],
+            })),
+            Ok(Context::builder()
+                .list("groups", ["staff", "ops"])
+                .str("role", "admin")
+                .float("score", 0.5)
+                .bool("user.active", true)
+                .int("user.id", 7)
+                .build())
+        );
+        assert_eq!(
+            Context::from_json(r#"{"role": "admin", "user": {"id": 7}}"#),
+            Context::from_str("role:admin,user.id:7")
+        );
+    }
+
+    #[test]
+    fn test_context_conversions_err() {
+        assert_eq!(
+            Context::try_from(serde_json::json!(["role"])),
+            Err(Error::InvalidContextValue(String::new()))
+        );
+        assert_eq!(
+            Context::try_from(serde_json::json!({"user": {"id": null}})),
+            Err(Error::InvalidContextValue(String::from("user.id")))
+        );
+        assert_eq!(
+            Context::try_from(serde_json::json!({"id": 5_000_000_000_i64})),
+            Err(Error::InvalidContextValue(String::from("id")))
+        );
+        assert_eq!(
+            Context::from_json(r#"{"role": "#),
+            Err(Error::CannotParse(String::from(r#"{"role": "#)))
+        );
+        assert_eq!(
+            Context::from_json("[]"),
+            Err(Error::InvalidContextValue(String::new()))
+        );
+    }
+
+    #[test]
+    fn test_context_categories_ok() {
+        let mut context = Context::categorized(
+            &Context::from_str("id:1,role:editor").unwrap(),
+            &Context::from_str("id:42,owner:1").unwrap(),
+            &Context::from_str("name:update").unwrap(),
+            &Context::from_str("").unwrap(),
+        );
+        context.insert_in(Category::Environment, "time", Rule::Integer(1_700));
+        assert_eq!(
+            context.category(Category::Resource),
+            Context::from_str("id:42,owner:1").unwrap()
+        );
+        assert_eq!(context.get("env.time"), Ok(&Rule::Integer(1_700)));
+        assert_eq!(
+            Rule::from_str(
+                "(and (eq $subject.id $resource.owner) (and (ne $subject.id $resource.id) (eq $action.name update)))"
+            )
+            .unwrap()
+            .eval(&context),
+            Ok(Rule::Bool(true))
+        );
+        assert_eq!(
+            Rule::from_str("(eq $resource.name x)")
+                .unwrap()
+                .eval(&context),
+            Err(Error::MissingSegment(
+                String::from("resource.name"),
+                String::from("name")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_context_list_ok() {
+        let context =
+            Context::from_str("groups:[dev|ops],ids:[1|2],empty:[],names:[a\\|b|\\1]").unwrap();
+        assert_eq!(
+            context,
+            [
+                (
+                    String::from("groups"),
+                    Rule::Tuple(vec![
+                        Rule::String(String::from("dev")),
+                        Rule::String(String::from("ops"))
+                    ])
+                ),
+                (
+                    String::from("ids"),
+                    Rule::Tuple(vec![Rule::Integer(1), Rule::Integer(2)])
+                ),
+                (String::from("empty"), Rule::Tuple(vec![])),
+                (
+                    String::from("names"),
+                    Rule::Tuple(vec![
+                        Rule::String(String::from("a|b")),
+                        Rule::String(String::from("1"))
+                    ])
+                ),
+            ]
+            .into_iter()
+            .collect()
+        );
+        for (rule, expected) in [
+            ("(in ops $groups)", true),
+            ("(in 2 $ids)", true),
+            ("(intersects $groups (list qa ops))", true),
+            ("(intersects $groups (list qa))", false),
+            ("(contains-all $groups [dev ops])", true),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule).unwrap().eval(&context),
+                Ok(Rule::Bool(expected)),
+                "{rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_context_list_err() {
+        for context in ["groups:[dev|ops", "groups:[dev]x", "groups:[dev\\"] {
+            assert_eq!(
+                Context::from_str(context),
+                Err(Error::CannotParse(String::from(context)))
+            );
+        }
+        assert_eq!(
+            Context::from_str("groups:x[dev]").unwrap().get("groups"),
+            Ok(&Rule::String(String::from("x[dev]")))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_perms_ok() {
+        let context = Context::from_str("role:admin").unwrap();
+        assert_eq!(
+            Rule::from_str("(perms read list (require mfa))")
+                .unwrap()
+                .eval(&context),
+            Rule::from_str("(list read list (require mfa))")
+                .unwrap()
+                .eval(&context)
+        );
+        assert_eq!(
+            Rule::from_str("(if (eq $role admin) (perms all) (perms))")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("all"))]))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_deny_ok() {
+        let context = Context::from_str("role:contractor").unwrap();
+        let granted = Rule::from_str(
+            "(if (eq $role contractor) (perms read update (deny update)) (perms read update))",
+        )
+        .unwrap()
+        .eval(&context)
+        .unwrap();
+        assert_eq!(
+            granted,
+            Rule::from_str("(read update (deny update))").unwrap()
+        );
+        assert_eq!(permission::denied(&granted), 0b00100);
+        assert_eq!(
+            permission::Permission::try_from(granted),
+            Ok(permission::Permission::from_bits(0b00110))
+        );
+        let denied = Rule::from_str("(deny all)")
+            .unwrap()
+            .eval(&context)
+            .unwrap();
+        assert_eq!(permission::denied(&denied), 0b11111);
+        assert_eq!(
+            permission::Permission::try_from(denied),
+            Ok(permission::Permission::NONE)
+        );
+
+        let concatenated = Rule::from_str("(concat (list read) (deny delete) (require audit))")
+            .unwrap()
+            .eval(&context)
+            .unwrap();
+        assert_eq!(
+            concatenated,
+            Rule::from_str("(read (deny delete) (require audit))").unwrap()
+        );
+        assert_eq!(permission::denied(&concatenated), 0b01000);
+        assert_eq!(
+            permission::Permission::try_from(concatenated),
+            Ok(permission::Permission::from_bits(0b00010))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_deny_err() {
+        assert_eq!(
+            Rule::from_str("(deny)").unwrap().validate(),
+            Err(Error::InvalidDenyStatement(Rule::Tuple(vec![Rule::Deny(
+                String::from("deny")
+            )])))
+        );
+        assert_eq!(
+            Rule::from_str("(deny read $role)")
+                .unwrap()
+                .eval(&Context::from_str("role:admin").unwrap()),
+            Err(Error::InvalidPermission(Rule::String(String::from(
+                "admin"
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_eval_rule_perms_err() {
+        assert_eq!(
+            Rule::from_str("(perms read $user)")
+                .unwrap()
+                .eval(&Context::from_str("user:alice").unwrap()),
+            Err(Error::InvalidPermission(Rule::String(String::from(
+                "alice"
+            ))))
+        );
+        assert_eq!(
+            Rule::from_str("(perms read 1)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidPermission(Rule::Integer(1)))
+        );
+        assert_eq!(
+            Rule::from_str("(perms readd)").map(|rule| rule.validate()),
+            Ok(Err(Error::InvalidPermission(Rule::String(String::from(
+                "readd"
+            )))))
+        );
+    }
+
+    #[test]
+    fn test_context_sanitize_ok() {
+        let context = Context::from_str("user:\\$admin,greeting:\\{$user\\},admin:true").unwrap();
+        assert_eq!(
+            Rule::from_str("(list $user $greeting (concat $user x))")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Tuple(vec![
+                Rule::String(String::from("$admin")),
+                Rule::String(String::from("{$user}")),
+                Rule::String(String::from("$adminx")),
+            ]))
+        );
+        assert_eq!(
+            Rule::from_str("(eq $role $user)").unwrap().bind(&context),
+            Rule::from_str("(eq $role $user)").unwrap()
+        );
+        assert_eq!(
+            Context::from_str("user:alice,groups:[a|b],score:0.5")
+                .unwrap()
+                .sanitize(),
+            Ok(Context::from_str("user:alice,groups:[a|b],score:0.5").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_context_sanitize_err() {
+        for (context, key) in [
+            ("user:\\$admin", "user"),
+            ("greeting:\\{$user\\}", "greeting"),
+            ("groups:[a|\\$b]", "groups"),
+            ("\\$role:admin", "$role"),
+            ("ro\\ le:admin", "ro le"),
+        ] {
+            assert_eq!(
+                Context::from_str(context).unwrap().sanitize(),
+                Err(Error::UntrustedContextValue(String::from(key))),
+                "{context}"
+            );
+        }
+        assert_eq!(
+            Context::try_from(serde_json::json!({"groups": [["if", true]]}))
+                .unwrap()
+                .sanitize(),
+            Err(Error::UntrustedContextValue(String::from("groups")))
+        );
+    }
+
+    #[test]
+    fn test_rule_dollar_literals_ok() {
+        assert_eq!(
+            Rule::from_str("(eq ${role} admin)").unwrap(),
+            Rule::from_str("(eq $role admin)").unwrap()
+        );
+        let rule = Rule::from_str(r#"(list \$5 "\$price" "a\$b {$role}" $price)"#).unwrap();
+        assert_eq!(
+            rule,
+            Rule::Tuple(vec![
+                Rule::List(String::from("list")),
+                Rule::Literal(String::from("$5")),
+                Rule::Literal(String::from("$price")),
+                Rule::Literal(String::from("a$b {$role}")),
+                Rule::String(String::from("$price")),
+            ])
+        );
+        assert_eq!(
+            rule.to_string(),
+            r#"(list "\$5" "\$price" "a\$b {\$role}" $price)"#
+        );
+        assert_eq!(Rule::from_str(&rule.to_string()), Ok(rule.clone()));
+        let context = Context::from_str("price:\\$5,role:admin").unwrap();
+        let expected = Ok(Rule::Tuple(vec![
+            Rule::String(String::from("$5")),
+            Rule::String(String::from("$price")),
+            Rule::String(String::from("a$b {$role}")),
+            Rule::String(String::from("$5")),
+        ]));
+        assert_eq!(rule.eval(&context), expected);
+        assert_eq!(
+            crate::bytecode::Program::compile(&rule)
+                .unwrap()
+                .eval(&context),
+            expected
+        );
+        assert_eq!(
+            Rule::from_str(r#"(eq $price "\$5")"#)
+                .unwrap()
+                .typecheck(&[("price", AttributeType::String)].into_iter().collect()),
+            Ok(Some(AttributeType::Bool))
+        );
+    }
+
+    #[test]
+    fn test_context_merge_ok() {
+        let mut context = Context::from_str("role:user,tenant:acme").unwrap();
+        context.merge(&Context::from_str("role:admin,mfa:true").unwrap());
+        assert_eq!(
+            context,
+            Context::from_str("role:admin,tenant:acme,mfa:true").unwrap()
+        );
+
+        let request = Context::from_str("ip:10.0.0.1,role:auditor").unwrap();
+        let session = Context::from_str("user_id:1,role:user").unwrap();
+        let tenant = Context::from_str("tenant:acme,role:guest,region:eu").unwrap();
+        let context = Context::layered(&[&request, &session, &tenant]);
+        assert_eq!(
+            context.get("role"),
+            Ok(&Rule::String(String::from("auditor")))
+        );
+        assert_eq!(context.get("user_id"), Ok(&Rule::Integer(1)));
+        assert_eq!(context.get("region"), Ok(&Rule::String(String::from("eu"))));
+        assert_eq!(Context::layered(&[]), Context::default());
+    }
+
+    #[test]
+    fn test_context_validate_ok() {
+        let schema: Schema = toml::from_str(
+            r#"
+            role = { type = "string", required = true }
+            score = "float"
+            groups = "list"
+            "#,
+        )
+        .unwrap();
+        for context in ["role:admin", "role:admin,score:1,groups:[a|b],extra:x"] {
+            assert_eq!(
+                Context::from_str(context).unwrap().validate(&schema),
+                Ok(()),
+                "{context}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_context_validate_err() {
+        let schema: Schema = toml::from_str(
+            r#"
+            role = { type = "string", required = true }
+            age = "integer"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Context::from_str("age:3").unwrap().validate(&schema),
+            Err(Error::KeyNotInContext(String::from("role")))
+        );
+        assert_eq!(
+            Context::from_str("role:admin,age:old")
+                .unwrap()
+                .validate(&schema),
+            Err(Error::InvalidAttributeType(
+                String::from("age"),
+                AttributeType::Integer
+            ))
+        );
+        assert_eq!(
+            Context::from_str("role:1").unwrap().validate(&schema),
+            Err(Error::InvalidAttributeType(
+                String::from("role"),
+                AttributeType::String
+            ))
+        );
+    }
+
+    #[test]
+    fn test_context_duplicate_keys_ok() {
+        assert_eq!(
+            Context::parse("role:user,role:admin", DuplicateKeys::LastWins),
+            Context::from_str("role:admin")
+        );
+        assert_eq!(
+            Context::parse(
+                "role:user,id:1,role:admin,role:3",
+                DuplicateKeys::MultiValue
+            ),
+            Context::from_str("role:[user|admin|3],id:1")
+        );
+        assert_eq!(
+            Context::parse("groups:[a|b],groups:c", DuplicateKeys::MultiValue),
+            Context::from_str("groups:[a|b|c]")
+        );
+    }
 
-impl FromStr for Rule {
-    type Err = Error;
+    #[test]
+    fn test_context_duplicate_keys_err() {
+        assert_eq!(
+            Context::from_str("role:user,role:admin"),
+            Err(Error::DuplicateKey(String::from("role")))
+        );
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        parse_rule(s)
+    #[test]
+    fn test_context_impersonation_ok() {
+        let context = Context::impersonation(
+            &Context::from_str("user_id:1,role:support").unwrap(),
+            &Context::from_str("user_id:2,role:user").unwrap(),
+        );
+        assert_eq!(
+            context,
+            Context::from_str(
+                "subject.user_id:1,subject.role:support,on_behalf_of.user_id:2,on_behalf_of.role:user,user_id:2,role:user"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            Rule::from_str("(and (eq $subject.role support) (eq $role user))")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Bool(true))
+        );
     }
-}
 
-impl<'a> Deserialize<'a> for Rule {
-    fn deserialize<D>(deserializer: D) -> Result<Rule, D::Error>
-    where
-        D: serde::Deserializer<'a>,
-    {
-        let s = String::deserialize(deserializer)?;
-        let rule = Rule::from_str(s.as_str()).map_err(serde::de::Error::custom)?;
-        rule.eval(&Context::from_str("").unwrap())
-            .map_err(serde::de::Error::custom)?;
-        Ok(rule)
+    #[test]
+    fn test_eval_rule_require_ok() {
+        assert_eq!(
+            Rule::from_str("(list read (require reauth $max_age))")
+                .unwrap()
+                .eval(&Context::from_str("max_age:5m").unwrap()),
+            Ok(Rule::Tuple(vec![
+                Rule::String(String::from("read")),
+                Rule::Tuple(vec![
+                    Rule::Require(String::from("require")),
+                    Rule::String(String::from("reauth")),
+                    Rule::String(String::from("5m")),
+                ]),
+            ]))
+        );
     }
-}
 
-fn parse_rule(rule: &str) -> Result<Rule, Error> {
-    let mut stack = vec![Rule::Tuple(Vec::new())];
-    let mut buffer = String::new();
-    let flush_buffer = |buffer: &mut String, stack: &mut Vec<Rule>| -> Result<(), Error> {
-        if !buffer.is_empty() {
-            let node: Rule;
-            let mut parent = stack.pop().ok_or(Error::CannotParse(String::from(rule)))?;
-            let Rule::Tuple(ref mut children) = parent else {
-                return Err(Error::CannotParse(String::from(rule)));
-            };
+    #[test]
+    fn test_eval_rule_obligation_ok() {
+        let rule = Rule::from_str("(list read (obligation log-access) (obligation mask-pii ssn))")
+            .unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "(list read (obligation log-access) (obligation mask-pii ssn))"
+        );
+        assert_eq!(
+            permission::Obligation::from_permission_rule(
+                &rule.eval(&Context::from_str("").unwrap()).unwrap()
+            ),
+            vec![
+                permission::Obligation {
+                    name: String::from("log-access"),
+                    arguments: vec![],
+                },
+                permission::Obligation {
+                    name: String::from("mask-pii"),
+                    arguments: vec![Rule::String(String::from("ssn"))],
+                },
+            ]
+        );
+    }
 
-            if buffer.parse::<i32>().is_ok()
-                || buffer.parse::<f32>().is_ok()
-                || buffer.parse::<bool>().is_ok()
-            {
-                node = Rule::from_literal(buffer.as_str())?;
-            } else if children.is_empty() {
-                match buffer.as_str() {
-                    "if" => {
-                        node = Rule::If(buffer.clone());
-                    }
-                    "eq" => {
-                        node = Rule::Eq(buffer.clone());
-                    }
-                    "list" => {
-                        node = Rule::List(buffer.clone());
-                    }
-                    "and" => {
-                        node = Rule::And(buffer.clone());
-                    }
-                    "or" => {
-                        node = Rule::Or(buffer.clone());
-                    }
-                    "in" => {
-                        node = Rule::In(buffer.clone());
-                    }
-                    _ => {
-                        node = Rule::String(buffer.clone());
-                    }
-                }
-            } else {
-                node = Rule::String(buffer.clone());
-            }
-            children.push(node);
-            buffer.clear();
-            stack.push(parent);
-        }
-        Ok(())
-    };
-    for c in rule.chars() {
-        if c == '(' {
-            flush_buffer(&mut buffer, &mut stack)?;
-            let node = Rule::Tuple(Vec::new());
-            stack.push(node);
-        } else if c == ')' {
-            flush_buffer(&mut buffer, &mut stack)?;
-            let node = stack.pop().ok_or(Error::CannotParse(String::from(rule)))?;
-            let mut parent = stack.pop().ok_or(Error::CannotParse(String::from(rule)))?;
-            if let Rule::Tuple(ref mut children) = parent {
-                children.push(node);
-            }
-            stack.push(parent);
-        } else if c == ' ' || c == '\n' || c == '\t' {
-            flush_buffer(&mut buffer, &mut stack)?;
-        } else {
-            buffer.push(c);
-        }
+    #[test]
+    fn test_eval_rule_require_err() {
+        assert_eq!(
+            Rule::from_str("(require)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidRequireStatement(Rule::Tuple(vec![
+                Rule::Require(String::from("require")),
+            ])))
+        );
     }
-    if let Rule::Tuple(ref mut children) =
-        stack.pop().ok_or(Error::CannotParse(String::from(rule)))?
-    {
-        children.pop().ok_or(Error::CannotParse(String::from(rule)))
-    } else {
-        Err(Error::CannotParse(String::from(rule)))
+
+    #[test]
+    fn test_eval_rule_usage_err() {
+        assert_eq!(
+            Rule::from_str("(usage exports $user_id)")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidUsageStatement(
+                Rule::from_str("(usage exports $user_id)").unwrap()
+            ))
+        );
+        assert_eq!(
+            Rule::from_str("(usage exports $user_id today)")
+                .unwrap()
+                .eval(&Context::from_str("user_id:1").unwrap()),
+            Err(Error::NoCounterProvider(
+                Rule::from_str("(usage exports $user_id today)").unwrap()
+            ))
+        );
     }
-}
 
-impl Rule {
-    pub fn from_literal(s: &str) -> Result<Rule, Error> {
-        if s.parse::<i32>().is_ok() {
-            Ok(Rule::Integer(s.parse::<i32>().ok().ok_or(
-                Error::CannotParseAs(Rule::Integer(0), s.to_string()),
-            )?))
-        } else if s.parse::<f32>().is_ok() {
-            Ok(Rule::Float(s.parse::<f32>().ok().ok_or(
-                Error::CannotParseAs(Rule::Float(0.0), s.to_string()),
-            )?))
-        } else if s.parse::<bool>().is_ok() {
-            Ok(Rule::Bool(s.parse::<bool>().ok().ok_or(
-                Error::CannotParseAs(Rule::Bool(false), s.to_string()),
-            )?))
-        } else {
-            Ok(Rule::String(s.to_string()))
+    #[test]
+    fn test_eval_rule_contains_ok() {
+        for (rule, expected) in [
+            (
+                "(contains-all (list read write admin) (list read write))",
+                true,
+            ),
+            ("(contains-all (list read) (list read write))", false),
+            ("(contains-all (list read) (list))", true),
+            ("(contains-any (list read) (list read write))", true),
+            ("(contains-any (list admin) (list read write))", false),
+            ("(contains-any (list read) (list))", false),
+            ("(contains-any read (list read write))", true),
+            ("(contains-all $scopes (list read))", false),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule)
+                    .unwrap()
+                    .eval(&Context::from_str("").unwrap()),
+                Ok(Rule::Bool(expected))
+            );
         }
     }
 
-    #[allow(clippy::too_many_lines)] // Prolly a way to improve it but ¯\_(ツ)_/¯
-    pub fn eval(&self, context: &Context) -> Result<Rule, Error> {
-        match self {
-            Rule::Tuple(children) => match children.first() {
-                Some(Rule::If(_)) => {
-                    if children.len() != 4 {
-                        return Err(Error::InvalidIfStatement(self.clone()));
-                    }
-                    let condition = children
-                        .get(1)
-                        .ok_or(Error::InvalidIfStatement(self.clone()))?
-                        .eval(context)?;
-                    let then = children
-                        .get(2)
-                        .ok_or(Error::InvalidIfStatement(self.clone()))?
-                        .eval(context)?;
-                    let otherwise = children
-                        .get(3)
-                        .ok_or(Error::InvalidIfStatement(self.clone()))?
-                        .eval(context)?;
-                    match condition {
-                        Rule::Bool(false) => Ok(otherwise),
-                        Rule::Bool(true) => Ok(then),
-                        _ => Err(Error::InvalidIfCondition(condition)),
-                    }
-                }
-                Some(Rule::Eq(_)) => {
-                    if children.len() != 3 {
-                        return Err(Error::InvalidEqStatement(self.clone()));
-                    }
-                    let left = children
-                        .get(1)
-                        .ok_or(Error::InvalidEqStatement(self.clone()))?
-                        .eval(context)?;
-                    let right = children
-                        .get(2)
-                        .ok_or(Error::InvalidEqStatement(self.clone()))?
-                        .eval(context)?;
-                    match (left, right) {
-                        (Rule::String(l), Rule::String(r)) => Ok(Rule::Bool(l == r)),
-                        (Rule::Integer(l), Rule::Integer(r)) => Ok(Rule::Bool(l == r)),
-                        (Rule::Float(l), Rule::Float(r)) => Ok(Rule::Bool((l - r).abs() < 0.1)), // Adjust tolerance
-                        (Rule::Bool(l), Rule::Bool(r)) => Ok(Rule::Bool(l == r)),
-                        (l, r) => Err(Error::CannotCompare(l, r)),
-                    }
-                }
-                Some(Rule::List(_)) => Ok(Rule::Tuple(
-                    children
-                        .iter()
-                        .skip(1)
-                        .map(|child| child.eval(context))
-                        .collect::<Result<Vec<Rule>, Error>>()?,
-                )),
-                Some(Rule::And(_)) => {
-                    if children.len() != 3 {
-                        return Err(Error::InvalidAndStatement(self.clone()));
-                    }
-                    let left = children
-                        .get(1)
-                        .ok_or(Error::InvalidAndStatement(self.clone()))?
-                        .eval(context)?;
-                    let right = children
-                        .get(2)
-                        .ok_or(Error::InvalidAndStatement(self.clone()))?
-                        .eval(context)?;
-                    match (left, right) {
-                        (Rule::Bool(l), Rule::Bool(r)) => Ok(Rule::Bool(l && r)),
-                        (l, r) => Err(Error::CannotCompare(l, r)),
-                    }
-                }
-                Some(Rule::Or(_)) => {
-                    if children.len() != 3 {
-                        return Err(Error::InvalidOrStatement(self.clone()));
-                    }
-                    let left = children
-                        .get(1)
-                        .ok_or(Error::InvalidOrStatement(self.clone()))?
-                        .eval(context)?;
-                    let right = children
-                        .get(2)
-                        .ok_or(Error::InvalidOrStatement(self.clone()))?
-                        .eval(context)?;
-                    match (left, right) {
-                        (Rule::Bool(l), Rule::Bool(r)) => Ok(Rule::Bool(l || r)),
-                        (l, r) => Err(Error::CannotCompare(l, r)),
-                    }
-                }
-                Some(Rule::In(_)) => {
-                    if children.len() != 3 {
-                        return Err(Error::InvalidInStatement(self.clone()));
-                    }
-                    let left = children
-                        .get(1)
-                        .ok_or(Error::InvalidInStatement(self.clone()))?
-                        .eval(context)?;
-                    let right = children
-                        .get(2)
-                        .ok_or(Error::InvalidInStatement(self.clone()))?
-                        .eval(context)?;
-                    match (left, right) {
-                        (Rule::String(l), Rule::Tuple(ref r)) => {
-                            Ok(Rule::Bool(r.contains(&Rule::String(l))))
-                        }
-                        (Rule::Integer(l), Rule::Tuple(ref r)) => {
-                            Ok(Rule::Bool(r.contains(&Rule::Integer(l))))
-                        }
-                        (Rule::Float(l), Rule::Tuple(ref r)) => {
-                            Ok(Rule::Bool(r.contains(&Rule::Float(l))))
-                        }
-                        (Rule::Bool(l), Rule::Tuple(ref r)) => {
-                            Ok(Rule::Bool(r.contains(&Rule::Bool(l))))
-                        }
-                        (_, _) => Err(Error::InvalidInStatement(self.clone())),
-                    }
-                }
-                _ => Ok(Rule::Tuple(vec![])),
-            },
-            Rule::String(val) => {
-                if val.starts_with('$') {
-                    let key = val.trim_start_matches('$');
-                    match context.0.iter().find(|(k, _)| k == key) {
-                        Some((_, val)) => Ok(val.clone()),
-                        None => Ok(Rule::String(String::new())),
-                    }
-                } else {
-                    Ok(Rule::String(val.clone()))
-                }
-            }
-            val => Ok(val.clone()),
-        }
+    #[test]
+    fn test_eval_rule_contains_err() {
+        assert_eq!(
+            Rule::from_str("(contains-all (list read))")
+                .unwrap()
+                .eval(&Context::from_str("").unwrap()),
+            Err(Error::InvalidContainsStatement(
+                Rule::from_str("(contains-all (list read))").unwrap()
+            ))
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_eval_rule_between_ok() {
+        for (rule, context, expected) in [
+            ("(between $age 18 65)", "age:18", true),
+            ("(between $age 18 65)", "age:65", true),
+            ("(between $age 18 65)", "age:66", false),
+            ("(between-exclusive $age 18 65)", "age:18", false),
+            ("(between-exclusive $age 18 65)", "age:40", true),
+            ("(between $weight 10.5 20.0)", "weight:10.5", true),
+            (
+                "(between-exclusive $weight 10.5 20.0)",
+                "weight:10.5",
+                false,
+            ),
+            (
+                "(between $date 2025-01-01 2025-12-31)",
+                "date:2025-06-15",
+                true,
+            ),
+            (
+                "(between $date 2025-01-01 2025-12-31)",
+                "date:2026-01-01",
+                false,
+            ),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule)
+                    .unwrap()
+                    .eval(&Context::from_str(context).unwrap()),
+                Ok(Rule::Bool(expected))
+            );
+        }
+    }
 
     #[test]
-    fn test_parse_context_ok() {
+    fn test_eval_rule_between_err() {
         assert_eq!(
-            Context::from_str("name:John,age:20,weight:70.5,active:true"),
-            Ok(Context(vec![
-                (String::from("name"), Rule::String(String::from("John"))),
-                (String::from("age"), Rule::Integer(20)),
-                (String::from("weight"), Rule::Float(70.5)),
-                (String::from("active"), Rule::Bool(true)),
-            ]))
+            Rule::from_str("(between $age 18)")
+                .unwrap()
+                .eval(&Context::from_str("age:20").unwrap()),
+            Err(Error::InvalidBetweenStatement(
+                Rule::from_str("(between $age 18)").unwrap()
+            ))
+        );
+        assert_eq!(
+            Rule::from_str("(between $age 18 65.0)")
+                .unwrap()
+                .eval(&Context::from_str("age:20").unwrap()),
+            Err(Error::InvalidBetweenStatement(
+                Rule::from_str("(between $age 18 65.0)").unwrap()
+            ))
         );
     }
 
     #[test]
-    fn test_parse_literal_ok() {
+    fn test_eval_rule_arithmetic_ok() {
+        for (rule, context, expected) in [
+            ("(mod $user_id 10)", "user_id:1234", Rule::Integer(4)),
+            ("(mod $user_id 10)", "user_id:-3", Rule::Integer(7)),
+            ("(mod 7.5 2.0)", "", Rule::Float(1.5)),
+            ("(min 3 $a 5)", "a:1", Rule::Integer(1)),
+            ("(max 3 $a 5)", "a:1", Rule::Integer(5)),
+            ("(max 1.5 0.5)", "", Rule::Float(1.5)),
+            ("(min 2)", "", Rule::Integer(2)),
+            ("(eq (mod $user_id 10) 3)", "user_id:13", Rule::Bool(true)),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule)
+                    .unwrap()
+                    .eval(&Context::from_str(context).unwrap()),
+                Ok(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_rule_arithmetic_err() {
+        for rule in [
+            "(mod 10 0)",
+            "(mod 10 2.0)",
+            "(mod 10)",
+            "(min)",
+            "(max 1 a)",
+            "(min a)",
+        ] {
+            assert_eq!(
+                Rule::from_str(rule)
+                    .unwrap()
+                    .eval(&Context::from_str("").unwrap()),
+                Err(Error::InvalidArithmeticStatement(
+                    Rule::from_str(rule).unwrap()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_rule_dotted_path_ok() {
+        let mut context = Context::from_str("role:admin").unwrap();
+        context.insert_nested(
+            "user",
+            &Context::from_str("id:1,department.name:data,department.floor:3").unwrap(),
+        );
         assert_eq!(
-            Rule::from_literal("John"),
-            Ok(Rule::String(String::from("John")))
+            Rule::from_str("(and (eq $user.department.name data) (eq $user.id 1))")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Bool(true))
         );
-        assert_eq!(Rule::from_literal("20"), Ok(Rule::Integer(20)));
-        assert_eq!(Rule::from_literal("70.5"), Ok(Rule::Float(70.5)));
-        assert_eq!(Rule::from_literal("true"), Ok(Rule::Bool(true)));
-        assert_eq!(Rule::from_literal(""), Ok(Rule::String(String::new())));
         assert_eq!(
-            Rule::from_literal("0d!"),
-            Ok(Rule::String(String::from("0d!")))
+            Rule::from_str("(eq $team.name data)")
+                .unwrap()
+                .eval(&context),
+            Ok(Rule::Bool(false))
         );
     }
 
     #[test]
-    fn test_parse_rule_ok() {
-        assert_eq!(Rule::from_str("()"), Ok(Rule::Tuple(vec![])));
+    fn test_eval_rule_dotted_path_err() {
+        let context = Context::from_str("user.id:1,user.department.floor:3").unwrap();
         assert_eq!(
-            Rule::from_str("(if)"),
-            Ok(Rule::Tuple(vec![Rule::If(String::from("if")),]))
+            Rule::from_str("(eq $user.department.name data)")
+                .unwrap()
+                .eval(&context),
+            Err(Error::MissingSegment(
+                "user.department.name".to_string(),
+                "name".to_string()
+            ))
         );
         assert_eq!(
-            Rule::from_str("(or true true)"),
-            Ok(Rule::Tuple(vec![
-                Rule::Or(String::from("or")),
-                Rule::Bool(true),
-                Rule::Bool(true),
-            ]))
+            Rule::from_str("(eq $user.team.name data)")
+                .unwrap()
+                .eval(&context),
+            Err(Error::MissingSegment(
+                "user.team.name".to_string(),
+                "team".to_string()
+            ))
         );
+    }
+
+    #[test]
+    fn test_rule_operators_ok() {
         assert_eq!(
-            Rule::from_str("(and true true)"),
-            Ok(Rule::Tuple(vec![
-                Rule::And(String::from("and")),
-                Rule::Bool(true),
-                Rule::Bool(true),
-            ]))
+            Rule::from_str(
+                "(if (and (eq $a b) (in $c (list d))) (list all) (usage exports $a today))"
+            )
+            .unwrap()
+            .operators(),
+            vec!["if", "and", "eq", "in", "list", "usage"]
         );
+        assert!(Rule::from_str("()").unwrap().operators().is_empty());
         assert_eq!(
-            Rule::from_str("(if (eq $name true) (list create) (list))"),
-            Ok(Rule::Tuple(vec![
-                Rule::If(String::from("if")),
-                Rule::Tuple(vec![
-                    Rule::Eq(String::from("eq")),
-                    Rule::String(String::from("$name")),
-                    Rule::Bool(true),
-                ]),
-                Rule::Tuple(vec![
-                    Rule::List(String::from("list")),
-                    Rule::String(String::from("create")),
-                ]),
-                Rule::Tuple(vec![Rule::List(String::from("list")),]),
-            ]))
+            Rule::from_str(
+                "(if (in admin (remote-roles $user_id)) (list all) (map (list 1) (fn (x) x)))"
+            )
+            .unwrap()
+            .operators(),
+            vec!["if", "in", "remote-roles", "list", "map", "fn"]
         );
+    }
+
+    #[test]
+    fn test_rule_grants_only_if_ok() {
+        let check = Rule::from_str("(eq $path.t $tenant)").unwrap();
+        for (rule, expected) in [
+            ("(if (eq $tenant $path.t) (list all) (list))", true),
+            (
+                "(if (and (eq $role admin) (eq $path.t $tenant)) (list all) (deny read))",
+                true,
+            ),
+            (
+                "(concat (if (eq $path.t $tenant) (list read) (list)) (if (or (eq $path.t $tenant) (eq $tenant $path.t)) (list update) ()))",
+                true,
+            ),
+            ("(list read)", false),
+            ("(if (eq $path.t $tenant) (list all) (list read))", false),
+            (
+                "(if (or (eq $role admin) (eq $tenant $tenant)) (list all) (list))",
+                false,
+            ),
+            ("(if (eq $path.t $other) (list all) (list))", false),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule).unwrap().grants_only_if(&check),
+                expected,
+                "{rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rule_granted_operations_ok() {
         assert_eq!(
-            Rule::from_str("(if (eq $name true) (list create) (list))"),
-            Ok(Rule::Tuple(vec![
-                Rule::If(String::from("if")),
-                Rule::Tuple(vec![
-                    Rule::Eq(String::from("eq")),
-                    Rule::String(String::from("$name")),
-                    Rule::Bool(true),
-                ]),
-                Rule::Tuple(vec![
-                    Rule::List(String::from("list")),
-                    Rule::String(String::from("create")),
-                ]),
-                Rule::Tuple(vec![Rule::List(String::from("list")),]),
-            ]))
+            Rule::from_str(
+                "(if (in $c (list d)) (concat (list read $op) (deny craete)) (list all update))"
+            )
+            .unwrap()
+            .granted_operations(),
+            vec!["read", "craete", "update"]
         );
     }
 
     #[test]
-    fn test_parse_rule_err() {
-        assert_eq!(Rule::from_str(""), Err(Error::CannotParse(String::new())));
+    fn test_rule_expand_all_ok() {
         assert_eq!(
-            Rule::from_str("(if (eq )) (list create) (list))"),
-            Err(Error::CannotParse(
-                "(if (eq )) (list create) (list))".to_string()
-            ))
+            Rule::from_str("(if (in all (list all)) (perms all (deny all)) (concat (list all)))")
+                .unwrap()
+                .expand_all(&[String::from("read"), String::from("list")]),
+            Rule::from_str(
+                "(if (in all (list all)) (perms read list (deny all)) (concat (list read list)))"
+            )
+            .unwrap()
         );
     }
 
     #[test]
-    fn test_eval_rule_in_ok() {
+    fn test_rule_node_count_ok() {
+        assert_eq!(Rule::from_str("()").unwrap().node_count(), 1);
         assert_eq!(
-            Rule::from_str("(  in   john   ( list ) )")
+            Rule::from_str("(if (eq $a b) (list all) (list))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .node_count(),
+            11
         );
+    }
+
+    #[test]
+    fn test_rule_eval_strict_err() {
+        let rule = Rule::from_str("(eq $rol admin)").unwrap();
+        let context = Context::from_str("role:admin").unwrap();
+        assert_eq!(rule.eval(&context), Ok(Rule::Bool(false)));
         assert_eq!(
-            Rule::from_str(
-                "(
-                in 10 (
-                    list
-                )
-            )"
-            )
-            .unwrap()
-            .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+            rule.eval_in(&context, &mut Environment::default().strict()),
+            Err(Error::KeyNotInContext(String::from("rol")))
         );
         assert_eq!(
-            Rule::from_str("(in john (list john jane))")
+            Rule::from_str("(eq $role admin)")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
+                .eval_in(&context, &mut Environment::default().strict()),
+            Ok(Rule::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_rule_eval_numeric_coercion_ok() {
+        let context = Context::from_str("age:18,score:7.5").unwrap();
+        let coercing = || Environment::default().with_numeric_coercion(true);
+        let rule = Rule::from_str("(eq $age 18.0)").unwrap();
+        assert_eq!(
+            rule.eval(&context),
+            Err(Error::CannotCompare(Rule::Integer(18), Rule::Float(18.0)))
+        );
+        assert_eq!(
+            rule.eval_in(&context, &mut coercing()),
             Ok(Rule::Bool(true))
         );
         assert_eq!(
-            Rule::from_str("(in john (list jane))")
+            Rule::from_str("(in $age (list 16.0 18.0))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval_in(&context, &mut coercing()),
+            Ok(Rule::Bool(true))
         );
         assert_eq!(
-            Rule::from_str("(in john (list 10))")
+            Rule::from_str("(between $score 5 10)")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval_in(&context, &mut coercing()),
+            Ok(Rule::Bool(true))
         );
         assert_eq!(
-            Rule::from_str("(in john (list 10 john))")
+            Rule::from_str("(eq $age 18)")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
+                .eval_in(&context, &mut coercing()),
             Ok(Rule::Bool(true))
         );
     }
+
     #[test]
-    fn test_eval_rule_in_err() {
+    fn test_rule_ne_ok() {
+        let context = Context::from_str("role:admin,age:20,weight:70.5,active:true").unwrap();
+        for (rule, expected) in [
+            ("(ne $role admin)", false),
+            ("(ne $role user)", true),
+            ("(ne $age 20)", false),
+            ("(ne $weight 80.0)", true),
+            ("(ne $active false)", true),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule).unwrap().eval(&context),
+                Ok(Rule::Bool(expected)),
+                "{rule}"
+            );
+        }
         assert_eq!(
-            Rule::from_str("(in john jane)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidInStatement(Rule::Tuple(vec![
-                Rule::In(String::from("in")),
-                Rule::String(String::from("john")),
-                Rule::String(String::from("jane")),
-            ])))
+            Rule::from_str("(ne $age admin)").unwrap().eval(&context),
+            Err(Error::CannotCompare(
+                Rule::Integer(20),
+                Rule::String(String::from("admin"))
+            ))
         );
         assert_eq!(
-            Rule::from_str("(in (list john) jane)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidInStatement(Rule::Tuple(vec![
-                Rule::In(String::from("in")),
-                Rule::Tuple(vec![
-                    Rule::List(String::from("list")),
-                    Rule::String(String::from("john"))
-                ]),
-                Rule::String(String::from("jane")),
-            ])))
+            Rule::from_str("(ne $role admin)").unwrap().to_string(),
+            "(ne $role admin)"
         );
     }
 
     #[test]
-    fn test_eval_rule_and_ok() {
+    fn test_rule_interpolation_ok() {
+        let rule = Rule::from_str("(eq $resource_owner {$tenant}/{$user_id})").unwrap();
         assert_eq!(
-            Rule::from_str("(and true true)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+            rule.variables(),
+            vec!["resource_owner", "tenant", "user_id"]
         );
         assert_eq!(
-            Rule::from_str("(and true false)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+            rule.eval(&Context::from_str("resource_owner:acme/42,tenant:acme,user_id:42").unwrap()),
+            Ok(Rule::Bool(true))
         );
         assert_eq!(
-            Rule::from_str("(and false true)")
+            Rule::from_str("(concat org- {$tenant}/{$user_id}/{unchanged})")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval(&Context::from_str("tenant:acme,user_id:42").unwrap()),
+            Ok(Rule::String(String::from("org-acme/42/{unchanged}")))
         );
         assert_eq!(
-            Rule::from_str("(and false false)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+            Rule::from_str("(list {$tenant})").unwrap().eval_in(
+                &Context::from_str("").unwrap(),
+                &mut Environment::default().strict()
+            ),
+            Err(Error::KeyNotInContext(String::from("tenant")))
         );
     }
 
     #[test]
-    fn test_eval_rule_and_err() {
+    fn test_rule_has_key_ok() {
+        let rule = Rule::from_str("(if (has-key role) (list read) (list))").unwrap();
+        assert_eq!(rule.variables(), vec!["role"]);
+        assert_eq!(rule.optimize(), rule);
         assert_eq!(
-            Rule::from_str("(and true)")
+            rule.eval(&Context::from_str("role:").unwrap()),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("read"))]))
+        );
+        assert_eq!(rule.eval(&Context::anonymous()), Ok(Rule::Tuple(vec![])));
+        assert_eq!(
+            Rule::from_str("(has-key subject)")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidAndStatement(Rule::Tuple(vec![
-                Rule::And(String::from("and")),
-                Rule::Bool(true),
-            ])))
+                .eval(&Context::from_str("subject.role:admin").unwrap()),
+            Ok(Rule::Bool(true))
         );
     }
 
     #[test]
-    fn test_eval_rule_or_ok() {
+    fn test_rule_has_key_err() {
         assert_eq!(
-            Rule::from_str("(or true true)")
+            Rule::from_str("(has-key role user)")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+                .eval(&Context::anonymous()),
+            Err(Error::InvalidHasKeyStatement(
+                Rule::from_str("(has-key role user)").unwrap()
+            ))
         );
         assert_eq!(
-            Rule::from_str("(or true false)")
+            Rule::from_str("(has-key (list role))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
+                .eval(&Context::anonymous()),
+            Err(Error::InvalidHasKeyStatement(
+                Rule::from_str("(has-key (list role))").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_rule_lambda_ok() {
+        let context = Context::from_str("role:admin,limit:2").unwrap();
+        for (rule, expected) in [
+            (
+                "(any (list user admin) (fn (x) (eq x $role)))",
+                Rule::Bool(true),
+            ),
+            ("(any (list) (fn (x) (eq x $role)))", Rule::Bool(false)),
+            (
+                "(filter (list 1 2 3) (fn (n) (between n 0 $limit)))",
+                Rule::Tuple(vec![Rule::Integer(1), Rule::Integer(2)]),
+            ),
+            (
+                "(map (list read list) (fn (op) (concat $role : op)))",
+                Rule::Tuple(vec![
+                    Rule::String(String::from("admin:read")),
+                    Rule::String(String::from("admin:list")),
+                ]),
+            ),
+            (
+                "(map (list 1 2) (fn (x) (any (list 2) (fn (x) (eq x 2)))))",
+                Rule::Tuple(vec![Rule::Bool(true), Rule::Bool(true)]),
+            ),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule).unwrap().eval(&context),
+                Ok(expected),
+                "{rule}"
+            );
+        }
+        let context = Context::from_str("name:$secret,secret:TOPSECRET,role:admin").unwrap();
+        for (rule, expected) in [
+            (
+                "(map (list $name) (fn (x) x))",
+                Rule::from_str("($secret)").unwrap(),
+            ),
+            (
+                "(filter (list $name) (fn (x) (eq x admin)))",
+                Rule::Tuple(vec![]),
+            ),
+            (
+                "(map (list (list 1 2)) (fn (x) x))",
+                Rule::from_str("((1 2))").unwrap(),
+            ),
+        ] {
+            assert_eq!(
+                Rule::from_str(rule).unwrap().eval(&context),
+                Ok(expected),
+                "{rule}"
+            );
+        }
+        let rule = Rule::from_str("(any (list user admin) (fn (x) (eq x admin)))").unwrap();
+        assert_eq!(rule.optimize(), Rule::Bool(true));
+        assert_eq!(
+            Program::compile(&rule).unwrap().eval(&context),
             Ok(Rule::Bool(true))
         );
+    }
+
+    #[test]
+    fn test_rule_lambda_err() {
+        let context = Context::from_str("").unwrap();
         assert_eq!(
-            Rule::from_str("(or false true)")
+            Rule::from_str("(any (list 1) (fn (x y) (eq x y)))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+                .eval(&context),
+            Err(Error::InvalidLambdaStatement(
+                Rule::from_str("(fn (x y) (eq x y))").unwrap()
+            ))
         );
         assert_eq!(
-            Rule::from_str("(or false false)")
+            Rule::from_str("(filter (list 1) (fn (x) x))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval(&context),
+            Err(Error::InvalidIfCondition(Rule::Integer(1)))
         );
-    }
-
-    #[test]
-    fn test_eval_rule_or_err() {
         assert_eq!(
-            Rule::from_str("(or true)")
+            Rule::from_str("(any (list 1) (list 1))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidOrStatement(Rule::Tuple(vec![
-                Rule::Or(String::from("or")),
-                Rule::Bool(true),
-            ])))
+                .eval(&context),
+            Err(Error::InvalidLambdaStatement(
+                Rule::from_str("(any (list 1) (list 1))").unwrap()
+            ))
         );
     }
 
     #[test]
-    fn test_eval_rule_eq_ok() {
+    fn test_rule_try_ok() {
+        let rule = Rule::from_str("(if (try (eq $age 18) false) (list read) (list))").unwrap();
         assert_eq!(
-            Rule::from_str("(eq $a $b)")
-                .unwrap()
-                .eval(&Context::from_str("a:10,b:10").unwrap()),
-            Ok(Rule::Bool(true))
+            rule.eval(&Context::from_str("age:18").unwrap()),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("read"))]))
         );
         assert_eq!(
-            Rule::from_str("(eq john john)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+            rule.eval(&Context::from_str("age:adult").unwrap()),
+            Ok(Rule::Tuple(vec![]))
         );
         assert_eq!(
-            Rule::from_str("(eq john jane)")
+            Program::compile(&rule)
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval(&Context::from_str("age:adult").unwrap()),
+            Ok(Rule::Tuple(vec![]))
         );
         assert_eq!(
-            Rule::from_str("(eq 10 10)")
+            Rule::from_str("(try (eq $age 18) (eq $age adult))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
+                .eval(&Context::from_str("age:adult").unwrap()),
             Ok(Rule::Bool(true))
         );
+    }
+
+    #[test]
+    fn test_rule_try_err() {
+        let context = Context::from_str("age:adult").unwrap();
         assert_eq!(
-            Rule::from_str("(eq 10 20)")
+            Rule::from_str("(try (eq $age 18) (eq $age 21))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval(&context),
+            Err(Error::CannotCompare(
+                Rule::String(String::from("adult")),
+                Rule::Integer(21)
+            ))
         );
         assert_eq!(
-            Rule::from_str("(eq 10.0 10.0)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+            Rule::from_str("(try (eq $age 18))").unwrap().eval(&context),
+            Err(Error::InvalidTryStatement(
+                Rule::from_str("(try (eq $age 18))").unwrap()
+            ))
         );
         assert_eq!(
-            Rule::from_str("(eq 10.0 20.0)")
+            Rule::from_str("(try (and (eq 1 1) (eq 1 1)) false)")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+                .eval_with_budget(&context, &mut Budget::default().with_nodes(3)),
+            Err(Error::BudgetExhausted)
         );
         assert_eq!(
-            Rule::from_str("(eq true true)")
+            Rule::from_str("(list (try (list (list 1)) false))")
                 .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+                .eval_with_max_depth(&context, 2),
+            Err(Error::MaxDepthExceeded(2))
         );
+    }
+
+    #[test]
+    fn test_parse_rule_escaping_ok() {
         assert_eq!(
-            Rule::from_str("(eq true false)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(false))
+            Rule::from_str(r#"(eq $name "John (Jr.); \"JJ\"")"#),
+            Ok(Rule::Tuple(vec![
+                Rule::Eq(String::from("eq")),
+                Rule::String(String::from("$name")),
+                Rule::String(String::from("John (Jr.); \"JJ\"")),
+            ]))
+        );
+        assert_eq!(
+            Rule::from_str(r"(in a\(b\) (list \10 a\ b if))"),
+            Ok(Rule::Tuple(vec![
+                Rule::In(String::from("in")),
+                Rule::String(String::from("a(b)")),
+                Rule::Tuple(vec![
+                    Rule::List(String::from("list")),
+                    Rule::String(String::from("10")),
+                    Rule::String(String::from("a b")),
+                    Rule::String(String::from("if")),
+                ]),
+            ]))
+        );
+        for rule in [
+            r#"(eq $name "John (Jr.)")"#,
+            r#"(list "10" "" "a \"b\" \\ c")"#,
+        ] {
+            let parsed = Rule::from_str(rule).unwrap();
+            assert_eq!(Rule::from_str(&parsed.to_string()), Ok(parsed), "{rule}");
+        }
+        assert_eq!(
+            Rule::from_str(r#"(list "unterminated)"#),
+            Err(Error::CannotParse(String::from(r#"(list "unterminated)"#)))
+        );
+        assert_eq!(
+            Rule::from_str("(list read"),
+            Err(Error::CannotParse(String::from("(list read")))
         );
     }
 
     #[test]
-    fn test_eval_rule_eq_err() {
+    fn test_context_map_api_ok() {
+        let mut context = Context::from_str("role:admin,id:7,groups:[a|b]").unwrap();
+        assert_eq!(context.len(), 3);
+        assert!(context.contains_key("id"));
+        assert_eq!(context.remove("id"), Some(Rule::Integer(7)));
+        assert_eq!(context.remove("id"), None);
+        assert!(!context.contains_key("id"));
         assert_eq!(
-            Rule::from_str("(eq)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidEqStatement(Rule::Tuple(vec![Rule::Eq(
-                String::from("eq")
-            ),])))
+            context.iter().map(|(k, _)| k).collect::<Vec<&str>>(),
+            vec!["role", "groups"]
         );
         assert_eq!(
-            Rule::from_str("(eq john)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidEqStatement(Rule::Tuple(vec![
-                Rule::Eq(String::from("eq")),
-                Rule::String(String::from("john")),
-            ])))
+            serde_json::to_string(&context).unwrap(),
+            r#"{"role":"admin","groups":["a","b"]}"#
+        );
+        let context = Context::from_str("active:true,score:0.5,user.id:7").unwrap();
+        assert_eq!(
+            Context::try_from(serde_json::to_value(&context).unwrap()),
+            Ok(context)
         );
+        assert!(Context::default().is_empty());
     }
 
     #[test]
-    fn test_eval_rule_if_ok() {
+    fn test_context_display_ok() {
+        let context = Context::builder()
+            .str("at", "2024-01-01T10:00:00")
+            .str("name", "Doe, John")
+            .str("code", "42")
+            .str("quote", r#""a\b""#)
+            .str("empty", "")
+            .str("bracket", "[x]")
+            .str("key:with,separators", "v")
+            .int("id", 42)
+            .float("score", 1.0)
+            .bool("admin", true)
+            .list("groups", ["a|b", "7", "c]", "d"])
+            .build();
+        assert_eq!(Context::from_str(&context.to_string()), Ok(context));
         assert_eq!(
-            Rule::from_str("(if true true false")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Bool(true))
+            Context::builder()
+                .str("at", "10:00")
+                .int("id", 1)
+                .build()
+                .to_string(),
+            "at:10:00,id:1"
         );
+    }
+
+    #[test]
+    fn test_parse_context_escaping_ok() {
         assert_eq!(
-            Rule::from_str("(if false true 10)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Ok(Rule::Integer(10))
+            Context::from_str(r#"name:"Doe, John",path:/a\:b\,c,url:http://x,code:"42",id:\42"#),
+            Ok(Context(vec![
+                (
+                    String::from("name"),
+                    Rule::String(String::from("Doe, John"))
+                ),
+                (String::from("path"), Rule::String(String::from("/a:b,c"))),
+                (String::from("url"), Rule::String(String::from("http://x"))),
+                (String::from("code"), Rule::String(String::from("42"))),
+                (String::from("id"), Rule::String(String::from("42"))),
+            ]))
+        );
+        assert_eq!(
+            Context::from_str("name"),
+            Err(Error::CannotParse(String::from("name")))
         );
     }
 
     #[test]
-    fn test_eval_rule_if_err() {
+    fn test_parse_rule_bracket_list_ok() {
         assert_eq!(
-            Rule::from_str("(if)")
-                .unwrap()
-                .eval(&Context::from_str("").unwrap()),
-            Err(Error::InvalidIfStatement(Rule::Tuple(vec![Rule::If(
-                String::from("if")
-            ),])))
+            Rule::from_str("(if (in $group [admins \"on call\" 42]) [read update] [])"),
+            Rule::from_str(
+                "(if (in $group (list admins \"on call\" 42)) (list read update) (list))"
+            )
+        );
+        assert_eq!(
+            Rule::from_str("[[a] (list b)]").unwrap().to_string(),
+            "(list (list a) (list b))"
         );
     }
 
     #[test]
-    fn test_eval_rule_ok() {
+    fn test_parse_rule_bracket_list_err() {
+        for rule in ["[read update)", "(list read]", "[read", "read]"] {
+            assert_eq!(
+                Rule::from_str(rule),
+                Err(Error::CannotParse(String::from(rule))),
+                "{rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rule_eval_trace_ok() {
+        let mut environment = Environment::default().with_trace();
         assert_eq!(
-            Rule::from_str("(if (eq $name john) (list create) (list))")
+            Rule::from_str("(if (eq $role admin) (list all) (list read))")
                 .unwrap()
-                .eval(&Context::from_str("name:john,role:admin").unwrap()),
-            Ok(Rule::Tuple(vec![Rule::String(String::from("create")),]))
+                .eval_in(&Context::from_str("role:user").unwrap(), &mut environment),
+            Ok(Rule::Tuple(vec![Rule::String(String::from("read"))]))
         );
         assert_eq!(
-            Rule::from_str("(if (eq $role admin) (list create read update delete list) (if (eq $role user) (list read list) (list)))")
-                .unwrap()
-                .eval(&Context::from_str("name:john,role:admin").unwrap()),
-            Ok(Rule::Tuple(vec![
-                Rule::String(String::from("create")),
-                Rule::String(String::from("read")),
-                Rule::String(String::from("update")),
-                Rule::String(String::from("delete")),
-                Rule::String(String::from("list")),
-            ]))
+            environment
+                .take_trace()
+                .iter()
+                .map(|(rule, value)| format!("{rule} => {value}"))
+                .collect::<Vec<String>>(),
+            vec![
+                "(eq $role admin) => false",
+                "(list all) => (all)",
+                "(list read) => (read)",
+                "(if (eq $role admin) (list all) (list read)) => (read)",
+            ]
         );
+        assert!(environment.take_trace().is_empty());
+        assert!(Environment::default().take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_rule_eval_memoization_ok() {
+        let rule = Rule::from_str("(and (eq (concat $a $b) xy) (eq (concat $a $b) xy))").unwrap();
+        let context = Context::from_str("a:x,b:y").unwrap();
         assert_eq!(
-            Rule::from_str("(if (eq $role admin) (list create read update delete list) (if (eq $role user) (list read list) (list)))")
-                .unwrap()
-                .eval(&Context::from_str("name:john,role:user").unwrap()),
-            Ok(Rule::Tuple(vec![
-                Rule::String(String::from("read")),
-                Rule::String(String::from("list")),
-            ]))
+            rule.eval_with_budget(&context, &mut Budget::default().with_nodes(7)),
+            Err(Error::BudgetExhausted)
+        );
+        assert_eq!(
+            rule.eval_in(
+                &context,
+                &mut Environment::default()
+                    .with_budget(Budget::default().with_nodes(7))
+                    .with_memoization()
+            ),
+            Ok(Rule::Bool(true))
         );
     }
 }
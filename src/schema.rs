@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+    String,
+    Bool,
+    Integer,
+    Float,
+    List,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+enum Declaration {
+    Type(AttributeType),
+    Detailed {
+        #[serde(rename = "type")]
+        attribute_type: AttributeType,
+        #[serde(default)]
+        required: bool,
+    },
+}
+
+impl Declaration {
+    fn attribute_type(self) -> AttributeType {
+        match self {
+            Declaration::Type(attribute_type) | Declaration::Detailed { attribute_type, .. } => {
+                attribute_type
+            }
+        }
+    }
+
+    fn required(self) -> bool {
+        matches!(self, Declaration::Detailed { required: true, .. })
+    }
+}
+
+/// Attributes the engine supplies itself: the operation being checked and the
+/// environment values a clock provides.
+pub(crate) const BUILTINS: [(&str, AttributeType); 5] = [
+    ("action", AttributeType::String),
+    ("env.now", AttributeType::String),
+    ("env.date", AttributeType::String),
+    ("env.weekday", AttributeType::String),
+    ("env.hour", AttributeType::Integer),
+];
+
+#[cfg(feature = "async")]
+pub(crate) fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|(builtin, _)| *builtin == name)
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(transparent)]
+pub struct Schema(BTreeMap<String, Declaration>);
+
+impl Schema {
+    #[must_use]
+    pub fn new() -> Self {
+        Schema::default()
+    }
+
+    pub fn insert(&mut self, name: &str, attribute_type: AttributeType) {
+        self.0
+            .insert(name.to_string(), Declaration::Type(attribute_type));
+    }
+
+    pub fn insert_required(&mut self, name: &str, attribute_type: AttributeType) {
+        self.0.insert(
+            name.to_string(),
+            Declaration::Detailed {
+                attribute_type,
+                required: true,
+            },
+        );
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<AttributeType> {
+        self.0
+            .get(name)
+            .map(|declaration| declaration.attribute_type())
+    }
+
+    pub fn required(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(|(_, declaration)| declaration.required())
+            .map(|(name, _)| name.as_str())
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+}
+
+impl<'a> FromIterator<(&'a str, AttributeType)> for Schema {
+    fn from_iter<T: IntoIterator<Item = (&'a str, AttributeType)>>(iter: T) -> Self {
+        Schema(
+            iter.into_iter()
+                .map(|(name, attribute_type)| (name.to_string(), Declaration::Type(attribute_type)))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_deserialization_ok() {
+        assert_eq!(
+            toml::from_str::<Schema>(
+                r#"
+                role = "string"
+                age = "integer"
+                scopes = "list"
+            "#
+            ),
+            Ok(Schema::from_iter([
+                ("role", AttributeType::String),
+                ("age", AttributeType::Integer),
+                ("scopes", AttributeType::List),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_schema_required_deserialization_ok() {
+        let schema = toml::from_str::<Schema>(
+            r#"
+            role = { type = "string", required = true }
+            age = "integer"
+            mfa = { type = "bool" }
+            "#,
+        )
+        .unwrap();
+        let mut expected = Schema::from_iter([("age", AttributeType::Integer)]);
+        expected.insert_required("role", AttributeType::String);
+        assert_eq!(schema.get("mfa"), Some(AttributeType::Bool));
+        assert_eq!(schema.required().collect::<Vec<&str>>(), vec!["role"]);
+        expected.0.insert(
+            String::from("mfa"),
+            Declaration::Detailed {
+                attribute_type: AttributeType::Bool,
+                required: false,
+            },
+        );
+        assert_eq!(schema, expected);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::permission::{Operation, Permission};
+use crate::resource::{Error, Path, PathOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Operations an OAuth2 scope stands for, on every resource under one of
+/// `prefixes`. A `:param` prefix segment matches any segment.
+#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
+pub struct ScopeGrant {
+    pub operations: Permission,
+    pub prefixes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Scopes(pub BTreeMap<String, ScopeGrant>);
+
+impl Scopes {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Checks that every prefix parses under the hierarchy's path `options`.
+    pub fn validate(&self, options: PathOptions) -> Result<(), Error> {
+        for grant in self.0.values() {
+            for prefix in &grant.prefixes {
+                Path::parse(prefix, options)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether one of the space-separated `scopes` grants `to` on `on`, with
+    /// prefixes parsed like request paths under `options`. Unknown scopes
+    /// grant nothing.
+    #[must_use]
+    pub fn permits(&self, scopes: &str, to: &Operation, on: &Path, options: PathOptions) -> bool {
+        scopes
+            .split_whitespace()
+            .filter_map(|scope| self.0.get(scope))
+            .filter(|grant| to.allowed_for(grant.operations))
+            .flat_map(|grant| &grant.prefixes)
+            .filter_map(|prefix| Path::parse(prefix, options).ok())
+            .any(|prefix| on.starts_with(&prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::resource::Hierarchy;
+    use crate::rule::Context;
+    use std::str::FromStr;
+
+    fn hierarchy() -> Result<Hierarchy, Error> {
+        toml::from_str::<Config>(
+            r#"
+            [scopes]
+            "repo:read" = {operations = "read|list", prefixes = ["/repos"]}
+            "repo:write" = {operations = "create|update", prefixes = ["/repos/:repo/issues", "/gists"]}
+
+            [resources]
+            "/" = {access_rule = "(list all)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+    }
+
+    #[test]
+    fn test_scopes_ok() {
+        let rh = hierarchy().unwrap();
+        let context = Context::default();
+        for (scopes, operation, path, expected) in [
+            ("repo:read", Operation::Read, "/repos/abac", true),
+            ("repo:read", Operation::List, "/repos", true),
+            ("repo:read", Operation::Update, "/repos/abac", false),
+            ("repo:read", Operation::Read, "/repository", false),
+            (
+                "openid repo:write",
+                Operation::Create,
+                "/repos/abac/issues/1",
+                true,
+            ),
+            (
+                "repo:write",
+                Operation::Create,
+                "/repos/abac/pulls/1",
+                false,
+            ),
+            ("repo:write", Operation::Update, "/gists/42", true),
+            ("", Operation::Read, "/repos/abac", false),
+        ] {
+            assert_eq!(
+                rh.is_allowed_with_scopes(
+                    scopes,
+                    operation.clone(),
+                    &Path::from_str(path).unwrap(),
+                    &context
+                ),
+                Ok(expected),
+                "{scopes} {operation} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scopes_err() {
+        assert_eq!(
+            Hierarchy::try_from(
+                toml::from_str::<Config>(
+                    r#"
+                    [scopes]
+                    "repo:read" = {operations = "read", prefixes = ["repos"]}
+                    [resources]
+                "#,
+                )
+                .unwrap()
+            ),
+            Err(Error::FormatError(String::from("repos")))
+        );
+        assert!(toml::from_str::<Config>(
+            r#"
+            [scopes]
+            "repo:read" = {operations = "raed", prefixes = ["/repos"]}
+            [resources]
+        "#,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::request::AccessRequest;
+use crate::resource::{self, Hierarchy};
+use crate::rule::Rule;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const SAMPLES: usize = 5;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("Invalid path in corpus request {0}: {1}")]
+    InvalidRequest(usize, resource::Error),
+    #[error("Policy exceeds {} SLO target(s)", .0.len())]
+    Exceeded(Vec<Violation>),
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Serialize)]
+pub struct Slo {
+    pub max_decision_latency_us: Option<u64>,
+    pub max_policy_nodes: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    DecisionLatency {
+        request: usize,
+        latency: Duration,
+        limit: Duration,
+    },
+    PolicySize {
+        nodes: usize,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::DecisionLatency {
+                request,
+                latency,
+                limit,
+            } => write!(f, "request {request} took {latency:?}, over {limit:?}"),
+            Violation::PolicySize { nodes, limit } => {
+                write!(f, "policy has {nodes} rule nodes, over {limit}")
+            }
+        }
+    }
+}
+
+impl Slo {
+    /// Measures the policy against the targets, timing every request of the
+    /// corpus. Fails on requests whose path the policy cannot parse.
+    pub fn check(
+        &self,
+        hierarchy: &Hierarchy,
+        corpus: &[AccessRequest],
+    ) -> Result<Vec<Violation>, Error> {
+        let mut violations = Vec::new();
+        if let Some(limit) = self.max_policy_nodes {
+            let nodes = hierarchy
+                .resources()
+                .iter()
+                .flat_map(|(_, attributes)| [&attributes.access_rule, &attributes.list_filter_rule])
+                .flatten()
+                .map(Rule::node_count)
+                .sum();
+            if nodes > limit {
+                violations.push(Violation::PolicySize { nodes, limit });
+            }
+        }
+        if let Some(limit) = self.max_decision_latency_us.map(Duration::from_micros) {
+            for (index, request) in corpus.iter().enumerate() {
+                let latency = decision_latency(hierarchy, request)
+                    .map_err(|e| Error::InvalidRequest(index, e))?;
+                if latency > limit {
+                    violations.push(Violation::DecisionLatency {
+                        request: index,
+                        latency,
+                        limit,
+                    });
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Like [`Slo::check`], but fails when any target is exceeded.
+    pub fn validate(&self, hierarchy: &Hierarchy, corpus: &[AccessRequest]) -> Result<(), Error> {
+        let violations = self.check(hierarchy, corpus)?;
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Exceeded(violations))
+        }
+    }
+}
+
+fn decision_latency(
+    hierarchy: &Hierarchy,
+    request: &AccessRequest,
+) -> Result<Duration, resource::Error> {
+    let path = hierarchy.path(&request.path)?;
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            let _ = hierarchy.is_allowed(request.operation.clone(), &path, &request.context);
+            start.elapsed()
+        })
+        .collect();
+    samples.sort_unstable();
+    Ok(samples[SAMPLES / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::request::LogFormat;
+
+    #[test]
+    fn test_slo_check_ok() {
+        let config = toml::from_str::<Config>(
+            r#"
+            [slo]
+            max_decision_latency_us = 1000000
+            max_policy_nodes = 20
+
+            [resources]
+            "/reports" = {access_rule = "(if (eq $principal user) (list read) (list))"}
+        "#,
+        )
+        .unwrap();
+        let slo = config.slo.clone();
+        let rh: Hierarchy = config.try_into().unwrap();
+        let corpus = LogFormat::Nginx
+            .parse_all(
+                r#"10.0.0.1 - alice [10/Oct/2024:13:55:36 +0000] "GET /reports HTTP/1.1" 200 0 "-" "-""#,
+            )
+            .unwrap();
+        assert_eq!(slo.check(&rh, &corpus), Ok(vec![]));
+        assert_eq!(slo.validate(&rh, &corpus), Ok(()));
+    }
+
+    #[test]
+    fn test_slo_check_err() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/reports" = {access_rule = "(if (eq $principal user) (list read) (list))"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let corpus = LogFormat::Nginx
+            .parse_all(
+                r#"10.0.0.1 - alice [10/Oct/2024:13:55:36 +0000] "GET /reports HTTP/1.1" 200 0 "-" "-""#,
+            )
+            .unwrap();
+        let slo = Slo {
+            max_decision_latency_us: Some(0),
+            max_policy_nodes: Some(3),
+        };
+        let violations = slo.check(&rh, &corpus).unwrap();
+        assert_eq!(
+            violations[0],
+            Violation::PolicySize {
+                nodes: 11,
+                limit: 3
+            }
+        );
+        assert!(matches!(
+            violations[1],
+            Violation::DecisionLatency {
+                request: 0,
+                limit: Duration::ZERO,
+                ..
+            }
+        ));
+        assert!(matches!(
+            slo.validate(&rh, &corpus),
+            Err(Error::Exceeded(violations)) if violations.len() == 2
+        ));
+
+        let corpus = LogFormat::Nginx
+            .parse_all(
+                r#"10.0.0.1 - alice [10/Oct/2024:13:55:36 +0000] "GET /reports/../admin HTTP/1.1" 200 0 "-" "-""#,
+            )
+            .unwrap();
+        assert!(matches!(
+            slo.check(&rh, &corpus),
+            Err(Error::InvalidRequest(0, resource::Error::DotSegment(_)))
+        ));
+    }
+
+    #[test]
+    fn test_slo_check_list_filter_rule_ok() {
+        let rh: Hierarchy = toml::from_str::<Config>(
+            r#"
+            [resources]
+            "/reports" = {access_rule = "(list list)", list_filter_rule = "(eq $resource.owner $user)"}
+        "#,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let slo = Slo {
+            max_decision_latency_us: None,
+            max_policy_nodes: Some(3),
+        };
+        assert_eq!(
+            slo.check(&rh, &[]),
+            Ok(vec![Violation::PolicySize { nodes: 7, limit: 3 }])
+        );
+    }
+}
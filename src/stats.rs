@@ -0,0 +1,73 @@
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceStats {
+    pub allowed: u64,
+    pub denied: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct NodeStats {
+    #[cfg(feature = "stats")]
+    allowed: AtomicU64,
+    #[cfg(feature = "stats")]
+    denied: AtomicU64,
+    #[cfg(feature = "stats")]
+    errors: AtomicU64,
+}
+
+impl NodeStats {
+    #[cfg(feature = "stats")]
+    pub(crate) fn record<T, E>(&self, outcome: &Result<Option<T>, E>) {
+        let counter = match outcome {
+            Ok(Some(_)) => &self.allowed,
+            Ok(None) => &self.denied,
+            Err(_) => &self.errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(not(feature = "stats"))]
+    pub(crate) fn record<T, E>(&self, _: &Result<Option<T>, E>) {}
+
+    #[cfg(feature = "stats")]
+    #[must_use]
+    pub fn snapshot(&self) -> ResourceStats {
+        ResourceStats {
+            allowed: self.allowed.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    #[cfg(not(feature = "stats"))]
+    #[must_use]
+    pub fn snapshot(&self) -> ResourceStats {
+        ResourceStats::default()
+    }
+}
+
+impl Clone for NodeStats {
+    #[cfg(feature = "stats")]
+    fn clone(&self) -> Self {
+        let stats = self.snapshot();
+        NodeStats {
+            allowed: AtomicU64::new(stats.allowed),
+            denied: AtomicU64::new(stats.denied),
+            errors: AtomicU64::new(stats.errors),
+        }
+    }
+
+    #[cfg(not(feature = "stats"))]
+    fn clone(&self) -> Self {
+        NodeStats::default()
+    }
+}
+
+impl PartialEq for NodeStats {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
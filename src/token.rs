@@ -0,0 +1,307 @@
+use crate::permission::{Operation, Permission};
+use crate::resource::{self, Hierarchy};
+use crate::rule::{self, Context};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Error {
+    #[error("Malformed permission token")]
+    Malformed,
+    #[error("Invalid permission token signature")]
+    InvalidSignature,
+    #[error("Permission token expired")]
+    Expired,
+    #[error("Permission token was issued for another policy version")]
+    PolicyChanged,
+    #[error("Permission token was issued for resource '{0}'")]
+    ResourceMismatch(String),
+    #[error("Permission token was issued for another subject")]
+    SubjectMismatch,
+    #[error("Resource error: {0}")]
+    Resource(#[from] resource::Error),
+    #[error("Rule error: {0}")]
+    Rule(#[from] rule::Error),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermissionToken {
+    pub resource: String,
+    pub permission: Permission,
+    pub policy_version: u64,
+    pub subject: u64,
+    pub expires_at: u64,
+}
+
+impl PermissionToken {
+    #[must_use]
+    pub fn allows(&self, operation: &Operation) -> bool {
+        operation.allowed_for(self.permission)
+    }
+}
+
+pub struct TokenSigner {
+    key: Vec<u8>,
+    ttl: Duration,
+}
+
+impl TokenSigner {
+    #[must_use]
+    pub fn new(key: &[u8], ttl: Duration) -> Self {
+        TokenSigner {
+            key: key.to_vec(),
+            ttl,
+        }
+    }
+
+    pub fn issue(&self, hierarchy: &Hierarchy, on: &str, with: &Context) -> Result<String, Error> {
+        self.issue_at(hierarchy, on, with, SystemTime::now())
+    }
+
+    pub fn issue_at(
+        &self,
+        hierarchy: &Hierarchy,
+        on: &str,
+        with: &Context,
+        now: SystemTime,
+    ) -> Result<String, Error> {
+        let token = PermissionToken {
+            resource: on.to_string(),
+            permission: hierarchy.permission(&hierarchy.path(on)?, with)?,
+            policy_version: hierarchy.version(),
+            subject: subject(with),
+            expires_at: seconds(now + self.ttl),
+        };
+        let payload = format!(
+            "{}|{:x}|{:x}|{:x}|{}",
+            token.permission.bits(),
+            token.policy_version,
+            token.subject,
+            token.expires_at,
+            token.resource
+        );
+        Ok(format!(
+            "{}.{}",
+            hex(payload.as_bytes()),
+            hex(&self.mac(payload.as_bytes()).finalize().into_bytes())
+        ))
+    }
+
+    /// Checks the token against the policy, the resource and the context of
+    /// the subject presenting it.
+    pub fn verify(
+        &self,
+        hierarchy: &Hierarchy,
+        on: &str,
+        with: &Context,
+        token: &str,
+    ) -> Result<PermissionToken, Error> {
+        self.verify_at(hierarchy, on, with, token, SystemTime::now())
+    }
+
+    pub fn verify_at(
+        &self,
+        hierarchy: &Hierarchy,
+        on: &str,
+        with: &Context,
+        token: &str,
+        now: SystemTime,
+    ) -> Result<PermissionToken, Error> {
+        let (payload, signature) = token.split_once('.').ok_or(Error::Malformed)?;
+        let (payload, signature) = (unhex(payload)?, unhex(signature)?);
+        self.mac(&payload)
+            .verify_slice(&signature)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        let payload = String::from_utf8(payload).map_err(|_| Error::Malformed)?;
+        let mut fields = payload.splitn(5, '|');
+        let (
+            Some(permission),
+            Some(policy_version),
+            Some(subject_digest),
+            Some(expires_at),
+            Some(resource),
+        ) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        )
+        else {
+            return Err(Error::Malformed);
+        };
+        let token = PermissionToken {
+            resource: resource.to_string(),
+            permission: Permission::from_bits(permission.parse().map_err(|_| Error::Malformed)?),
+            policy_version: u64::from_str_radix(policy_version, 16)
+                .map_err(|_| Error::Malformed)?,
+            subject: u64::from_str_radix(subject_digest, 16).map_err(|_| Error::Malformed)?,
+            expires_at: u64::from_str_radix(expires_at, 16).map_err(|_| Error::Malformed)?,
+        };
+
+        if token.expires_at <= seconds(now) {
+            return Err(Error::Expired);
+        }
+        if token.policy_version != hierarchy.version() {
+            return Err(Error::PolicyChanged);
+        }
+        if token.resource != on {
+            return Err(Error::ResourceMismatch(token.resource));
+        }
+        if token.subject != subject(with) {
+            return Err(Error::SubjectMismatch);
+        }
+        Ok(token)
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// Digest of the context the token is issued for, over its key-sorted JSON form.
+fn subject(with: &Context) -> u64 {
+    let canonical = serde_json::to_value(with)
+        .and_then(|value| serde_json::to_vec(&value))
+        .unwrap_or_else(|_| with.to_string().into_bytes());
+    let digest = Sha256::digest(canonical);
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, Error> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(Error::Malformed);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| Error::Malformed))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::str::FromStr;
+
+    fn hierarchy(rule: &str) -> Hierarchy {
+        toml::from_str::<Config>(&format!(
+            r#"
+            [resources."/reports"]
+            access_rule = "{rule}"
+        "#
+        ))
+        .unwrap()
+        .try_into()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_permission_token_ok() {
+        let rh = hierarchy("(if (eq $role admin) (list list read delete) (list list))");
+        let signer = TokenSigner::new(b"secret", Duration::from_secs(60));
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let token = signer
+            .issue_at(
+                &rh,
+                "/reports",
+                &Context::from_str("role:admin").unwrap(),
+                now,
+            )
+            .unwrap();
+        let verified = signer
+            .verify_at(
+                &rh,
+                "/reports",
+                &Context::from_str("role:admin").unwrap(),
+                &token,
+                now + Duration::from_secs(59),
+            )
+            .unwrap();
+        assert_eq!(verified.expires_at, 1_060);
+        assert!(verified.allows(&Operation::List));
+        assert!(verified.allows(&Operation::Delete));
+        assert!(!verified.allows(&Operation::Update));
+    }
+
+    #[test]
+    fn test_permission_token_err() {
+        let rh = hierarchy("(list list)");
+        let signer = TokenSigner::new(b"secret", Duration::from_secs(60));
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let context = Context::from_str("user_id:1").unwrap();
+        let token = signer.issue_at(&rh, "/reports", &context, now).unwrap();
+
+        assert_eq!(
+            signer.verify_at(
+                &rh,
+                "/reports",
+                &context,
+                &token,
+                now + Duration::from_secs(60)
+            ),
+            Err(Error::Expired)
+        );
+        assert_eq!(
+            signer.verify_at(
+                &hierarchy("(list list read)"),
+                "/reports",
+                &context,
+                &token,
+                now
+            ),
+            Err(Error::PolicyChanged)
+        );
+        assert_eq!(
+            signer.verify_at(&rh, "/invoices", &context, &token, now),
+            Err(Error::ResourceMismatch(String::from("/reports")))
+        );
+        assert_eq!(
+            TokenSigner::new(b"other", Duration::from_secs(60))
+                .verify_at(&rh, "/reports", &context, &token, now),
+            Err(Error::InvalidSignature)
+        );
+        let (payload, signature) = token.split_once('.').unwrap();
+        assert_eq!(
+            signer.verify_at(
+                &rh,
+                "/reports",
+                &context,
+                &format!("{}.{signature}", payload.replacen('3', "4", 1)),
+                now
+            ),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            signer.verify_at(&rh, "/reports", &context, "garbage", now),
+            Err(Error::Malformed)
+        );
+        assert_eq!(
+            signer.verify_at(
+                &rh,
+                "/reports",
+                &Context::from_str("user_id:2").unwrap(),
+                &token,
+                now
+            ),
+            Err(Error::SubjectMismatch)
+        );
+    }
+}